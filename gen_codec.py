@@ -98,6 +98,7 @@ PartKind = Enum("PartKind", [
     "PowerHub",
     "HubThruster",
     "LandingWheel",
+    "Cannon",
 ])
 enums.append(PartKind)
 TypePartKind = EnumType("PartKind")
@@ -143,6 +144,10 @@ ToServerMsg.messages.append(SendChatMessage)
 RequestUpdate = Message("RequestUpdate")
 ToServerMsg.messages.append(RequestUpdate)
 
+SetFiring = Message("SetFiring")
+SetFiring.fields.append(Field("firing", TypeBoolean))
+ToServerMsg.messages.append(SetFiring)
+
 
 ToClientMsg = MessageCategory("ToClientMsg")
 categories.append(ToClientMsg)
@@ -237,6 +242,47 @@ ChatMessage.fields.append(Field("msg", TypeString))
 ChatMessage.fields.append(Field("color", TypeString))
 ToClientMsg.messages.append(ChatMessage)
 
+QueuePosition = Message("QueuePosition")
+QueuePosition.fields.append(Field("position", TypeUShort))
+ToClientMsg.messages.append(QueuePosition)
+
+HandshakeRejected = Message("HandshakeRejected")
+HandshakeRejected.fields.append(Field("reason", TypeString))
+ToClientMsg.messages.append(HandshakeRejected)
+
+CelestialObjectOrbit = Message("CelestialObjectOrbit")
+CelestialObjectOrbit.fields.append(Field("id", TypeUShort))
+CelestialObjectOrbit.fields.append(Field("parent", TypeUShort))
+CelestialObjectOrbit.fields.append(Field("semi_major_axis", TypeFloat))
+CelestialObjectOrbit.fields.append(Field("eccentricity", TypeFloat))
+CelestialObjectOrbit.fields.append(Field("argument_of_periapsis", TypeFloat))
+CelestialObjectOrbit.fields.append(Field("mean_anomaly", TypeFloat))
+CelestialObjectOrbit.fields.append(Field("mean_motion", TypeFloat))
+ToClientMsg.messages.append(CelestialObjectOrbit)
+
+UpdatePartHealth = Message("UpdatePartHealth")
+UpdatePartHealth.fields.append(Field("id", TypeUShort))
+UpdatePartHealth.fields.append(Field("health", TypeUShort))
+UpdatePartHealth.fields.append(Field("max_health", TypeUShort))
+ToClientMsg.messages.append(UpdatePartHealth)
+
+AddProjectile = Message("AddProjectile")
+AddProjectile.fields.append(Field("id", TypeUShort))
+AddProjectile.fields.append(Field("x", TypeFloat))
+AddProjectile.fields.append(Field("y", TypeFloat))
+AddProjectile.fields.append(Field("vel_x", TypeFloat))
+AddProjectile.fields.append(Field("vel_y", TypeFloat))
+ToClientMsg.messages.append(AddProjectile)
+
+RemoveProjectile = Message("RemoveProjectile")
+RemoveProjectile.fields.append(Field("id", TypeUShort))
+ToClientMsg.messages.append(RemoveProjectile)
+
+KillFeed = Message("KillFeed")
+KillFeed.fields.append(Field("victim", TypeString))
+KillFeed.fields.append(Field("killer", OptionType(TypeString)))
+ToClientMsg.messages.append(KillFeed)
+
 rust_header = open("codec_header.rs", "r")
 rust_out = open("codec.rs", "w")
 rust_out.write(rust_header.read())
@@ -269,7 +315,11 @@ for category in categories:
         for field in message.fields:
             rust_out.write("\t\t\t\t%s\n" % field.kind.rust_deserialize(field.name))
         rust_out.write("\t\t\t\tOk(%s::%s { %s})\n\t\t\t},\n" % (category.name, message.name, ", ".join(map(lambda field: field.name, message.fields))))
-    rust_out.write("\t\t\t_ => Err(())\n\t\t}\n\t}\n}\n\n")
+    rust_out.write("\t\t\t_ => Err(())\n\t\t}\n\t}\n")
+    rust_out.write("\tpub fn name(&self) -> &'static str {\n\t\tmatch self {\n")
+    for message in category.messages:
+        rust_out.write("\t\t\tSelf::%s { .. } => \"%s\",\n" % (message.name, message.name))
+    rust_out.write("\t\t}\n\t}\n}\n\n")
 rust_out.close()
 
 typescript_header = open("codec_header.ts", "r")
@@ -1,10 +1,8 @@
 use crate::world::parts::{RecursivePartDescription, PartKind};
 use serde::ser::{Serialize, Serializer};
 use serde::de::{Deserialize, Deserializer, Error};
-use crate::ApiDat;
-use std::sync::Arc;
+use crate::roles::Role;
 use futures::FutureExt;
-use async_std::task::JoinHandle;
 
 
 impl Serialize for PartKind {
@@ -22,44 +20,63 @@ impl<'de> Deserialize<'de> for PartKind {
     }
 }
 
-pub fn spawn_beamout_request(beamout_token: String, mut beamout_layout: RecursivePartDescription, api: Arc<ApiDat>) -> JoinHandle<()> {
-    fn recurse_can_beamout(part: &mut RecursivePartDescription) {
-        for attachment in &mut part.attachments {
-            if let Some(part) = attachment {
-                if !part.kind.can_beamout() { *attachment = None }
-                else { recurse_can_beamout(part) }
-            }
+/// Drops the parts that can't be taken home (cargo and the like)
+pub fn prune_for_beamout(part: &mut RecursivePartDescription) {
+    for attachment in &mut part.attachments {
+        if let Some(part) = attachment {
+            if !part.kind.can_beamout() { *attachment = None }
+            else { prune_for_beamout(part) }
         }
     }
-    recurse_can_beamout(&mut beamout_layout);
+}
+
+/// Why a beamout didn't go through, and whether trying the same request again could help
+#[derive(Debug)]
+pub struct BeamoutError { pub retryable: bool, pub reason: String }
 
-    let uri = api.beamout.replacen("^^^^", &beamout_token, 1);
-    let password = api.password.clone();
-    async_std::task::spawn(async move {
-        let beamout_layout = beamout_layout;
-        match surf::post(uri).header("password", password).body(serde_json::to_string(&beamout_layout).unwrap()).await {
-            Ok(res) if !res.status().is_success() => { eprintln!("Beamout post for {} does not indicate success {}", beamout_token, res.status()); },
-            Err(err) => { eprintln!("Beamout post failed for {}\n{}", beamout_token, err); },
-            _ => {}
-        };
-    })
+/// What a beamout is, alongside the layout itself
+#[derive(Copy, Clone, Debug)]
+pub struct BeamoutMeta {
+    /// Taken by the server while the player was still playing, rather than by them beaming out
+    pub autosave: bool,
+    /// Unix time in seconds the layout was taken, so a late delivery can't overwrite something newer
+    pub saved_at: u64,
+}
+
+/// A layout and when it was saved
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Checkpoint {
+    pub layout: RecursivePartDescription,
+    pub saved_at: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BeaminResponse {
+    #[serde(default)]
     pub is_admin: bool,
+    /// Kept as the name the API sent, so a role this server doesn't know yet doesn't fail the whole beamin
+    #[serde(default)]
+    pub role: Option<String>,
     pub beamout_token: String,
-    pub layout: Option<RecursivePartDescription>
+    pub layout: Option<RecursivePartDescription>,
+    /// When `layout` was beamed out, in Unix seconds
+    #[serde(default)]
+    pub saved_at: Option<u64>,
+    /// The newest autosave, if the store keeps them
+    #[serde(default)]
+    pub autosave: Option<Checkpoint>,
 }
-
-pub async fn beamin_request(session: String, api: Arc<ApiDat>) -> Result<BeaminResponse, Box<dyn std::error::Error>> {
-    let api = api.as_ref();
-    let session = session;
-    let uri = api.beamin.replacen("^^^^", &session, 1);
-    let password = api.password.clone();
-    let mut response = surf::get(uri).header("password", password).await?;
-    if response.status().is_success() {
-        let body_json = response.body_json().await?;
-        serde_json::from_value::<BeaminResponse>(body_json).map_err(|err| Box::new(err) as Box<dyn std::error::Error>)
-    } else { eprintln!("Beamin bad for {}\n{}", session, response.status()); Err(Box::new(serde_json::Error::custom("Invalid response"))) }
+impl BeaminResponse {
+    /// The role the API granted, falling back to the older is_admin flag if it granted none or one this server doesn't know
+    pub fn role(&self) -> Role {
+        self.role.as_deref().and_then(Role::from_name).unwrap_or(if self.is_admin { Role::Admin } else { Role::Player })
+    }
+    /// Whether the autosave is more recent than the last deliberate beamout. Without a time on the beamout, the autosave is assumed to be newer.
+    pub fn autosave_is_newest(&self) -> bool {
+        match (&self.autosave, &self.layout, self.saved_at) {
+            (None, _, _) => false,
+            (Some(_), None, _) | (Some(_), Some(_), None) => true,
+            (Some(autosave), Some(_), Some(saved_at)) => autosave.saved_at > saved_at,
+        }
+    }
 }
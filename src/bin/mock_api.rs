@@ -0,0 +1,47 @@
+//! Runs the mock beamin/beamout API on its own, e.g. for a LAN server or poking at the game server by hand:
+//! `cargo run --bin mock_api -- --port 8082 --secret hunter2`, then start the server with `API=http://127.0.0.1:8082 API_PASSWORD=hunter2`.
+
+#[allow(dead_code)]
+#[path = "../mock_api.rs"]
+mod mock_api;
+#[allow(dead_code)]
+#[path = "../signing.rs"]
+mod signing;
+
+use mock_api::{MockApi, MockUser};
+
+const USAGE: &str = "Usage: mock_api [--port <port>] [--secret <secret>] [--latency-ms <ms>] [--admin <session>]... [--down]";
+
+#[async_std::main]
+async fn main() {
+    let mut port = 8082u16;
+    let mut secret = String::new();
+    let mut latency = 0u64;
+    let mut admins = Vec::new();
+    let mut down = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| { eprintln!("{} needs a value\n{}", arg, USAGE); std::process::exit(2) });
+        match arg.as_str() {
+            "--port" => port = value().parse().expect("--port should be a number"),
+            "--secret" => secret = value(),
+            "--latency-ms" => latency = value().parse().expect("--latency-ms should be a number"),
+            "--admin" => admins.push(value()),
+            "--down" => down = true,
+            _ => { eprintln!("Unknown argument {}\n{}", arg, USAGE); std::process::exit(2); }
+        }
+    }
+
+    let api = MockApi::bind(&format!("127.0.0.1:{}", port), &secret).await.unwrap_or_else(|err| panic!("Failed to bind to port {}: {}", port, err));
+    api.set_auto_register(true);
+    api.set_log_requests(true);
+    api.set_latency(std::time::Duration::from_millis(latency));
+    api.set_down(down);
+    for session in admins {
+        let mut user = MockUser::new(&format!("token-{}", session));
+        user.is_admin = true;
+        api.add_user(&session, user);
+    }
+    eprintln!("Mock API listening on {}", api.prefix());
+    futures::future::pending::<()>().await;
+}
@@ -75,11 +75,11 @@ async fn type_bool_deserialize<S: Stream<Item=u8>+Unpin>(stream: &mut S) -> Resu
 
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)] pub enum PartKind {
-	Core, Cargo, LandingThruster, Hub, SolarPanel, EcoThruster, Thruster, SuperThruster, PowerHub, HubThruster, LandingWheel
+	Core, Cargo, LandingThruster, Hub, SolarPanel, EcoThruster, Thruster, SuperThruster, PowerHub, HubThruster, LandingWheel, Cannon
 }
 impl PartKind {
 	pub fn val_of(&self) -> u8 { match self {
-			Self::Core => 0, Self::Cargo => 1, Self::LandingThruster => 2, Self::Hub => 3, Self::SolarPanel => 4, Self::EcoThruster => 5, Self::Thruster => 6, Self::SuperThruster => 7, Self::PowerHub => 8, Self::HubThruster => 9, Self::LandingWheel => 10
+			Self::Core => 0, Self::Cargo => 1, Self::LandingThruster => 2, Self::Hub => 3, Self::SolarPanel => 4, Self::EcoThruster => 5, Self::Thruster => 6, Self::SuperThruster => 7, Self::PowerHub => 8, Self::HubThruster => 9, Self::LandingWheel => 10, Self::Cannon => 11
 		} }
 	pub fn serialize(&self, buf: &mut Vec<u8>) {
 		buf.push(self.val_of());
@@ -87,7 +87,7 @@ impl PartKind {
 	pub async fn deserialize<S: Stream<Item=u8>+Unpin>(stream: &mut S) -> Result<Self, ()> {
 		let me = stream.next().await.ok_or(())?;
 		match me {
-			0 => Ok(Self::Core), 1 => Ok(Self::Cargo), 2 => Ok(Self::LandingThruster), 3 => Ok(Self::Hub), 4 => Ok(Self::SolarPanel), 5 => Ok(Self::EcoThruster), 6 => Ok(Self::Thruster), 7 => Ok(Self::SuperThruster), 8 => Ok(Self::PowerHub), 9 => Ok(Self::HubThruster), 10 => Ok(Self::LandingWheel),
+			0 => Ok(Self::Core), 1 => Ok(Self::Cargo), 2 => Ok(Self::LandingThruster), 3 => Ok(Self::Hub), 4 => Ok(Self::SolarPanel), 5 => Ok(Self::EcoThruster), 6 => Ok(Self::Thruster), 7 => Ok(Self::SuperThruster), 8 => Ok(Self::PowerHub), 9 => Ok(Self::HubThruster), 10 => Ok(Self::LandingWheel), 11 => Ok(Self::Cannon),
 			_ => Err(())
 		}
 	}
@@ -102,6 +102,7 @@ pub enum ToServerMsg {
 	BeamOut,
 	SendChatMessage { msg: String, },
 	RequestUpdate,
+	SetFiring { firing: bool, },
 }
 impl ToServerMsg {
 	pub fn serialize(&self, out: &mut Vec<u8>) {
@@ -143,6 +144,10 @@ impl ToServerMsg {
 			Self::RequestUpdate { } => {
 				out.push(7);
 			},
+			Self::SetFiring { firing} => {
+				out.push(8);
+				type_bool_serialize(out, firing);
+			},
 		};
 	}
 	pub async fn deserialize<S: Stream<Item=u8>+Unpin>(stream: &mut S) -> Result<Self, ()> {
@@ -192,9 +197,27 @@ impl ToServerMsg {
 				
 				Ok(ToServerMsg::RequestUpdate { })
 			},
+			8 => {
+				let firing;
+				firing = type_bool_deserialize(stream).await?;
+				Ok(ToServerMsg::SetFiring { firing})
+			},
 			_ => Err(())
 		}
 	}
+	pub fn name(&self) -> &'static str {
+		match self {
+			Self::Handshake { .. } => "Handshake",
+			Self::SetThrusters { .. } => "SetThrusters",
+			Self::CommitGrab { .. } => "CommitGrab",
+			Self::MoveGrab { .. } => "MoveGrab",
+			Self::ReleaseGrab { .. } => "ReleaseGrab",
+			Self::BeamOut { .. } => "BeamOut",
+			Self::SendChatMessage { .. } => "SendChatMessage",
+			Self::RequestUpdate { .. } => "RequestUpdate",
+			Self::SetFiring { .. } => "SetFiring",
+		}
+	}
 }
 
 pub enum ToClientMsg {
@@ -214,6 +237,13 @@ pub enum ToClientMsg {
 	BeamOutAnimation { player_id: u16, },
 	IncinerationAnimation { player_id: u16, },
 	ChatMessage { username: String, msg: String, color: String, },
+	QueuePosition { position: u16, },
+	HandshakeRejected { reason: String, },
+	CelestialObjectOrbit { id: u16, parent: u16, semi_major_axis: f32, eccentricity: f32, argument_of_periapsis: f32, mean_anomaly: f32, mean_motion: f32, },
+	UpdatePartHealth { id: u16, health: u16, max_health: u16, },
+	AddProjectile { id: u16, x: f32, y: f32, vel_x: f32, vel_y: f32, },
+	RemoveProjectile { id: u16, },
+	KillFeed { victim: String, killer: Option<String>, },
 }
 impl ToClientMsg {
 	pub fn serialize(&self, out: &mut Vec<u8>) {
@@ -307,6 +337,47 @@ impl ToClientMsg {
 				type_string_serialize(out, msg);
 				type_string_serialize(out, color);
 			},
+			Self::QueuePosition { position} => {
+				out.push(16);
+				type_u16_serialize(out, position);
+			},
+			Self::HandshakeRejected { reason} => {
+				out.push(17);
+				type_string_serialize(out, reason);
+			},
+			Self::CelestialObjectOrbit { id, parent, semi_major_axis, eccentricity, argument_of_periapsis, mean_anomaly, mean_motion} => {
+				out.push(18);
+				type_u16_serialize(out, id);
+				type_u16_serialize(out, parent);
+				type_float_serialize(out, semi_major_axis);
+				type_float_serialize(out, eccentricity);
+				type_float_serialize(out, argument_of_periapsis);
+				type_float_serialize(out, mean_anomaly);
+				type_float_serialize(out, mean_motion);
+			},
+			Self::UpdatePartHealth { id, health, max_health} => {
+				out.push(19);
+				type_u16_serialize(out, id);
+				type_u16_serialize(out, health);
+				type_u16_serialize(out, max_health);
+			},
+			Self::AddProjectile { id, x, y, vel_x, vel_y} => {
+				out.push(20);
+				type_u16_serialize(out, id);
+				type_float_serialize(out, x);
+				type_float_serialize(out, y);
+				type_float_serialize(out, vel_x);
+				type_float_serialize(out, vel_y);
+			},
+			Self::RemoveProjectile { id} => {
+				out.push(21);
+				type_u16_serialize(out, id);
+			},
+			Self::KillFeed { victim, killer} => {
+				out.push(22);
+				type_string_serialize(out, victim);
+				if let Some(tmp) = killer {out.push(1); type_string_serialize(out, tmp);} else {out.push(0);}
+			},
 		};
 	}
 	pub async fn deserialize<S: Stream<Item=u8>+Unpin>(stream: &mut S) -> Result<Self, ()> {
@@ -416,8 +487,83 @@ impl ToClientMsg {
 				color = type_string_deserialize(stream).await?;
 				Ok(ToClientMsg::ChatMessage { username, msg, color})
 			},
+			16 => {
+				let position;
+				position = type_u16_deserialize(stream).await?;
+				Ok(ToClientMsg::QueuePosition { position})
+			},
+			17 => {
+				let reason;
+				reason = type_string_deserialize(stream).await?;
+				Ok(ToClientMsg::HandshakeRejected { reason})
+			},
+			18 => {
+				let id; let parent; let semi_major_axis; let eccentricity; let argument_of_periapsis; let mean_anomaly; let mean_motion;
+				id = type_u16_deserialize(stream).await?;
+				parent = type_u16_deserialize(stream).await?;
+				semi_major_axis = type_float_deserialize(stream).await?;
+				eccentricity = type_float_deserialize(stream).await?;
+				argument_of_periapsis = type_float_deserialize(stream).await?;
+				mean_anomaly = type_float_deserialize(stream).await?;
+				mean_motion = type_float_deserialize(stream).await?;
+				Ok(ToClientMsg::CelestialObjectOrbit { id, parent, semi_major_axis, eccentricity, argument_of_periapsis, mean_anomaly, mean_motion})
+			},
+			19 => {
+				let id; let health; let max_health;
+				id = type_u16_deserialize(stream).await?;
+				health = type_u16_deserialize(stream).await?;
+				max_health = type_u16_deserialize(stream).await?;
+				Ok(ToClientMsg::UpdatePartHealth { id, health, max_health})
+			},
+			20 => {
+				let id; let x; let y; let vel_x; let vel_y;
+				id = type_u16_deserialize(stream).await?;
+				x = type_float_deserialize(stream).await?;
+				y = type_float_deserialize(stream).await?;
+				vel_x = type_float_deserialize(stream).await?;
+				vel_y = type_float_deserialize(stream).await?;
+				Ok(ToClientMsg::AddProjectile { id, x, y, vel_x, vel_y})
+			},
+			21 => {
+				let id;
+				id = type_u16_deserialize(stream).await?;
+				Ok(ToClientMsg::RemoveProjectile { id})
+			},
+			22 => {
+				let victim; let killer;
+				victim = type_string_deserialize(stream).await?;
+				killer = {if stream.next().await.ok_or(())? > 0 { let tmp; tmp = type_string_deserialize(stream).await?; Some(tmp)} else { None }};
+				Ok(ToClientMsg::KillFeed { victim, killer})
+			},
 			_ => Err(())
 		}
 	}
+	pub fn name(&self) -> &'static str {
+		match self {
+			Self::MessagePack { .. } => "MessagePack",
+			Self::HandshakeAccepted { .. } => "HandshakeAccepted",
+			Self::AddCelestialObject { .. } => "AddCelestialObject",
+			Self::AddPart { .. } => "AddPart",
+			Self::MovePart { .. } => "MovePart",
+			Self::UpdatePartMeta { .. } => "UpdatePartMeta",
+			Self::RemovePart { .. } => "RemovePart",
+			Self::AddPlayer { .. } => "AddPlayer",
+			Self::UpdatePlayerMeta { .. } => "UpdatePlayerMeta",
+			Self::UpdatePlayerVelocity { .. } => "UpdatePlayerVelocity",
+			Self::RemovePlayer { .. } => "RemovePlayer",
+			Self::PostSimulationTick { .. } => "PostSimulationTick",
+			Self::UpdateMyMeta { .. } => "UpdateMyMeta",
+			Self::BeamOutAnimation { .. } => "BeamOutAnimation",
+			Self::IncinerationAnimation { .. } => "IncinerationAnimation",
+			Self::ChatMessage { .. } => "ChatMessage",
+			Self::QueuePosition { .. } => "QueuePosition",
+			Self::HandshakeRejected { .. } => "HandshakeRejected",
+			Self::CelestialObjectOrbit { .. } => "CelestialObjectOrbit",
+			Self::UpdatePartHealth { .. } => "UpdatePartHealth",
+			Self::AddProjectile { .. } => "AddProjectile",
+			Self::RemoveProjectile { .. } => "RemoveProjectile",
+			Self::KillFeed { .. } => "KillFeed",
+		}
+	}
 }
 
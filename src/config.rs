@@ -0,0 +1,270 @@
+use std::sync::OnceLock;
+
+/// Everything tunable about a server. Loaded from a JSON file (`--config <path>` or the `CONFIG` env var),
+/// after which any field can be overridden by an env var of the same name in upper case (`PORT`, `MAX_EARTH_CARGOS`, ...)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub port: u16,
+    /// Prefix of the beamin/beamout API, e.g. `https://example.com/api`
+    pub api: Option<String>,
+    /// Secret shared with the API, used to sign requests (see `signing`) rather than sent as is
+    pub api_password: String,
+    /// Directory to keep ships in when there's no API, for self-hosted servers. Can't be combined with `api`.
+    pub store_dir: Option<String>,
+    /// JSON role table, see `roles::RoleTable`
+    pub roles: Option<String>,
+    /// JSON list of celestial objects, see `world::planets::CelestialObjectDef`. The built-in solar system otherwise
+    pub planets: Option<String>,
+    /// Generate a fresh solar system from the seed instead, see `world::generator::GeneratorSettings`. Can't be combined with `planets`.
+    pub generate_planets: Option<crate::world::generator::GeneratorSettings>,
+    /// Drives everything random about the world (planet placement, spawn points, ...). Picked at random and logged if unset
+    pub seed: Option<u64>,
+    /// Unix socket path for the operator console. Commands sent through it run as the owner, so the socket
+    /// is made readable and writable only by the user running the server; put it in a directory only that user can enter
+    /// to close the gap between creating it and locking it down
+    pub console_socket: Option<String>,
+    /// Address for the Prometheus metrics listener, e.g. `127.0.0.1:9100`
+    pub metrics_addr: Option<String>,
+    /// error, warn, info, debug or trace
+    pub log_level: String,
+    /// text or json
+    pub log_format: String,
+
+    /// Players past this many wait in a queue for a slot; 0 means no limit
+    pub max_players: u16,
+    pub max_name_length: u8,
+    /// Beamed-in ships with more parts than this are trimmed
+    pub max_ship_parts: u16,
+    /// Cut the broken parts off a beamed-in ship rather than replacing the whole thing with a plain core
+    pub repair_layouts: bool,
+    /// Names nobody may join as, compared case-insensitively. As an env var, a comma separated list.
+    pub reserved_names: Vec<String>,
+    pub ticks_per_second: u8,
+    /// How long a part floats around unattached before it's deleted
+    pub part_decay_seconds: u16,
+    pub max_earth_cargos: u8,
+    pub earth_cargo_spawn_seconds: u16,
+    /// How long an ungrabbed earth cargo drifts before it's put back next to earth
+    pub earth_cargo_reset_seconds: u16,
+    /// How often a landed player's cargo gets upgraded by the planet
+    pub cargo_upgrade_seconds: u16,
+    /// How long a disconnected player stays in the world waiting to reconnect
+    pub suspend_seconds: u64,
+    /// Players only receive updates about ships within this many units in each direction
+    pub view_distance: f32,
+    /// Torque and force at which the joint between two attached parts breaks
+    pub joint_break_torque: f32,
+    pub joint_break_force: f32,
+    /// Parts shrug off hits with less impulse than this (roughly mass times closing speed, as the contact solver sees it); past it, every unit costs `impact_damage` hit points
+    pub impact_damage_threshold: f32,
+    pub impact_damage: f32,
+    /// Hit points a second given back to every part of a ship resting on a planet that repairs
+    pub repair_per_second: u16,
+    /// Lets weapons hurt other players' ships. Planets with a `safe_zone` stay safe regardless; `/pvp` flips it while running
+    pub pvp: bool,
+    pub core_max_power: u32,
+    pub gravitational_constant: f32,
+    /// Softens gravity near a planet's center, as a fraction of its radius
+    pub gravity_softening: f32,
+    /// Planets only pull within their sphere of influence (the sun pulls everywhere)
+    pub gravity_soi_cutoff: bool,
+    /// Multiplies how fast planets and moons go around their orbits. 0 holds them still
+    pub orbit_speed: f32,
+    /// Where beamouts are journaled until the API confirms them
+    pub outbox_dir: String,
+    /// Failed beamouts are retried with exponential backoff up to this interval
+    pub outbox_max_retry_seconds: u64,
+    /// How often every ship is checkpointed to the store, so a crash or disconnect loses at most this much progress; 0 turns autosave off
+    pub autosave_seconds: u16,
+    /// Countdown players get before a signal or `/shutdown` restarts the server
+    pub shutdown_countdown_seconds: u16,
+    pub shutdown_beamout_timeout_seconds: u64,
+    /// Where the whole world is saved on shutdown and loaded from at startup, see `snapshot::WorldSnapshot`. Empty, the default, turns snapshots off
+    pub snapshot_file: String,
+    /// How often the snapshot is also written while running, so a crash loses at most this much; 0 only writes it on shutdown
+    pub snapshot_seconds: u16,
+    /// Record every input the game gets into a new file in this directory each run, for replaying with `--replay`. See `recording`
+    pub record_dir: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            port: 8081,
+            api: None,
+            api_password: String::new(),
+            store_dir: None,
+            roles: None,
+            planets: None,
+            generate_planets: None,
+            seed: None,
+            console_socket: None,
+            metrics_addr: None,
+            log_level: String::from("info"),
+            log_format: String::from("text"),
+
+            max_players: 0,
+            max_name_length: 24,
+            max_ship_parts: 250,
+            repair_layouts: true,
+            reserved_names: ["Server", "Console", "Admin", "Moderator", "Owner"].iter().map(|name| name.to_string()).collect(),
+            ticks_per_second: 20,
+            part_decay_seconds: 90,
+            max_earth_cargos: 20,
+            earth_cargo_spawn_seconds: 4,
+            earth_cargo_reset_seconds: 60,
+            cargo_upgrade_seconds: 1,
+            suspend_seconds: 70,
+            view_distance: 200.0,
+            joint_break_torque: 200.0,
+            joint_break_force: 700.0,
+            impact_damage_threshold: 6.0,
+            impact_damage: 10.0,
+            repair_per_second: 20,
+            pvp: false,
+            core_max_power: 2000,
+            gravitational_constant: 1.0,
+            gravity_softening: 0.1,
+            gravity_soi_cutoff: false,
+            orbit_speed: 1.0,
+            outbox_dir: String::from("outbox"),
+            outbox_max_retry_seconds: 300,
+            autosave_seconds: 120,
+            shutdown_countdown_seconds: 10,
+            shutdown_beamout_timeout_seconds: 15,
+            snapshot_file: String::new(),
+            snapshot_seconds: 300,
+            record_dir: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Reads the file (if any), then applies env overrides and validates the result
+    pub fn load(path: Option<&str>) -> Result<ServerConfig, String> {
+        let mut value = match path {
+            Some(path) => {
+                let file = std::fs::read_to_string(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+                serde_json::from_str::<serde_json::Value>(&file).map_err(|err| format!("Failed to parse {}: {}", path, err))?
+            },
+            None => serde_json::Value::Object(Default::default()),
+        };
+        let object = value.as_object_mut().ok_or_else(|| String::from("The config file must be a JSON object"))?;
+        apply_overrides(object, std::env::vars())?;
+        let config: ServerConfig = serde_json::from_value(value).map_err(|err| format!("Invalid config: {}", err))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let mut problems = Vec::new();
+        if self.ticks_per_second == 0 || self.ticks_per_second > 120 { problems.push("ticks_per_second must be between 1 and 120"); }
+        if self.earth_cargo_spawn_seconds == 0 { problems.push("earth_cargo_spawn_seconds must be at least 1"); }
+        if self.earth_cargo_reset_seconds == 0 { problems.push("earth_cargo_reset_seconds must be at least 1"); }
+        if self.cargo_upgrade_seconds == 0 { problems.push("cargo_upgrade_seconds must be at least 1"); }
+        if !self.view_distance.is_finite() || self.view_distance <= 0.0 { problems.push("view_distance must be positive"); }
+        if self.joint_break_torque.is_nan() || self.joint_break_torque <= 0.0 || self.joint_break_force.is_nan() || self.joint_break_force <= 0.0 { problems.push("joint_break_torque and joint_break_force must be positive"); }
+        if !self.impact_damage_threshold.is_finite() || self.impact_damage_threshold < 0.0 || !self.impact_damage.is_finite() || self.impact_damage < 0.0 { problems.push("impact_damage_threshold and impact_damage can't be negative"); }
+        if !self.gravitational_constant.is_finite() { problems.push("gravitational_constant must be a finite number"); }
+        if !self.gravity_softening.is_finite() || self.gravity_softening < 0.0 { problems.push("gravity_softening can't be negative"); }
+        if !self.orbit_speed.is_finite() || self.orbit_speed < 0.0 { problems.push("orbit_speed can't be negative"); }
+        if crate::logging::Level::parse(&self.log_level).is_none() { problems.push("log_level must be one of error, warn, info, debug or trace"); }
+        if self.log_format != "text" && self.log_format != "json" { problems.push("log_format must be text or json"); }
+        if self.api.is_some() && self.store_dir.is_some() { problems.push("Set either api or store_dir, not both"); }
+        if self.planets.is_some() && self.generate_planets.is_some() { problems.push("Set either planets or generate_planets, not both"); }
+        if let Some(settings) = &self.generate_planets { problems.extend(settings.problems()); }
+        if self.max_ship_parts == 0 { problems.push("max_ship_parts must be at least 1"); }
+        if self.max_name_length == 0 { problems.push("max_name_length must be at least 1"); }
+        //Tick counters are u16
+        let longest = [self.part_decay_seconds, self.earth_cargo_spawn_seconds, self.earth_cargo_reset_seconds, self.cargo_upgrade_seconds, self.shutdown_countdown_seconds, self.autosave_seconds, self.snapshot_seconds].iter().copied().max().unwrap();
+        if longest as u32 * self.ticks_per_second as u32 > u16::MAX as u32 { problems.push("A duration in seconds is too long to count in ticks at this tick rate"); }
+        if problems.is_empty() { Ok(()) } else { Err(problems.join("\n")) }
+    }
+
+    /// Configured durations are checked to fit by `validate`; anything longer just stops at the longest count there is
+    pub fn ticks(&self, seconds: u16) -> u16 { self.checked_ticks(seconds).unwrap_or(u16::MAX) }
+    /// For durations that come from somewhere other than the config, like a command, which might not fit in a tick counter
+    pub fn checked_ticks(&self, seconds: u16) -> Option<u16> { seconds.checked_mul(self.ticks_per_second as u16) }
+    pub fn timestep(&self) -> f32 { 1.0 / self.ticks_per_second as f32 }
+}
+
+/// Sets every field named by one of `vars` (the field's name in upper case) to its value. Values are read as JSON if that suits
+/// the field, and as a plain string otherwise, so `PORT=9000` is a number but `CONSOLE_SOCKET=9000` is a path.
+/// Lists can also be given comma separated.
+fn apply_overrides(object: &mut serde_json::Map<String, serde_json::Value>, vars: impl IntoIterator<Item = (String, String)>) -> Result<(), String> {
+    let defaults = serde_json::to_value(ServerConfig::default()).unwrap();
+    let defaults = defaults.as_object().unwrap();
+    //Whether the field can take the value, tried on its own so nothing else in the config gets in the way
+    let suits = |key: &str, value: &serde_json::Value| {
+        let mut alone = defaults.clone();
+        alone.insert(key.to_owned(), value.clone());
+        serde_json::from_value::<ServerConfig>(serde_json::Value::Object(alone)).map(|_| ()).map_err(|err| err.to_string())
+    };
+    for (var, text) in vars {
+        let key = var.to_ascii_lowercase();
+        if var != key.to_ascii_uppercase() || !defaults.contains_key(&key) { continue };
+        let mut candidates: Vec<serde_json::Value> = serde_json::from_str(&text).into_iter().collect();
+        if defaults[&key].is_array() {
+            candidates.push(text.split(',').map(str::trim).filter(|item| !item.is_empty()).map(serde_json::Value::from).collect());
+        }
+        candidates.push(serde_json::Value::String(text.clone()));
+        let mut problem = None;
+        let value = candidates.into_iter().find(|candidate| match suits(&key, candidate) {
+            Ok(()) => true,
+            Err(err) => { problem.get_or_insert(err); false },
+        });
+        match value {
+            Some(value) => { object.insert(key, value); },
+            None => return Err(format!("{}={:?} doesn't work: {}", var, text, problem.unwrap())),
+        }
+    }
+    Ok(())
+}
+
+static CONFIG: OnceLock<ServerConfig> = OnceLock::new();
+
+/// Makes `config` the one `config()` returns. Only the first call has any effect.
+pub fn install(config: ServerConfig) { let _ = CONFIG.set(config); }
+
+/// The server's config, or the defaults if none was installed
+pub fn config() -> &'static ServerConfig { CONFIG.get_or_init(ServerConfig::default) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overridden(vars: &[(&str, &str)]) -> Result<ServerConfig, String> {
+        let mut object = serde_json::Map::new();
+        object.insert(String::from("port"), serde_json::Value::from(1234));
+        apply_overrides(&mut object, vars.iter().map(|(var, value)| (var.to_string(), value.to_string())))?;
+        serde_json::from_value(serde_json::Value::Object(object)).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn overrides_take_whatever_type_the_field_is() {
+        let config = overridden(&[
+            ("SEED", "42"), ("CONSOLE_SOCKET", "9000"), ("STORE_DIR", "2024"), ("MAX_PLAYERS", "8"), ("PVP", "true"), ("API", "http://localhost/api"),
+            ("GENERATE_PLANETS", r#"{ "planets": 5 }"#), ("RESERVED_NAMES", "a, b,,c"), ("IMPACT_DAMAGE", "2.5"),
+        ]).unwrap();
+        assert_eq!(config.seed, Some(42));
+        assert_eq!(config.console_socket.as_deref(), Some("9000"));
+        assert_eq!(config.store_dir.as_deref(), Some("2024"));
+        assert_eq!((config.max_players, config.pvp, config.impact_damage), (8, true, 2.5));
+        assert_eq!(config.api.as_deref(), Some("http://localhost/api"));
+        assert!(config.generate_planets.is_some());
+        assert_eq!(config.reserved_names, vec!["a", "b", "c"]);
+        assert_eq!(overridden(&[("RESERVED_NAMES", r#"["x,y"]"#)]).unwrap().reserved_names, vec!["x,y"]);
+        //What the file said stays unless overridden, and variables that aren't fields are ignored
+        assert_eq!(config.port, 1234);
+        assert_eq!(overridden(&[("PORT", "80"), ("HOME", "/root"), ("port", "81")]).unwrap().port, 80);
+    }
+
+    #[test]
+    fn overrides_that_dont_fit_are_refused() {
+        assert!(overridden(&[("SEED", "not a number")]).unwrap_err().contains("SEED"));
+        assert!(overridden(&[("PORT", "70000")]).unwrap_err().contains("PORT"));
+        assert!(overridden(&[("PVP", "maybe")]).is_err());
+        assert!(overridden(&[("GENERATE_PLANETS", "lots")]).is_err());
+    }
+}
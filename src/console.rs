@@ -0,0 +1,53 @@
+use std::os::unix::fs::PermissionsExt;
+use async_std::prelude::*;
+use async_std::io::BufReader;
+use async_std::os::unix::net::{UnixListener, UnixStream};
+use async_std::sync::{Sender, channel};
+use crate::session::{ToGameEvent, CommandIssuer};
+
+/// Sends one console line to the game and waits for whatever it has to say back
+async fn run_line(line: &str, to_game: &Sender<ToGameEvent>) -> Option<String> {
+    let line = line.trim();
+    if line.is_empty() { return None };
+    let command = if line.starts_with('/') { line.to_owned() } else { format!("/{}", line) };
+    let (reply_to, replies) = channel(1);
+    to_game.send(ToGameEvent::AdminCommand { issuer: CommandIssuer::Console(reply_to), command }).await;
+    replies.recv().await.ok()
+}
+
+pub async fn stdin_console(to_game: Sender<ToGameEvent>) {
+    let mut lines = BufReader::new(async_std::io::stdin()).lines();
+    while let Some(Ok(line)) = lines.next().await {
+        if let Some(reply) = run_line(&line, &to_game).await { println!("{}", reply); }
+    }
+}
+
+/// Everything sent down the socket runs as the owner, so only the user running the server may connect to it
+pub async fn socket_console(path: String, to_game: Sender<ToGameEvent>) {
+    let _ = std::fs::remove_file(&path);
+    let listener = match UnixListener::bind(&path).await {
+        Ok(listener) => listener,
+        Err(err) => { error!("console"; "Failed to bind console socket {}: {}", path, err); return; }
+    };
+    if let Err(err) = std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)) {
+        error!("console"; "Failed to lock down console socket {}, not listening on it: {}", path, err);
+        drop(listener);
+        let _ = std::fs::remove_file(&path);
+        return;
+    }
+    info!("console"; "Console listening on {}", path);
+    let mut incoming = listener.incoming();
+    while let Some(Ok(stream)) = incoming.next().await {
+        async_std::task::spawn(socket_console_client(stream, to_game.clone()));
+    }
+}
+
+async fn socket_console_client(stream: UnixStream, to_game: Sender<ToGameEvent>) {
+    let mut writer = stream.clone();
+    let mut lines = BufReader::new(stream).lines();
+    while let Some(Ok(line)) = lines.next().await {
+        if let Some(reply) = run_line(&line, &to_game).await {
+            if writer.write_all(format!("{}\n", reply).as_bytes()).await.is_err() { break };
+        }
+    }
+}
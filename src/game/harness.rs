@@ -0,0 +1,134 @@
+//! Plays a `Game` out tick by tick with no sockets, ticker or serializer behind it.
+//! Everything the game would have sent to clients is kept in `sent` to be looked over.
+
+use std::sync::Arc;
+use async_std::sync::{Receiver, channel};
+use async_std::task::block_on;
+use crate::world::planets::{load_definitions, CelestialObjectDef};
+use crate::world::parts::{Part, RecursivePartDescription};
+use crate::codec::{ToClientMsg, ToServerMsg};
+use crate::session::{self, ToGameEvent, ToSerializerEvent, CommandIssuer};
+use crate::roles::Role;
+use crate::recording::{Recorder, Replay, ReplayEvent};
+use super::{Game, Event};
+
+pub struct Harness {
+    pub game: Game,
+    /// What the game sent, and who to. `None` went to everyone.
+    pub sent: Vec<(Option<u16>, ToClientMsg)>,
+    next_id: u16,
+    /// Writes down everything the game is told, as `record_dir` would
+    pub recorder: Option<Recorder>,
+    //Kept so the game's own channels stay open
+    _to_game: Receiver<ToGameEvent>,
+    _to_serializer: Receiver<Vec<ToSerializerEvent>>,
+}
+
+impl Harness {
+    /// The built in solar system with a fixed seed, and nobody in it
+    pub fn new() -> Harness {
+        Harness::with_planets(&load_definitions(None).unwrap())
+    }
+
+    pub fn with_planets(planets: &[CelestialObjectDef]) -> Harness {
+        let (to_game, _to_game) = channel(64);
+        let (to_serializer, _to_serializer) = channel(64);
+        let session_shared = Arc::new(session::SessionShared { store: None, suspended_players: Default::default(), roles: Default::default(), slots: Default::default(), names: Default::default() });
+        let game = Game::new(planets, 1, None, session_shared, to_game, to_serializer);
+        Harness { game, sent: Vec::new(), next_id: 1, recorder: None, _to_game, _to_serializer }
+    }
+
+    fn handle(&mut self, event: Event) {
+        if let (Some(recorder), Event::InboundEvent(event)) = (self.recorder.as_mut(), &event) { recorder.record(event); }
+        let is_tick = matches!(event, Event::Simulate);
+        for event in block_on(self.game.handle(event)) {
+            match event {
+                ToSerializerEvent::Message(id, msg) => self.sent.push((Some(id), msg)),
+                ToSerializerEvent::Broadcast(msg) => self.sent.push((None, msg)),
+                _ => (),
+            }
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            if is_tick { recorder.ticked(self.game.simulation.checksum()); }
+            recorder.flush();
+        }
+    }
+
+    /// Feeds a whole recording through, checking checksums along the way like `--replay` does
+    pub fn replay(&mut self, replay: &mut Replay) {
+        while let Some(event) = replay.next_event() {
+            match event {
+                ReplayEvent::Inbound(event) => self.handle(Event::InboundEvent(event)),
+                ReplayEvent::Simulate => {
+                    self.handle(Event::Simulate);
+                    replay.ticked(self.game.simulation.checksum());
+                },
+            }
+        }
+    }
+
+    /// Joins a player flying `ship` next to the spawn planet, as if they'd just finished the handshake
+    pub fn spawn_player(&mut self, name: &str, ship: RecursivePartDescription) -> u16 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.handle(Event::InboundEvent(ToGameEvent::NewPlayer { id, name: name.to_owned(), session: None, parts: ship, beamout_token: None, role: Role::Player }));
+        id
+    }
+
+    /// What the session asks for once a player is in, or back
+    pub fn send_world(&mut self, id: u16) {
+        self.handle(Event::InboundEvent(ToGameEvent::SendEntireWorld { to_player: id, send_self: true }));
+    }
+
+    pub fn step(&mut self, ticks: u32) {
+        for _ in 0..ticks { self.handle(Event::Simulate); }
+    }
+
+    pub fn input(&mut self, id: u16, msg: ToServerMsg) {
+        self.handle(Event::InboundEvent(ToGameEvent::PlayerMessage { id, msg }));
+    }
+
+    /// Drops the player's connection without them quitting, as if their network went away
+    pub fn suspend(&mut self, id: u16) {
+        self.handle(Event::InboundEvent(ToGameEvent::PlayerSuspend { id, ref_handle: format!("session {}", id) }));
+    }
+
+    pub fn quit(&mut self, id: u16) {
+        self.handle(Event::InboundEvent(ToGameEvent::PlayerQuit { id }));
+    }
+
+    /// Runs a command from the console and returns its reply
+    pub fn command(&mut self, command: &str) -> String {
+        let (reply_to, replies) = channel(1);
+        self.handle(Event::InboundEvent(ToGameEvent::AdminCommand { issuer: CommandIssuer::Console(reply_to), command: command.to_owned() }));
+        block_on(replies.recv()).unwrap()
+    }
+
+    /// Empties `sent`
+    pub fn take_sent(&mut self) -> Vec<(Option<u16>, ToClientMsg)> {
+        std::mem::take(&mut self.sent)
+    }
+
+    pub fn core_position(&self, id: u16) -> (f32, f32) {
+        let position = self.game.simulation.world.get_rigid(self.game.players[&id].core).unwrap().position().translation;
+        (position.x, position.y)
+    }
+
+    pub fn core(&self, id: u16) -> &Part {
+        self.game.simulation.world.get_part(self.game.players[&id].core).unwrap()
+    }
+
+    /// The player's ship as it's put together right now
+    pub fn ship(&self, id: u16) -> RecursivePartDescription {
+        self.game.simulation.world.get_part(self.game.players[&id].core).unwrap().deflate(&self.game.simulation.world)
+    }
+
+    /// Every part in the player's ship, core included
+    pub fn part_count(&self, id: u16) -> usize {
+        fn count(part: &RecursivePartDescription) -> usize { 1 + part.attachments.iter().flatten().map(count).sum::<usize>() }
+        count(&self.ship(id))
+    }
+}
+impl Default for Harness {
+    fn default() -> Harness { Harness::new() }
+}
@@ -0,0 +1,1074 @@
+//! The game itself: everything that happens to the world on a tick or when a player does something.
+//! `main` feeds it events from the network, ticker and signals and hands what comes back to the serializer;
+//! tests drive it directly through `harness::Harness`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::sync::atomic::Ordering as AtomicOrdering;
+use async_std::sync::{Sender, channel};
+use rand::Rng;
+use nalgebra::Vector2;
+use nalgebra::geometry::Isometry2;
+use nphysics2d::object::Body;
+use crate::world::{self, nphysics_types::*};
+use crate::world::parts::{RecursivePartDescription, PartKind};
+use crate::codec::{self, ToClientMsg, ToServerMsg};
+use crate::session::{self, ToSerializerEvent, SessionShared};
+use crate::metrics::{METRICS, Metrics};
+use crate::config::config;
+use crate::{roles, outbox, snapshot, FreePart, PlayerMeta, rotate_vector, SHUTTING_DOWN, EMERGENCY_STOP};
+
+#[cfg(test)] pub mod harness;
+#[cfg(test)] mod tests;
+
+pub enum Event {
+    InboundEvent(session::ToGameEvent),
+    Simulate,
+    Signal(i32),
+}
+
+pub struct Game {
+    pub simulation: world::Simulation,
+    pub players: BTreeMap<u16, PlayerMeta>,
+    pub free_parts: BTreeMap<u16, FreePart>,
+    pub earth_cargos: u8,
+    pub ticks_til_earth_cargo_spawn: u16,
+    /// Whether weapons can hurt other players' ships outside of safe zones
+    pub pvp: bool,
+    ticks_til_power_regen: u8,
+    ticks_til_autosave: u16,
+    ticks_til_snapshot: u16,
+    shutdown_countdown: Option<u16>,
+    simulation_events: Vec<world::SimulationEvent>,
+    seed: u64,
+
+    outbox: Option<Arc<outbox::Outbox>>,
+    session_shared: Arc<SessionShared>,
+    /// For players that quit on their own later, like suspended ones
+    to_game: Sender<session::ToGameEvent>,
+    /// For messages that go out later, like deleting the writer of someone who just beamed out
+    to_serializer: Sender<Vec<ToSerializerEvent>>,
+}
+
+impl Game {
+    pub fn new(planets: &[world::planets::CelestialObjectDef], seed: u64, outbox: Option<Arc<outbox::Outbox>>, session_shared: Arc<SessionShared>, to_game: Sender<session::ToGameEvent>, to_serializer: Sender<Vec<ToSerializerEvent>>) -> Game {
+        Game {
+            simulation: world::Simulation::new(config().timestep(), planets, seed),
+            players: BTreeMap::new(),
+            free_parts: BTreeMap::new(),
+            earth_cargos: 0,
+            ticks_til_earth_cargo_spawn: config().ticks(config().earth_cargo_spawn_seconds),
+            pvp: config().pvp,
+            ticks_til_power_regen: 5,
+            ticks_til_autosave: config().ticks(config().autosave_seconds),
+            ticks_til_snapshot: config().ticks(config().snapshot_seconds),
+            shutdown_countdown: None,
+            simulation_events: Vec::new(),
+            seed,
+            outbox, session_shared, to_game, to_serializer,
+        }
+    }
+
+    /// Brings back a snapshot's world. Everyone comes back disconnected and has `suspend_seconds` to reconnect.
+    pub async fn restore(&mut self, snapshot: &snapshot::WorldSnapshot) {
+        let (players, free_parts) = snapshot.restore(&mut self.simulation);
+        self.players = players;
+        self.free_parts = free_parts;
+        self.earth_cargos = snapshot.earth_cargos;
+        self.ticks_til_earth_cargo_spawn = snapshot.ticks_til_earth_cargo_spawn.max(1);
+        for player in self.players.values_mut() {
+            self.session_shared.slots.join(player.id, true);
+            player.name = self.session_shared.names.claim(player.id, &player.name);
+            suspend_player(player.id, player.session.clone().unwrap(), player.role, &self.session_shared.suspended_players, &self.to_game).await;
+        }
+        info!("game"; "Restored the world with {} players and {} free parts", self.players.len(), self.free_parts.len());
+    }
+
+    pub fn snapshot(&self) -> snapshot::WorldSnapshot {
+        snapshot::WorldSnapshot::capture(&self.simulation, &self.players, &self.free_parts, self.earth_cargos, self.ticks_til_earth_cargo_spawn, self.seed)
+    }
+
+    /// Applies one event, returning what to send to clients because of it
+    pub async fn handle(&mut self, event: Event) -> Vec<ToSerializerEvent> {
+        use session::ToGameEvent::*;
+        use session::ToSerializerEvent as ToSerializer;
+        let Game {
+            simulation, players, free_parts, earth_cargos, ticks_til_earth_cargo_spawn, pvp, ticks_til_power_regen, ticks_til_autosave, ticks_til_snapshot,
+            shutdown_countdown, simulation_events, seed, outbox, session_shared, to_game, to_serializer,
+        } = self;
+        let mut outbound_events = Vec::new();
+        match event {
+            Event::Simulate => {
+                if let Some(ticks) = shutdown_countdown.as_mut() {
+                    if *ticks == 0 { graceful_shutdown(players, &simulation.world, outbox, to_serializer, snapshot::WorldSnapshot::capture(simulation, players, free_parts, *earth_cargos, *ticks_til_earth_cargo_spawn, *seed)).await; }
+                    *ticks -= 1;
+                    if *ticks % config().ticks_per_second as u16 == 0 && *ticks > 0 {
+                        let seconds = *ticks / config().ticks_per_second as u16;
+                        if matches!(seconds, 1..=5 | 10 | 30 | 60) { outbound_events.push(shutdown_notice(seconds)); }
+                    }
+                }
+                let tick_start = std::time::Instant::now();
+                let mut to_delete: Vec<u16> = Vec::new();
+                for (part_handle, meta) in free_parts.iter_mut() {
+                    match meta {
+                        FreePart::Decaying(_part, ticks) => {
+                            *ticks -= 1;
+                            if *ticks < 1 { to_delete.push(*part_handle); }
+                        },
+                        FreePart::EarthCargo(part, ticks) => {
+                            *ticks -= 1;
+                            if *ticks < 1 {
+                                let earth = simulation.planets.spawn_planet();
+                                let earth_position = simulation.world.get_rigid(earth.body).unwrap().position().translation;
+                                let earth_velocity = simulation.planets.velocity_of(earth);
+                                let spawn_radius = earth.radius * 1.25 + 1.0;
+                                let part = simulation.world.get_part_mut(*part).expect("Invalid Earth Cargo");
+                                let body = part.body_mut();
+                                let spawn_degrees: f32 = simulation.rng.gen::<f32>() * std::f32::consts::PI * 2.0;
+                                body.set_position(Isometry2::new(Vector2::new(spawn_degrees.sin() * spawn_radius + earth_position.x, spawn_degrees.cos() * spawn_radius + earth_position.y), 0.0)); // spawn_degrees));
+                                body.set_velocity(nphysics2d::math::Velocity::new(earth_velocity, 0.0));
+                                //use nphysics2d::object::Body;
+                                //body.apply_force(0, &nphysics2d::math::Force::zero(), nphysics2d::math::ForceType::Force, true);
+                                body.activate();
+                                *ticks = config().ticks(config().earth_cargo_reset_seconds);
+                            }
+                        },
+                        FreePart::Grabbed(_part) => (),
+                        FreePart::PlaceholderLol => panic!(),
+                    }
+                }
+                for to_delete in to_delete {
+                    let meta = free_parts.remove(&to_delete).unwrap();
+                    outbound_events.extend(simulation.delete_parts_recursive(*meta).into_iter().map(|msg| ToSerializer::Broadcast(msg)));
+                }
+                if *earth_cargos < config().max_earth_cargos {
+                    *ticks_til_earth_cargo_spawn -= 1;
+                    if *ticks_til_earth_cargo_spawn == 0 {
+                        *ticks_til_earth_cargo_spawn = config().ticks(config().earth_cargo_spawn_seconds);
+                        *earth_cargos += 1; 
+                        let earth = simulation.planets.spawn_planet();
+                        let earth_position = simulation.world.get_rigid(earth.body).unwrap().position().translation;
+                        let earth_velocity = simulation.planets.velocity_of(earth);
+                        let spawn_degrees: f32 = simulation.rng.gen::<f32>() * std::f32::consts::PI * 2.0;
+                        let spawn_radius = earth.radius * 1.25 + 1.0;
+                        let spawn_pos = Isometry2::new(Vector2::new(spawn_degrees.sin() * spawn_radius + earth_position.x, spawn_degrees.cos() * spawn_radius + earth_position.y), 0.0);
+                        let part_handle = RecursivePartDescription::from(PartKind::Cargo).inflate(&mut (&mut simulation.world).into(), &mut simulation.colliders, &mut simulation.joints, spawn_pos);
+                        //Keep up with Earth as it orbits
+                        simulation.world.get_rigid_mut(part_handle).unwrap().set_linear_velocity(earth_velocity);
+                        let part = simulation.world.get_part(part_handle).unwrap();
+                        let part_id = part.id();
+                        free_parts.insert(part_id, FreePart::EarthCargo(part_handle, config().ticks(config().earth_cargo_reset_seconds)));
+                        outbound_events.push(ToSerializer::Broadcast(part.add_msg()));
+                        outbound_events.push(ToSerializer::Broadcast(part.move_msg()));
+                        outbound_events.push(ToSerializer::Broadcast(part.update_meta_msg()));
+                    }
+                }
+                if let (Some(outbox), true) = (outbox.as_ref(), config().autosave_seconds > 0) {
+                    *ticks_til_autosave -= 1;
+                    if *ticks_til_autosave == 0 {
+                        *ticks_til_autosave = config().ticks(config().autosave_seconds);
+                        let mut saved = 0;
+                        for player in players.values() {
+                            if let Some(beamout_token) = &player.beamout_token {
+                                let layout = simulation.world.get_part(player.core).unwrap().deflate(&simulation.world);
+                                outbox.submit(&player.name, beamout_token.clone(), layout, true);
+                                saved += 1;
+                            }
+                        }
+                        if saved > 0 { debug!("game"; "Autosaving {} ships", saved); }
+                    }
+                }
+                if !config().snapshot_file.is_empty() && config().snapshot_seconds > 0 {
+                    *ticks_til_snapshot -= 1;
+                    if *ticks_til_snapshot == 0 {
+                        *ticks_til_snapshot = config().ticks(config().snapshot_seconds);
+                        let snapshot = snapshot::WorldSnapshot::capture(simulation, players, free_parts, *earth_cargos, *ticks_til_earth_cargo_spawn, *seed);
+                        async_std::task::spawn_blocking(move || match snapshot::save(&config().snapshot_file, &snapshot) {
+                            Ok(()) => debug!("game"; "Saved a snapshot of the world"),
+                            Err(err) => error!("game"; "{}", err),
+                        });
+                    }
+                }
+                *ticks_til_power_regen -= 1;
+                let is_power_regen_tick;
+                if *ticks_til_power_regen == 0 { *ticks_til_power_regen = 5; is_power_regen_tick = true; }
+                else { is_power_regen_tick = false; }
+                for (id, player) in players.iter_mut() {
+                    if is_power_regen_tick {
+                        player.power += player.power_regen_per_5_ticks;
+                        if player.power > player.max_power { player.power = player.max_power; };
+                    };
+                    if player.power > 0 {
+                        simulation.world.recurse_part_mut(player.core, Default::default(), &mut |mut handle: world::PartVisitHandleMut| {
+                            (*handle).thrust_no_recurse(&mut player.power, player.thrust_forwards, player.thrust_backwards, player.thrust_clockwise, player.thrust_counterclockwise);
+                        });
+                        if player.power < 1 {
+                            player.thrust_backwards = false; player.thrust_forwards = false; player.thrust_clockwise = false; player.thrust_counterclockwise = false;
+                            outbound_events.push(ToSerializer::Broadcast(codec::ToClientMsg::UpdatePlayerMeta {
+                                id:  *id,
+                                thrust_forward: player.thrust_forwards, thrust_backward: player.thrust_backwards, thrust_clockwise: player.thrust_clockwise, thrust_counter_clockwise: player.thrust_counterclockwise,
+                                grabed_part: player.grabbed_part.map(|(id,_,_,_)| id)
+                            }));
+                        }
+                    }
+                    player.ticks_til_fire = player.ticks_til_fire.saturating_sub(1);
+                    if player.firing && player.ticks_til_fire == 0 {
+                        let mut weapons = Vec::new();
+                        simulation.world.recurse_part(player.core, Default::default(), &mut |handle: world::PartVisitHandle| {
+                            if let Some(weapon) = handle.kind().weapon() { weapons.push((handle.handle(), weapon)); }
+                        });
+                        //Shots from a safe zone can't hurt anyone either, or it'd be a safe place to snipe from
+                        let core_position = simulation.world.get_rigid(player.core).unwrap().position().translation.vector;
+                        let harmless = !*pvp || simulation.planets.in_safe_zone(core_position);
+                        for (handle, weapon) in weapons {
+                            if player.power < weapon.power_cost { break };
+                            player.power -= weapon.power_cost;
+                            player.ticks_til_fire = player.ticks_til_fire.max((weapon.reload_seconds * config().ticks_per_second as f32).ceil() as u16);
+                            if let Some(msg) = simulation.fire(handle, *id, harmless) { outbound_events.push(ToSerializer::Broadcast(msg)); }
+                        }
+                    }
+                    if let Some((part_id, constraint, x, y)) = player.grabbed_part {
+                        let core = simulation.world.get_part_mut(player.core).expect("Player iter invalid core part");
+                        let position = core.body().position().translation;
+                        simulation.move_mouse_constraint(constraint, x + position.x, y + position.y);
+                    }
+                    if let Some(planet_id) = player.touching_planet {
+                        player.ticks_til_repair -= 1;
+                        if player.ticks_til_repair < 1 {
+                            player.ticks_til_repair = config().ticks(1);
+                            if simulation.planets.get_celestial_object(planet_id).unwrap().repairs {
+                                simulation.world.recurse_part_mut(player.core, Default::default(), &mut |mut handle: world::PartVisitHandleMut| {
+                                    if handle.is_damaged() {
+                                        let health = handle.health().saturating_add(config().repair_per_second);
+                                        handle.set_health(health);
+                                        outbound_events.push(ToSerializer::Broadcast(handle.health_msg()));
+                                    }
+                                });
+                            }
+                        }
+                        player.ticks_til_cargo_transform -= 1;
+                        if player.ticks_til_cargo_transform < 1 {
+                            player.ticks_til_cargo_transform = config().ticks(config().cargo_upgrade_seconds);
+                            if let Some(upgrade_into) = simulation.planets.get_celestial_object(planet_id).unwrap().cargo_upgrade {
+                                let core = simulation.world.get_part(player.core).expect("Player iter invalid core part");
+                                if let Some((parent_part_handle, slot)) = core.find_cargo_recursive(&simulation.world) {
+                                    let parent_part_handle = parent_part_handle.unwrap_or(player.core);
+                                    let parent_part = simulation.world.get_part_mut(parent_part_handle).unwrap();
+                                    let old_part_handle = parent_part.detach_part_player_agnostic(slot, &mut simulation.joints).unwrap();
+                                    let old_part = simulation.world.remove_part_unprotected(old_part_handle);
+                                    outbound_events.push(ToSerializer::Broadcast(old_part.remove_msg()));
+                                    if player.parts_touching_planet.remove(&old_part_handle) {
+                                        if player.parts_touching_planet.is_empty() { 
+                                            player.touching_planet = None;
+                                            player.can_beamout = false;
+                                        }
+                                    }
+                                    let new_part_handle = old_part.mutate(upgrade_into, &mut Some(player), &mut simulation.world, &mut simulation.colliders, &mut simulation.joints);
+                                    let parent_part = simulation.world.get_part_mut(parent_part_handle).unwrap();
+                                    parent_part.attach_part_player_agnostic(slot, new_part_handle, parent_part_handle, &mut simulation.joints);
+                                    let new_part = simulation.world.get_part(new_part_handle).unwrap();
+                                    outbound_events.push(ToSerializer::Message(*id, player.update_my_meta()));
+                                    outbound_events.push(ToSerializer::Broadcast(new_part.add_msg()));
+                                    outbound_events.push(ToSerializer::Broadcast(new_part.move_msg()));
+                                    outbound_events.push(ToSerializer::Broadcast(new_part.update_meta_msg()));
+                                }   
+                            }
+                        }
+                    }
+                }
+
+                let mut my_simulation_events = std::panic::AssertUnwindSafe(&mut *simulation_events);
+                let mut my_simulation = std::panic::AssertUnwindSafe(&mut *simulation);
+                if let Err(err) = std::panic::catch_unwind(move || my_simulation.simulate(&mut my_simulation_events)) {
+                    error!("game"; "Simulation panicked: {:?}", err);
+                    emergency_stop(players, &simulation.world, outbox).await;
+                }
+
+                for event in simulation_events.drain(..) {
+                    use world::SimulationEvent::*;
+                    match event {
+                        PlayerTouchPlanet{ player, planet, part } => {
+                            let player_id = player;
+                            if let Some(player) = players.get_mut(&player) {
+                                if simulation.planets.get_celestial_object(planet).unwrap().hazards.incinerates {
+                                    //Kill player
+                                    outbound_events.push(ToSerializer::Broadcast(codec::ToClientMsg::IncinerationAnimation{ player_id }));
+                                    let my_to_serializer = to_serializer.clone();
+                                    let player = players.remove(&player_id).unwrap();
+                                    let deflated_ship = simulation.world.get_part(player.core).unwrap().deflate(&simulation.world);
+                                    //Don't need to send deletion messages since the client will
+                                    //take care of IncinerationAnimation
+                                    simulation.delete_parts_recursive(player.core);
+                                    async_std::task::spawn(async move {
+                                        futures_timer::Delay::new(std::time::Duration::from_millis(2500)).await;
+                                        my_to_serializer.send(vec![ ToSerializer::DeleteWriter(player_id) ]).await;
+                                    });
+                                } else {
+                                    player.touching_planet = Some(planet);
+                                    player.can_beamout = simulation.planets.get_celestial_object(planet).unwrap().can_beamout;
+                                    player.ticks_til_cargo_transform = config().ticks(config().cargo_upgrade_seconds);
+                                    player.parts_touching_planet.insert(part);
+                                    player.power = player.max_power;
+                                    outbound_events.push(ToSerializer::Message(player_id, codec::ToClientMsg::UpdateMyMeta{ max_power: player.max_power, can_beamout: player.can_beamout }));
+                                }
+                            } else if simulation.planets.get_celestial_object(planet).unwrap().hazards.incinerates {
+                                if let Some(part) = simulation.world.get_part(part) {
+                                    outbound_events.push(ToSerializer::Broadcast(part.remove_msg()));
+                                }
+                            }
+                        },
+                        PartDamaged { part } => {
+                            let destroyed = match simulation.world.get_part(part) {
+                                Some(damaged) if damaged.health() > 0 => { outbound_events.push(ToSerializer::Broadcast(damaged.health_msg())); false },
+                                Some(_) => true,
+                                None => false,
+                            };
+                            if destroyed { destroy_part(part, None, simulation, players, free_parts, earth_cargos, to_serializer, &mut outbound_events); }
+                        },
+                        ProjectileHit { projectile, shooter, damage, part } => {
+                            outbound_events.push(ToSerializer::Broadcast(ToClientMsg::RemoveProjectile { id: projectile }));
+                            //Only other players' ships take damage, and only while PvP is on and they're out of any safe zone
+                            let hurt = part.filter(|part| damage > 0 && *pvp && simulation.world.get_part(*part).map(|target| {
+                                target.part_of_player().map(|owner| owner != shooter).unwrap_or(false) && !simulation.planets.in_safe_zone(target.body().position().translation.vector)
+                            }).unwrap_or(false));
+                            if let Some(part) = hurt {
+                                let target = simulation.world.get_part_mut(part).unwrap();
+                                let health = target.health().saturating_sub(damage);
+                                target.set_health(health);
+                                if health > 0 { outbound_events.push(ToSerializer::Broadcast(target.health_msg())); }
+                                else { destroy_part(part, Some(shooter), simulation, players, free_parts, earth_cargos, to_serializer, &mut outbound_events); }
+                            }
+                        },
+                        ProjectileExpired { projectile } => {
+                            outbound_events.push(ToSerializer::Broadcast(ToClientMsg::RemoveProjectile { id: projectile }));
+                        },
+                        PlayerUntouchPlanet{ player, planet, part } => {
+                            let player_id = player;
+                            if let Some(player) = players.get_mut(&player) {
+                                if player.parts_touching_planet.remove(&part) {
+                                    if player.parts_touching_planet.is_empty() { 
+                                        player.touching_planet = None;
+                                        player.can_beamout = false;
+                                        outbound_events.push(ToSerializer::Message(player_id, codec::ToClientMsg::UpdateMyMeta{ max_power: player.max_power, can_beamout: player.can_beamout }));
+                                    }
+                                }
+                            }
+                        },
+                    }
+                }
+
+                for player in players.values_mut() { 
+                    recursive_broken_detach(player.core, simulation, free_parts, &mut Some(player), &mut outbound_events);
+                }
+
+                outbound_events.push(ToSerializer::WorldUpdate(
+                    {
+                        let mut out = BTreeMap::new();
+                        for (id, player) in players.iter() {
+                            let mut parts = Vec::new();
+                            let part = simulation.world.get_part(player.core).unwrap();
+                            let vel = part.body().velocity();
+                            part.physics_update_msg(&simulation.world, &mut parts);
+                            out.insert(*id, ((parts[0].x, parts[0].y), (vel.linear.x, vel.linear.y), parts, ToClientMsg::PostSimulationTick{ your_power: player.power }));
+                        }
+                        out
+                    },
+                    free_parts.iter().map(|(id, meta)| {
+                        let body = simulation.world.get_rigid(**meta).unwrap();
+                        let position = body.position();
+                        session::WorldUpdatePartMove {
+                            id: *id,
+                            x: position.translation.x, y: position.translation.y,
+                            rot_cos: position.rotation.re, rot_sin: position.rotation.im
+                        }
+                    }).collect::<Vec<_>>()
+                ));
+
+                METRICS.record_tick(tick_start.elapsed());
+                Metrics::set(&METRICS.connected_players, players.len());
+                Metrics::set(&METRICS.queued_players, session_shared.slots.queued());
+                Metrics::set(&METRICS.free_parts, free_parts.len());
+                Metrics::set(&METRICS.earth_cargos, *earth_cargos as usize);
+                Metrics::set(&METRICS.game_queue_depth, to_game.len());
+                Metrics::set(&METRICS.serializer_queue_depth, to_serializer.len());
+
+            },
+
+
+            Event::InboundEvent(PlayerQuit { id }) => {
+                player_quit(id, session_shared, simulation, players, free_parts, &mut outbound_events);
+            },
+
+            Event::InboundEvent(PlayerSuspend { id, ref_handle }) => {
+                if let Some(player) = players.get_mut(&id) {
+                    info!("game", id = id, session = ref_handle; "Player {} suspended", player.name);
+                    player.thrust_forwards = false;
+                    player.thrust_backwards = false;
+                    player.thrust_counterclockwise = false;
+                    player.thrust_clockwise = false;
+                    player.firing = false;
+                    if let Some((id, constraint, _, _)) = std::mem::replace(&mut player.grabbed_part, None) {
+                        simulation.release_constraint(constraint);
+                        free_parts.get_mut(&id).unwrap().become_decaying();
+                    }
+                    outbound_events.push(ToSerializer::Broadcast(player.update_meta_msg()));
+                    outbound_events.push(ToSerializer::Broadcast(ToClientMsg::ChatMessage { username: "Server".to_owned(), msg: format!("{} has disconnected", player.name), color: "#e270ff".to_owned() }));
+
+                    suspend_player(id, ref_handle, player.role, &session_shared.suspended_players, to_game).await;
+                } else {
+                    warn!("game", id = id; "Tried to suspend a player that doesn't exist");
+                }
+            },
+            Event::InboundEvent(PlayerReconnect { id }) => {
+                if let Some(player) = players.get(&id) {
+                    info!("game", id = id; "Player {} reconnected", player.name);
+                    outbound_events.push(ToSerializer::Message(id, ToClientMsg::HandshakeAccepted{ id, core_id: simulation.world.get_part(player.core).unwrap().id(), can_beamout: player.beamout_token.is_some() }));
+                    outbound_events.push(ToSerializer::Broadcast(ToClientMsg::ChatMessage { username: "Server".to_owned(), msg: format!("{} has reconnected", player.name), color: "#e270ff".to_owned() }));
+                } else {
+                    warn!("game", id = id; "Tried to reconnect a player that doesn't exist");
+                    outbound_events.push(ToSerializer::DeleteWriter(id));
+                }
+            },
+            
+            Event::InboundEvent(NewPlayer{ id, name, session, parts, beamout_token, role }) => { 
+                info!("game", id = id; "New player {}", name);
+                let earth = simulation.planets.spawn_planet();
+                let earth_position = simulation.world.get_rigid(earth.body).unwrap().position().translation.vector;
+                let earth_radius = earth.radius;
+                let earth_velocity = simulation.planets.velocity_of(earth);
+
+                let spawn_degrees: f32 = simulation.rng.gen::<f32>() * std::f32::consts::PI * 2.0;
+                let core_handle = simulation.inflate(&parts, Isometry2::new(Vector2::new(0.0,0.0), spawn_degrees - std::f32::consts::FRAC_PI_2));
+                let mut max_extent: i32 = 1;
+                simulation.world.recurse_part(core_handle, Default::default(), &mut |handle: world::PartVisitHandle| max_extent = max_extent.min(handle.details().part_rel_y));
+                let max_extent = max_extent as f32 / 3.0;
+                let spawn_radius: f32 = earth_radius * 1.25 + 1.0 + max_extent.abs();
+                let spawn_center = (Vector2::new(spawn_degrees.cos(), spawn_degrees.sin()) * spawn_radius) + earth_position;
+                simulation.world.recurse_part_mut(core_handle, Default::default(), &mut |mut handle: world::PartVisitHandleMut| {
+                    let part = &mut handle;
+                    let new_pos = Isometry2::new(
+                        part.body().position().translation.vector.clone() + spawn_center,
+                        part.body().position().rotation.angle()
+                    );
+                    part.body_mut().set_position(new_pos);
+                    part.body_mut().set_linear_velocity(earth_velocity);
+                });
+
+                let core = simulation.world.get_part_mut(core_handle).unwrap();
+
+                outbound_events.push(ToSerializer::Message(id, ToClientMsg::HandshakeAccepted{ id, core_id: core.id(), can_beamout: beamout_token.is_some() }));
+                outbound_events.push(ToSerializer::Broadcast(codec::ToClientMsg::AddPlayer { id, name: name.clone(), core_id: core.id() }));
+                
+                let mut player = PlayerMeta::new(id, core_handle, name.clone(), session, beamout_token, role);
+                simulation.world.recurse_part_mut(core_handle, Default::default(), &mut |mut handle| {
+                    let part = &mut handle;
+                    part.join_to(&mut player);
+                    outbound_events.push(ToSerializer::Broadcast(part.add_msg()));
+                    outbound_events.push(ToSerializer::Broadcast(part.move_msg()));
+                    outbound_events.push(ToSerializer::Broadcast(part.update_meta_msg()));
+                });
+                player.power = player.max_power;
+
+                outbound_events.push(ToSerializer::Message(id, player.update_my_meta()));
+                players.insert(id, player);
+                outbound_events.push(ToSerializer::Broadcast(ToClientMsg::ChatMessage{ username: String::from("Server"), msg: name + " joined the game", color: String::from("#e270ff") }));
+            },
+            Event::InboundEvent(SendEntireWorld{ to_player, send_self }) => {
+                //Send over celestial object locations
+                for planet in simulation.planets.celestial_objects() {
+                    let position = simulation.world.get_rigid(planet.body).unwrap().position().translation;
+                    outbound_events.push(ToSerializer::Message(to_player, ToClientMsg::AddCelestialObject {
+                        name: planet.name.clone(), display_name: planet.display_name.clone(),
+                        id: planet.id, radius: planet.radius, position: (position.x, position.y)
+                    }));
+                }
+                //Clients extrapolate the orbits themselves from here on
+                for planet in simulation.planets.celestial_objects() {
+                    if let Some(orbit) = &planet.orbit {
+                        outbound_events.push(ToSerializer::Message(to_player, ToClientMsg::CelestialObjectOrbit {
+                            id: planet.id, parent: orbit.parent, semi_major_axis: orbit.semi_major_axis, eccentricity: orbit.eccentricity,
+                            argument_of_periapsis: orbit.argument_of_periapsis, mean_anomaly: orbit.mean_anomaly(simulation.planets.time), mean_motion: orbit.mean_motion,
+                        }));
+                    }
+                }
+
+                for (_id, part) in free_parts.iter() { simulation.world.recurse_part(**part, Default::default(), &mut |handle: world::PartVisitHandle| {
+                    let part = &handle;
+                    outbound_events.push(ToSerializer::Message(to_player, part.add_msg()));
+                    outbound_events.push(ToSerializer::Message(to_player, part.move_msg()));
+                    outbound_events.push(ToSerializer::Message(to_player, part.update_meta_msg()));
+                    if part.is_damaged() { outbound_events.push(ToSerializer::Message(to_player, part.health_msg())); }
+                }); }
+                for (other_id, other_player) in players.iter() {
+                    if !send_self && *other_id == to_player { continue };
+                    let other_core = simulation.world.get_part(other_player.core).unwrap();
+                    outbound_events.push(ToSerializer::Message(to_player, codec::ToClientMsg::AddPlayer{ id: *other_id, name: other_player.name.clone(), core_id: other_core.id() }));
+                    simulation.world.recurse_part(other_player.core, Default::default(), &mut |handle: world::PartVisitHandle| {
+                        let part = &handle;
+                        outbound_events.push(ToSerializer::Message(to_player, part.add_msg()));
+                        outbound_events.push(ToSerializer::Message(to_player, part.move_msg()));
+                        outbound_events.push(ToSerializer::Message(to_player, part.update_meta_msg()));
+                        if part.is_damaged() { outbound_events.push(ToSerializer::Message(to_player, part.health_msg())); }
+                    });
+                }
+                for (_, projectile) in simulation.world.iter_projectiles() {
+                    outbound_events.push(ToSerializer::Message(to_player, projectile.add_msg()));
+                }
+                if send_self {
+                    if let Some(player) = players.get(&to_player) {
+                        outbound_events.push(ToSerializer::Message(to_player, player.update_my_meta()));
+                    }
+                }
+
+                outbound_events.push(ToSerializer::Message(to_player, codec::ToClientMsg::ChatMessage{ color: "#e270ff".to_owned(), username: "Server".to_owned(), msg: format!("There are {} players online", players.len()) }));
+            },
+
+            Event::InboundEvent(PlayerMessage{ id, msg }) => {
+                match msg {
+                    ToServerMsg::SetThrusters{ forward, backward, clockwise, counter_clockwise } => {
+                        if let Some(player) = players.get_mut(&id) {
+                            if player.power > 0 {
+                                player.thrust_forwards = forward;
+                                player.thrust_backwards = backward;
+                                player.thrust_clockwise = clockwise;
+                                player.thrust_counterclockwise = counter_clockwise;
+                                outbound_events.push(ToSerializer::Broadcast(codec::ToClientMsg::UpdatePlayerMeta {
+                                    id,
+                                    thrust_forward: forward, thrust_backward: backward, thrust_clockwise: clockwise, thrust_counter_clockwise: counter_clockwise,
+                                    grabed_part: player.grabbed_part.map(|(id, _, _, _)| id)
+                                }));
+                            };
+                        }
+                    },
+
+                    ToServerMsg::CommitGrab{ grabbed_id, x, y } => {
+                        if let Some(player_meta) = players.get_mut(&id) {
+                            let core = simulation.world.get_part(player_meta.core).unwrap();
+                            if player_meta.grabbed_part.is_none() {
+                                let core_location = core.body().position().translation;
+                                let point = nphysics2d::math::Point::new(x + core_location.x, y + core_location.y);
+                                if let Some(free_part) = free_parts.get_mut(&grabbed_id) {
+                                    if let FreePart::Decaying(part, _) | FreePart::EarthCargo(part, _) = &free_part {
+                                        player_meta.grabbed_part = Some((grabbed_id, simulation.equip_mouse_dragging(*part), x, y));
+                                        outbound_events.push(ToSerializer::Broadcast(simulation.world.get_part(*part).unwrap().update_meta_msg()));
+                                        outbound_events.push(ToSerializer::Broadcast(player_meta.update_meta_msg()));
+                                        free_part.become_grabbed(earth_cargos);
+                                    }
+                                } else {
+                                    let world = &mut simulation.world;
+                                    let joints = &mut simulation.joints;
+                                    if let Some(part_handle) = simulation.world.recurse_part_mut_with_return(player_meta.core, Default::default(), &mut |mut handle| {
+                                        for (i, attachment) in (*handle).attachments().iter().enumerate() {
+                                            if let Some(attachment) = attachment {
+                                                if handle.get_part(**attachment).unwrap().id() == grabbed_id {
+                                                    return Some((*handle).detach_part_player_agnostic(i, joints).unwrap())
+                                                };
+                                            }
+                                        };
+                                        None
+                                    }) {
+                                        simulation.world.get_part_mut(part_handle).unwrap().remove_from(player_meta);
+                                        //what was I thinking here simulation.world.recurse_part_mut(part_handle, 0, 0, AttachedPartFacing::Up, AttachedPartFacing::Up, &mut |_handle, part: &mut world::parts::Part, _, _, _, _| part.join_to(player_meta));
+                                        let mut parts_affected = BTreeSet::new();
+                                        parts_affected.insert(part_handle);
+                                        simulation.world.recursive_detach_all(part_handle, &mut Some(player_meta), &mut simulation.joints, &mut parts_affected);
+                                        player_meta.grabbed_part = Some((grabbed_id, simulation.equip_mouse_dragging(part_handle), x, y));
+                                        if player_meta.parts_touching_planet.remove(&part_handle) {
+                                            if player_meta.parts_touching_planet.is_empty() { 
+                                                player_meta.touching_planet = None;
+                                                player_meta.can_beamout = false;
+                                            }
+                                        }
+                                        //outbound_events.push(ToSerializer::Message(id, codec::ToClientMsg::UpdateMyMeta{ max_power: player_meta.max_power, can_beamout: player_meta.can_beamout }));
+
+                                        for part_affected in parts_affected {
+                                            let part = simulation.world.get_part(part_affected).unwrap();
+                                            free_parts.insert(part.id(), FreePart::Decaying(part_affected, config().ticks(config().part_decay_seconds)));
+                                            outbound_events.push(ToSerializer::Broadcast(part.update_meta_msg()));
+                                        }
+                                        free_parts.insert(grabbed_id, FreePart::Grabbed(part_handle));
+                                        outbound_events.push(ToSerializer::Broadcast(player_meta.update_meta_msg()));
+                                    };
+                                }
+                            }
+                        }
+                    },
+                    ToServerMsg::MoveGrab{ x, y } => {
+                        if let Some(player_meta) = players.get_mut(&id) {
+                            if let Some((part_id, constraint, _, _)) = player_meta.grabbed_part {
+                                //simulation.move_mouse_constraint(constraint, x, y);
+                                player_meta.grabbed_part = Some((part_id, constraint, x, y));
+                            }
+                        }
+                    },
+                    ToServerMsg::ReleaseGrab => {
+                        if let Some(player_meta) = players.get_mut(&id) {
+                            if let Some((part_id, constraint, x, y)) = player_meta.grabbed_part {
+                                simulation.release_constraint(constraint);
+                                player_meta.grabbed_part = None;
+                                let mut attachment_msg: Option<Vec<u8>> = None;
+                                let core_location = simulation.world.get_rigid(player_meta.core).unwrap().position().clone();
+                                let grabbed_part_handle = **free_parts.get(&part_id).unwrap();
+                                let grabbed_part = simulation.world.get_part_mut(grabbed_part_handle).unwrap();
+                                let inertia = grabbed_part.kind().inertia();
+                                grabbed_part.body_mut().set_local_inertia(inertia);
+                                grabbed_part.body_mut().set_velocity(nphysics2d::algebra::Velocity2::new(Vector2::new(0.0,0.0), 0.0));
+        
+                                use world::parts::CompactThrustMode;
+                                let target_x = x + core_location.translation.x;
+                                let target_y = y + core_location.translation.y; 
+                                if let Some((parent_handle, attachment_slot, attachment_details, teleport_to, thrust_mode, true_facing)) = simulation.world.recurse_part_mut_with_return(
+                                    player_meta.core, Default::default(),
+                                    &mut |mut handle| {
+                                        let parent = &mut handle;
+                                        let attachments = parent.kind().attachment_locations();
+                                        let pos = parent.body().position().clone();
+                                        for (i, attachment) in parent.attachments().iter().enumerate() {
+                                            if attachment.is_none() {
+                                                if let Some(details) = &attachments[i] {
+                                                    let mut rotated = rotate_vector(details.x, details.y, pos.rotation.im, pos.rotation.re);
+                                                    rotated.0 += pos.translation.x;
+                                                    rotated.1 += pos.translation.y;
+                                                    if (rotated.0 - target_x).abs() <= 0.4 && (rotated.1 - target_y).abs() <= 0.4 {
+                                                        let my_true_facing = details.facing.compute_true_facing(handle.details().true_facing);
+                                                        let thrust_mode = CompactThrustMode::calculate(my_true_facing, handle.details().part_rel_x, handle.details().part_rel_y);
+                                                        return Some((handle.handle(), i, *details, rotated, thrust_mode, my_true_facing));
+                                                    }
+                                                }
+                                            }
+                                        }
+                                        None
+                                    }
+                                ) {
+                                    let parent = simulation.world.get_part_mut(parent_handle).unwrap();
+                                    //TODO: Check if we can use parent.body.position instead of core_location
+                                    parent.attach_part_player_agnostic(attachment_slot, grabbed_part_handle, parent_handle, &mut simulation.joints);
+                                    free_parts.remove(&part_id);
+                                    let grabbed_part = simulation.world.get_part_mut(grabbed_part_handle).unwrap();
+                                    grabbed_part.body_mut().set_position(Isometry2::new(Vector2::new(teleport_to.0, teleport_to.1), true_facing.part_rotation() + core_location.rotation.angle()));
+                                    grabbed_part.join_to(player_meta);
+                                    outbound_events.push(ToSerializer::Message(id, player_meta.update_my_meta()));
+                                    grabbed_part.thrust_mode = thrust_mode;
+                                    outbound_events.push(ToSerializer::Broadcast(grabbed_part.update_meta_msg()));
+                                } else {
+                                    free_parts.get_mut(&part_id).unwrap().become_decaying();
+                                }
+        
+                                outbound_events.push(ToSerializer::Broadcast(codec::ToClientMsg::UpdatePlayerMeta {
+                                    id,
+                                    thrust_forward: player_meta.thrust_forwards, thrust_backward: player_meta.thrust_backwards, thrust_clockwise: player_meta.thrust_clockwise, thrust_counter_clockwise: player_meta.thrust_counterclockwise,
+                                    grabed_part: None
+                                }));
+                            }
+                        }
+                    },
+                    ToServerMsg::SetFiring{ firing } => {
+                        if let Some(player) = players.get_mut(&id) { player.firing = firing; }
+                    },
+                    ToServerMsg::BeamOut => {
+                        if let Some(player) = players.get(&id) {
+                            if player.can_beamout {
+                                let player = players.remove(&id).unwrap();
+                                let core = simulation.world.get_part(player.core).unwrap();
+                                let beamout_layout = core.deflate(&simulation.world);
+                                outbound_events.push(ToSerializer::Broadcast(codec::ToClientMsg::BeamOutAnimation { player_id: id }));
+                                outbound_events.push(ToSerializer::Broadcast(codec::ToClientMsg::ChatMessage { username: "Server".to_owned(), msg: format!("{} has left the game", player.name), color: "#e270ff".to_owned() }));
+                                simulation.delete_parts_recursive(player.core);
+                                if let (Some(beamout_token), Some(outbox)) = (player.beamout_token, outbox.as_ref()) {
+                                    outbox.submit(&player.name, beamout_token, beamout_layout, false);
+                                };
+                                let my_to_serializer = to_serializer.clone();
+                                async_std::task::spawn(async move {
+                                    futures_timer::Delay::new(std::time::Duration::from_millis(2500)).await;
+                                    my_to_serializer.send(vec![ ToSerializer::DeleteWriter(id) ]).await;
+                                });
+                            }
+                        }
+                    },
+                    _ => { outbound_events.push(ToSerializer::DeleteWriter(id)); }
+                }
+            },
+
+            Event::InboundEvent(AdminCommand { issuer, command }) => {
+                use session::CommandIssuer;
+                let chunks: Vec<String> = command.split_whitespace().map(|s| s.to_string()).collect();
+                let (issuer_name, issuer_role) = match &issuer {
+                    CommandIssuer::Player(id) => players.get(id).map(|player| (player.name.clone(), player.role)).unwrap_or_default(),
+                    CommandIssuer::Console(_) => (String::from("Console"), roles::Role::Owner),
+                };
+                //Replies are (is_error, message)
+                let mut replies: Vec<(bool, String)> = Vec::new();
+                let permitted = chunks.first().and_then(|command| roles::command_permission(command)).map(|permission| issuer_role.has(permission)).unwrap_or(false);
+                match if permitted { chunks[0].as_str() } else { "" } {
+                    "/teleport" => {
+                        //Either "/teleport x y" for yourself or "/teleport name x y" for someone else
+                        let target = if chunks.len() == 3 {
+                            if let CommandIssuer::Player(id) = &issuer { Some(*id) } else { None }
+                        } else if chunks.len() > 3 {
+                            let target_name = chunks[1..chunks.len() - 2].join(" ");
+                            players.values().find(|player| player.name == target_name).map(|player| player.id)
+                        } else { None };
+                        let coords = if chunks.len() >= 3 { (chunks[chunks.len() - 2].parse::<f32>().ok(), chunks[chunks.len() - 1].parse::<f32>().ok()) } else { (None, None) };
+                        if let (Some(player_meta), (Some(x), Some(y))) = (target.and_then(|target| players.get_mut(&target)), coords) {
+                            let teleport_to = Vector2::new(x, y);
+                            let core_pos = simulation.world.get_rigid(player_meta.core).unwrap().position().translation.vector;
+                            info!("admin"; "{} teleported {} to {} {}", issuer_name, player_meta.name, x, y);
+                            simulation.world.recurse_part_mut(player_meta.core, Default::default(), &mut |mut handle: world::PartVisitHandleMut| {
+                                let pos = Isometry2::new(
+                                        (*handle).body().position().clone().translation.vector - core_pos + teleport_to,
+                                        (*handle).body().position().rotation.angle()
+                                );
+                                (*handle).body_mut().set_position(pos);
+                            });
+                            replies.push((false, format!("Teleported {} to {} {}", player_meta.name, x, y)));
+                        } else {
+                            replies.push((true, String::from("Usage: /teleport [name] <x> <y>")));
+                        }
+                    },
+
+                    "/kick" => {
+                        let target_name = chunks[1..].join(" ");
+                        if let Some(target) = players.values().find(|player| player.name == target_name) {
+                            if issuer_role.outranks(target.role) {
+                                let target_id = target.id;
+                                info!("admin", id = target_id; "{} kicked {}", issuer_name, target.name);
+                                outbound_events.push(ToSerializer::Broadcast(ToClientMsg::ChatMessage { username: "Server".to_owned(), msg: format!("{} was kicked", target.name), color: "#e270ff".to_owned() }));
+                                replies.push((false, format!("Kicked {}", target.name)));
+                                //A suspended player has no connection to close, so nothing would tell us they quit until they time out.
+                                //Taking them off the list also stops their timeout, and them reconnecting, from doing anything.
+                                let mut suspended_players = session_shared.suspended_players.lock().await;
+                                let was_suspended = suspended_players.iter().any(|suspended| suspended.id == target_id);
+                                suspended_players.retain(|suspended| suspended.id != target_id);
+                                drop(suspended_players);
+                                if was_suspended { player_quit(target_id, session_shared, simulation, players, free_parts, &mut outbound_events); }
+                                else { outbound_events.push(ToSerializer::DeleteWriter(target_id)); }
+                            } else { replies.push((true, format!("You cannot kick {}", target.name))); }
+                        } else { replies.push((true, format!("There is no player named {}", target_name))); }
+                    },
+
+                    "/list" => {
+                        let mut list = format!("There are {} players online", players.len());
+                        let queued = session_shared.slots.queued();
+                        if queued > 0 { list += &format!(" and {} waiting in the queue", queued); }
+                        for player in players.values() {
+                            let position = simulation.world.get_rigid(player.core).unwrap().position().translation;
+                            list += &format!("\n{} {} ({:?}) at {:.0} {:.0}", player.id, player.name, player.role, position.x, position.y);
+                        }
+                        replies.push((false, list));
+                    },
+
+                    "/broadcast" => {
+                        let msg = chunks[1..].join(" ");
+                        if msg.is_empty() { replies.push((true, String::from("Usage: /broadcast <message>"))); }
+                        else {
+                            outbound_events.push(ToSerializer::Broadcast(ToClientMsg::ChatMessage { username: "Server".to_owned(), msg, color: "#e270ff".to_owned() }));
+                            replies.push((false, String::from("Broadcasted")));
+                        }
+                    },
+
+                    "/outbox" => {
+                        if let Some(outbox) = &outbox {
+                            if chunks.get(1).map(|arg| arg == "retry").unwrap_or(false) {
+                                match outbox.retry_failed() {
+                                    Ok(count) => replies.push((false, format!("Retrying {} failed beamouts", count))),
+                                    Err(err) => replies.push((true, format!("Failed to requeue beamouts: {}", err))),
+                                }
+                            } else {
+                                let lines = outbox.describe();
+                                replies.push((false, format!("{} beamouts in the outbox", lines.len())));
+                                replies.extend(lines.into_iter().map(|line| (false, line)));
+                            }
+                        } else { replies.push((true, String::from("There is no API to beam out to"))); }
+                    },
+
+                    "/shutdown" => {
+                        let seconds = match chunks.get(1).map(|seconds| seconds.parse::<u16>()) {
+                            Some(Ok(seconds)) if config().checked_ticks(seconds).is_some() => Some(seconds),
+                            Some(Ok(_)) => { replies.push((true, format!("That's too long, the most is {} seconds", u16::MAX / config().ticks_per_second as u16))); None },
+                            None => Some(config().shutdown_countdown_seconds),
+                            Some(Err(_)) => None,
+                        };
+                        if let Some(seconds) = seconds {
+                            warn!("admin"; "{} started a shutdown in {} seconds", issuer_name, seconds);
+                            begin_shutdown(seconds, shutdown_countdown, &mut outbound_events);
+                            replies.push((false, format!("Shutting down in {} seconds", seconds)));
+                        } else if replies.is_empty() {
+                            replies.push((true, String::from("Usage: /shutdown [seconds]")));
+                        }
+                    },
+
+                    "/pvp" => {
+                        match chunks.get(1).map(String::as_str) {
+                            None => replies.push((false, format!("PvP is {}", if *pvp { "on" } else { "off" }))),
+                            Some(setting @ "on") | Some(setting @ "off") => {
+                                *pvp = setting == "on";
+                                info!("admin"; "{} turned PvP {}", issuer_name, setting);
+                                outbound_events.push(ToSerializer::Broadcast(ToClientMsg::ChatMessage { username: "Server".to_owned(), msg: format!("PvP is now {}", setting), color: "#e270ff".to_owned() }));
+                                replies.push((false, format!("PvP is now {}", setting)));
+                            },
+                            Some(_) => replies.push((true, String::from("Usage: /pvp [on|off]"))),
+                        }
+                    },
+
+                    "/stop" => {
+                        warn!("admin"; "{} called an emergency stop", issuer_name);
+                        emergency_stop(players, &simulation.world, outbox).await;
+                    },
+
+                    _ => {
+                        replies.push((true, String::from("You cannot use that command")));
+                    }
+                    
+                }
+                match issuer {
+                    CommandIssuer::Player(id) => {
+                        for (is_error, msg) in replies {
+                            outbound_events.push(ToSerializer::Message(id, ToClientMsg::ChatMessage{ username: String::from("Server"), msg, color: String::from(if is_error { "#FF0000" } else { "#e270ff" }) }));
+                        }
+                    },
+                    CommandIssuer::Console(reply_to) => {
+                        reply_to.send(replies.into_iter().map(|(_, msg)| msg).collect::<Vec<_>>().join("\n")).await;
+                    }
+                }
+            },
+
+            Event::Signal(signal) => {
+                //A second signal, or SIGQUIT, skips the countdown
+                if signal == signal_hook::consts::SIGQUIT || shutdown_countdown.is_some() {
+                    warn!("game"; "Received signal {}, shutting down now", signal);
+                    graceful_shutdown(players, &simulation.world, outbox, to_serializer, snapshot::WorldSnapshot::capture(simulation, players, free_parts, *earth_cargos, *ticks_til_earth_cargo_spawn, *seed)).await;
+                } else {
+                    warn!("game"; "Received signal {}, shutting down in {} seconds", signal, config().shutdown_countdown_seconds);
+                    begin_shutdown(config().shutdown_countdown_seconds, shutdown_countdown, &mut outbound_events);
+                }
+            }
+        }
+        outbound_events
+    }
+}
+
+fn recursive_broken_detach(root: MyHandle, simulation: &mut world::Simulation, free_parts: &mut BTreeMap<u16, FreePart>, player: &mut Option<&mut PlayerMeta>, out: &mut Vec<ToSerializerEvent> ) {
+    let mut broken_parts = Vec::new();
+    let world = &mut simulation.world;
+    let joints = &mut simulation.joints;
+    world.recurse_part(root, Default::default(), &mut |handle| {
+        for (i, attachment) in (*handle).attachments().iter().enumerate() {
+            if let Some(attachment) = attachment {
+                if attachment.is_broken(joints) { broken_parts.push((handle.handle(), i)) };
+            }
+        }
+    });
+    let mut affected_parts = BTreeSet::new();
+    for (parent, attachment_slot) in broken_parts {
+        simulation.world.recursive_detach_one(parent, attachment_slot, player, &mut simulation.joints, &mut affected_parts);
+    }
+    free_detached_parts(affected_parts, simulation, free_parts, player, out);
+}
+
+/// Sets parts that just came off a player's ship adrift to decay
+fn free_detached_parts(affected_parts: BTreeSet<MyHandle>, simulation: &mut world::Simulation, free_parts: &mut BTreeMap<u16, FreePart>, player: &mut Option<&mut PlayerMeta>, out: &mut Vec<ToSerializerEvent>) {
+    if !affected_parts.is_empty() {
+        for part_handle in affected_parts {
+            if let Some(part) = simulation.world.get_part(part_handle) {
+                out.push(ToSerializerEvent::Broadcast(part.update_meta_msg()));
+                free_parts.insert(part.id(), FreePart::Decaying(part_handle, config().ticks(config().part_decay_seconds)));
+            }
+            if let Some(player) = player {
+                if player.parts_touching_planet.remove(&part_handle) {
+                    if player.parts_touching_planet.is_empty() {
+                        player.can_beamout = false;
+                        player.touching_planet = None;
+                    }
+                }
+            }
+        }
+        if let Some(player) = player {
+            out.push(ToSerializerEvent::Message(player.id, player.update_my_meta()));
+        }
+    }
+}
+
+/// Takes a part that's run out of health out of the world. Whatever was attached to it comes loose,
+/// and losing a core destroys the player along with it, crediting `killer` in the kill feed if someone shot it.
+#[allow(clippy::too_many_arguments)]
+fn destroy_part(handle: MyHandle, killer: Option<u16>, simulation: &mut world::Simulation, players: &mut BTreeMap<u16, PlayerMeta>, free_parts: &mut BTreeMap<u16, FreePart>, earth_cargos: &mut u8, to_serializer: &Sender<Vec<ToSerializerEvent>>, out: &mut Vec<ToSerializerEvent>) {
+    let part = match simulation.world.get_part(handle) { Some(part) => part, None => return };
+    let part_id = part.id();
+    if let Some(player_id) = part.part_of_player() {
+        if players.get(&player_id).map(|player| player.core == handle).unwrap_or(false) {
+            let mut player = players.remove(&player_id).unwrap();
+            let killer = killer.and_then(|killer| players.get(&killer)).map(|killer| killer.name.clone());
+            match &killer {
+                Some(killer) => info!("game", id = player_id; "Player {}'s core was destroyed by {}", player.name, killer),
+                None => info!("game", id = player_id; "Player {}'s core was destroyed", player.name),
+            }
+            if let Some((grabbed_id, constraint, _, _)) = player.grabbed_part.take() {
+                simulation.release_constraint(constraint);
+                if let Some(free_part) = free_parts.get_mut(&grabbed_id) { free_part.become_decaying(); }
+            }
+            let mut affected_parts = BTreeSet::new();
+            simulation.world.recursive_detach_all(handle, &mut Some(&mut player), &mut simulation.joints, &mut affected_parts);
+            free_detached_parts(affected_parts, simulation, free_parts, &mut None, out);
+            out.extend(simulation.delete_parts_recursive(handle).into_iter().map(ToSerializerEvent::Broadcast));
+            out.push(ToSerializerEvent::Broadcast(ToClientMsg::RemovePlayer { id: player_id }));
+            let msg = match &killer { Some(killer) => format!("{} was destroyed by {}", player.name, killer), None => format!("{} was destroyed", player.name) };
+            out.push(ToSerializerEvent::Broadcast(ToClientMsg::ChatMessage { username: String::from("Server"), msg, color: String::from("#e270ff") }));
+            out.push(ToSerializerEvent::Broadcast(ToClientMsg::KillFeed { victim: player.name.clone(), killer }));
+            //Long enough for the client to see what happened
+            let to_serializer = to_serializer.clone();
+            async_std::task::spawn(async move {
+                futures_timer::Delay::new(std::time::Duration::from_millis(2500)).await;
+                to_serializer.send(vec![ ToSerializerEvent::DeleteWriter(player_id) ]).await;
+            });
+        } else if let Some(player) = players.get_mut(&player_id) {
+            let parent = simulation.world.recurse_part_with_return(player.core, Default::default(), &mut |parent| {
+                parent.attachments().iter().position(|attachment| attachment.as_ref().map(|attachment| **attachment) == Some(handle)).map(|slot| (parent.handle(), slot))
+            });
+            if let Some((parent, slot)) = parent {
+                let mut affected_parts = BTreeSet::new();
+                simulation.world.recursive_detach_one(parent, slot, &mut Some(player), &mut simulation.joints, &mut affected_parts);
+                affected_parts.remove(&handle);
+                if player.parts_touching_planet.remove(&handle) && player.parts_touching_planet.is_empty() {
+                    player.can_beamout = false;
+                    player.touching_planet = None;
+                }
+                out.extend(simulation.delete_parts_recursive(handle).into_iter().map(ToSerializerEvent::Broadcast));
+                free_detached_parts(affected_parts, simulation, free_parts, &mut Some(player), out);
+                out.push(ToSerializerEvent::Message(player.id, player.update_my_meta()));
+            }
+        }
+    } else if let Some(free_part) = free_parts.remove(&part_id) {
+        match free_part {
+            FreePart::EarthCargo(_, _) => *earth_cargos -= 1,
+            FreePart::Grabbed(_) => {
+                if let Some(player) = players.values_mut().find(|player| player.grabbed_part.map(|(grabbed_id, _, _, _)| grabbed_id) == Some(part_id)) {
+                    simulation.release_constraint(player.grabbed_part.take().unwrap().1);
+                    out.push(ToSerializerEvent::Broadcast(player.update_meta_msg()));
+                }
+            },
+            FreePart::Decaying(_, _) | FreePart::PlaceholderLol => (),
+        }
+        out.extend(simulation.delete_parts_recursive(handle).into_iter().map(ToSerializerEvent::Broadcast));
+    }
+}
+
+fn shutdown_notice(seconds: u16) -> ToSerializerEvent {
+    let msg = if seconds == 1 { String::from("Server restarting in 1 second") } else { format!("Server restarting in {} seconds", seconds) };
+    ToSerializerEvent::Broadcast(ToClientMsg::ChatMessage { username: "Server".to_owned(), msg, color: "#FF0000".to_owned() })
+}
+
+/// Stops new connections and starts counting down; the ticker calls graceful_shutdown when it runs out.
+/// Asking again only ever shortens the countdown.
+fn begin_shutdown(seconds: u16, countdown: &mut Option<u16>, outbound_events: &mut Vec<ToSerializerEvent>) {
+    SHUTTING_DOWN.store(true, AtomicOrdering::Release);
+    let ticks = config().ticks(seconds);
+    if countdown.map(|current| ticks < current).unwrap_or(true) {
+        *countdown = Some(ticks);
+        outbound_events.push(shutdown_notice(seconds));
+    }
+}
+
+/// Saves the world, beams out every player and waits (bounded) for the API to confirm, then tells clients the server is restarting.
+/// The game loop is blocked for the duration, which is what freezes the simulation.
+async fn graceful_shutdown(players: &BTreeMap<u16, PlayerMeta>, world: &world::World, outbox: &Option<Arc<outbox::Outbox>>, to_serializer: &Sender<Vec<ToSerializerEvent>>, snapshot: snapshot::WorldSnapshot) -> ! {
+    SHUTTING_DOWN.store(true, AtomicOrdering::Release);
+    info!("game"; "Shutting down");
+    if !config().snapshot_file.is_empty() {
+        match snapshot::save(&config().snapshot_file, &snapshot) {
+            Ok(()) => info!("game"; "Saved the world to {}", config().snapshot_file),
+            Err(err) => error!("game"; "{}", err),
+        }
+    }
+    to_serializer.send(vec![ ToSerializerEvent::Broadcast(ToClientMsg::ChatMessage { username: "Server".to_owned(), msg: "Server restarting now".to_owned(), color: "#FF0000".to_owned() }) ]).await;
+
+    if let Some(outbox) = outbox {
+        let beamouts = players.values().filter_map(|player| {
+            let beamout_token = player.beamout_token.as_ref()?;
+            let beamout_layout = world.get_part(player.core).unwrap().deflate(world);
+            Some(outbox.submit(&player.name, beamout_token.to_owned(), beamout_layout, false))
+        }).collect::<Vec<_>>();
+        let total = beamouts.len();
+        let timeout = std::time::Duration::from_secs(config().shutdown_beamout_timeout_seconds);
+        match async_std::future::timeout(timeout, futures::future::join_all(beamouts)).await {
+            Ok(results) => info!("beamout"; "{} of {} beamouts confirmed", results.into_iter().filter(|ok| *ok).count(), total),
+            Err(_) => warn!("beamout"; "Gave up waiting on beamouts after {:?}, the rest stay in the outbox for next startup", timeout),
+        }
+    }
+
+    let (ack, acked) = channel(1);
+    to_serializer.send(vec![ ToSerializerEvent::Shutdown(ack) ]).await;
+    let _ = async_std::future::timeout(std::time::Duration::from_secs(5), acked.recv()).await;
+    //Give the writer tasks a moment to flush the close frames
+    async_std::task::sleep(std::time::Duration::from_millis(500)).await;
+    info!("game"; "Shutdown complete");
+    std::process::exit(0);
+}
+
+/// Takes a player out of the game for good, leaving their ship behind to decay
+fn player_quit(id: u16, session_shared: &SessionShared, simulation: &mut world::Simulation, players: &mut BTreeMap<u16, PlayerMeta>, free_parts: &mut BTreeMap<u16, FreePart>, out: &mut Vec<ToSerializerEvent>) {
+    session_shared.slots.release(id);
+    session_shared.names.release(id);
+    out.push(ToSerializerEvent::Broadcast(codec::ToClientMsg::RemovePlayer{ id }));
+    if let Some(mut player) = players.remove(&id) {
+        info!("game", id = id; "Player {} quit", player.name);
+        let mut affected_parts = BTreeSet::new(); //Why is this a b tree set
+        simulation.world.recursive_detach_all(player.core, &mut Some(&mut player), &mut simulation.joints, &mut affected_parts);
+        for handle in affected_parts {
+            let part = simulation.world.get_part(handle).unwrap();
+            out.push(ToSerializerEvent::Broadcast(part.update_meta_msg()));
+            free_parts.insert(part.id(), FreePart::Decaying(handle, config().ticks(config().part_decay_seconds)));
+        }
+        out.extend(simulation.delete_parts_recursive(player.core).into_iter().map(ToSerializerEvent::Broadcast));
+        if let Some((part_id, constraint_id, _, _)) = player.grabbed_part {
+            if let Some(part) = free_parts.get_mut(&part_id) {
+                part.become_decaying();
+                simulation.release_constraint(constraint_id);
+            }
+        }
+        out.push(ToSerializerEvent::Broadcast(ToClientMsg::ChatMessage{ username: String::from("Server"), msg: player.name.clone() + " left the game", color: String::from("#e270ff") }));
+    }
+}
+
+/// Keeps a disconnected player in the world for `suspend_seconds`, after which they quit unless they reconnected with `session`
+async fn suspend_player(id: u16, session: String, role: roles::Role, suspended_players: &session::SuspendedPlayers, to_game: &Sender<session::ToGameEvent>) {
+    let suspended_player = Arc::new(session::SuspendedPlayer { id, session, role });
+    let my_suspended_player = Arc::downgrade(&suspended_player);
+    suspended_players.lock().await.push_back(suspended_player);
+    let my_suspended_players = suspended_players.clone();
+    let my_to_game = to_game.clone();
+    async_std::task::spawn(async move {
+        async_std::task::sleep(std::time::Duration::from_secs(config().suspend_seconds)).await;
+        let mut my_suspended_players = my_suspended_players.lock().await;
+        if let Some(my_suspended_player) = my_suspended_player.upgrade() {
+            for i in 0..my_suspended_players.len() {
+                if Arc::ptr_eq(&my_suspended_player, &my_suspended_players[i]) {
+                    my_suspended_players.remove(i);
+                    break;
+                }
+            }
+            my_to_game.send(session::ToGameEvent::PlayerQuit { id }).await;
+        }
+        drop(my_suspended_players);
+    });
+}
+
+async fn emergency_stop(players: &BTreeMap<u16, PlayerMeta>, world: &world::World, outbox: &Option<Arc<outbox::Outbox>>) {
+    unsafe { EMERGENCY_STOP.store(true, AtomicOrdering::Release) };
+    error!("game"; "EMERGENCY STOP");
+    if let Some(outbox) = outbox {
+        let mut beamouts = Vec::new();
+        for player in players.values() {
+            let core = world.get_part(player.core).unwrap();
+            let beamout_layout = core.deflate(world);
+            if let Some(beamout_token) = &player.beamout_token {
+                info!("beamout"; "Beaming out {}", player.name);
+                beamouts.push(outbox.submit(&player.name, beamout_token.to_owned(), beamout_layout, false));
+            } else {
+                debug!("beamout"; "Player {} has no beamout token", player.name);
+            }
+        }
+        //Everything is journaled by now, so whatever doesn't make it in time gets replayed next startup
+        let _ = async_std::future::timeout(std::time::Duration::from_secs(5), futures::future::join_all(beamouts)).await;
+    }
+    std::process::exit(1);
+}
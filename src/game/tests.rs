@@ -0,0 +1,375 @@
+use nalgebra::Vector2;
+use nalgebra::geometry::Isometry2;
+use crate::world::PartVisitHandleMut;
+use crate::world::parts::{PartKind, RecursivePartDescription};
+use crate::world::planets::load_definitions;
+use crate::codec::{ToClientMsg, ToServerMsg};
+use crate::FreePart;
+use crate::config::config;
+use crate::recording::{Recorder, Replay};
+use super::harness::Harness;
+
+fn small_ship() -> RecursivePartDescription {
+    RecursivePartDescription { kind: PartKind::Core, attachments: vec![Some(PartKind::Hub.into()), None, Some(PartKind::Cargo.into())] }
+}
+
+/// How far the player's core is from the middle of the spawn planet, and how fast it's moving away from it
+fn relative_to_spawn_planet(harness: &Harness, id: u16) -> (f32, f32) {
+    let simulation = &harness.game.simulation;
+    let planet = simulation.planets.spawn_planet();
+    let planet_position = simulation.world.get_rigid(planet.body).unwrap().position().translation.vector;
+    let core = simulation.world.get_rigid(harness.game.players[&id].core).unwrap();
+    let offset = core.position().translation.vector - planet_position;
+    let velocity = core.velocity().linear - simulation.planets.velocity_of(planet);
+    (offset.norm(), velocity.dot(&offset.normalize()))
+}
+
+/// Moves the whole ship to `height` above the top of the spawn planet, coming straight down at it at `speed`
+fn drop_onto_spawn_planet(harness: &mut Harness, id: u16, height: f32, speed: f32) {
+    let simulation = &mut harness.game.simulation;
+    let planet = simulation.planets.spawn_planet();
+    let (center, velocity) = simulation.planets.state_of(planet);
+    let core = harness.game.players[&id].core;
+    let offset = center + Vector2::new(0.0, planet.radius + height) - simulation.world.get_rigid(core).unwrap().position().translation.vector;
+    simulation.world.recurse_part_mut(core, Default::default(), &mut |mut handle: PartVisitHandleMut| {
+        let body = handle.body_mut();
+        let position = body.position();
+        body.set_position(Isometry2::new(position.translation.vector + offset, position.rotation.angle()));
+        body.set_linear_velocity(velocity + Vector2::new(0.0, -speed));
+        body.set_angular_velocity(0.0);
+    });
+}
+
+#[test]
+fn new_players_are_told_about_themselves_and_announced() {
+    let mut harness = Harness::new();
+    let id = harness.spawn_player("alice", small_ship());
+    let sent = harness.take_sent();
+    assert!(sent.iter().any(|(to, msg)| *to == Some(id) && matches!(msg, ToClientMsg::HandshakeAccepted { id: accepted, .. } if *accepted == id)));
+    assert!(sent.iter().any(|(to, msg)| to.is_none() && matches!(msg, ToClientMsg::AddPlayer { name, .. } if name == "alice")));
+    assert_eq!(sent.iter().filter(|(to, msg)| to.is_none() && matches!(msg, ToClientMsg::AddPart { .. })).count(), 3);
+    assert_eq!(harness.part_count(id), 3);
+
+    let radius = harness.game.simulation.planets.spawn_planet().radius;
+    let (distance, _) = relative_to_spawn_planet(&harness, id);
+    assert!(distance > radius && distance < radius * 1.5 + 2.0, "spawned {} away from a planet {} across", distance, radius);
+}
+
+#[test]
+fn the_world_is_sent_with_display_names() {
+    let mut harness = Harness::new();
+    let id = harness.spawn_player("alice", small_ship());
+    harness.take_sent();
+    harness.send_world(id);
+    let sent = harness.take_sent();
+    assert!(sent.iter().any(|(to, msg)| *to == Some(id) && matches!(msg, ToClientMsg::AddCelestialObject { name, display_name, .. } if name == "earth" && display_name == "Earth")));
+    assert!(sent.iter().any(|(to, msg)| *to == Some(id) && matches!(msg, ToClientMsg::CelestialObjectOrbit { .. })));
+}
+
+#[test]
+fn thrusting_pushes_the_ship_away() {
+    let mut harness = Harness::new();
+    let idle = harness.spawn_player("idle", PartKind::Core.into());
+    let thrusting = harness.spawn_player("thrusting", PartKind::Core.into());
+    harness.input(thrusting, ToServerMsg::SetThrusters { forward: true, backward: false, clockwise: false, counter_clockwise: false });
+    harness.step(40);
+
+    let (_, idle_speed) = relative_to_spawn_planet(&harness, idle);
+    let (_, thrusting_speed) = relative_to_spawn_planet(&harness, thrusting);
+    assert!(thrusting_speed > idle_speed + 1.0, "thrusting at {} against idling at {}", thrusting_speed, idle_speed);
+    assert!(harness.game.players[&thrusting].power < harness.game.players[&idle].power);
+}
+
+#[test]
+fn grabbed_parts_come_off_the_ship_and_decay_once_let_go() {
+    let mut harness = Harness::new();
+    let id = harness.spawn_player("alice", small_ship());
+    let simulation = &harness.game.simulation;
+    let hub = simulation.world.get_part(harness.game.players[&id].core).unwrap().attachments()[0].as_ref().map(|hub| simulation.world.get_part(**hub).unwrap().id()).unwrap();
+
+    harness.input(id, ToServerMsg::CommitGrab { grabbed_id: hub, x: 0.0, y: 5.0 });
+    assert_eq!(harness.part_count(id), 2);
+    assert!(matches!(harness.game.free_parts.get(&hub), Some(FreePart::Grabbed(_))));
+
+    harness.step(5);
+    harness.input(id, ToServerMsg::ReleaseGrab);
+    assert!(matches!(harness.game.free_parts.get(&hub), Some(FreePart::Decaying(_, _))));
+    assert!(harness.game.players[&id].grabbed_part.is_none());
+}
+
+#[test]
+fn quitting_leaves_the_ship_behind_in_pieces() {
+    let mut harness = Harness::new();
+    let id = harness.spawn_player("alice", small_ship());
+    harness.step(2);
+    harness.take_sent();
+    harness.quit(id);
+
+    assert!(harness.game.players.is_empty());
+    assert_eq!(harness.game.free_parts.len(), 2);
+    let sent = harness.take_sent();
+    assert!(sent.iter().any(|(_, msg)| matches!(msg, ToClientMsg::RemovePlayer { id: removed } if *removed == id)));
+    assert!(harness.command("/list").starts_with("There are 0 players online"));
+}
+
+#[test]
+fn kicking_a_suspended_player_removes_them_straight_away() {
+    let mut harness = Harness::new();
+    let online = harness.spawn_player("alice", PartKind::Core.into());
+    let suspended = harness.spawn_player("bob", small_ship());
+    harness.suspend(suspended);
+    harness.take_sent();
+
+    assert_eq!(harness.command("/kick bob"), "Kicked bob");
+    assert!(!harness.game.players.contains_key(&suspended));
+    assert!(async_std::task::block_on(harness.game.session_shared.suspended_players.lock()).is_empty());
+    assert_eq!(harness.game.free_parts.len(), 2);
+    assert!(harness.take_sent().iter().any(|(_, msg)| matches!(msg, ToClientMsg::RemovePlayer { id } if *id == suspended)));
+
+    //Someone still connected goes once their connection closes
+    assert_eq!(harness.command("/kick alice"), "Kicked alice");
+    assert!(harness.game.players.contains_key(&online));
+    assert_eq!(harness.command("/kick carol"), "There is no player named carol");
+}
+
+#[test]
+fn teleport_without_coordinates_is_a_usage_error() {
+    let mut harness = Harness::new();
+    let id = harness.spawn_player("alice", PartKind::Core.into());
+    for command in &["/teleport", "/teleport 5", "/teleport alice", "/teleport alice 5 north"] {
+        assert_eq!(harness.command(command), "Usage: /teleport [name] <x> <y>");
+    }
+    assert_eq!(harness.command("/teleport alice 300 200"), "Teleported alice to 300 200");
+    let (x, y) = harness.core_position(id);
+    assert!((x - 300.0).abs() < 0.01 && (y - 200.0).abs() < 0.01);
+}
+
+#[test]
+fn shutdowns_too_long_to_count_are_refused() {
+    let mut harness = Harness::new();
+    assert_eq!(harness.command("/shutdown 4000"), "That's too long, the most is 3276 seconds");
+    assert_eq!(harness.command("/shutdown soon"), "Usage: /shutdown [seconds]");
+    assert!(harness.game.shutdown_countdown.is_none());
+}
+
+#[test]
+fn replaying_a_recording_matches_it_tick_for_tick() {
+    let dir = std::env::temp_dir().join(format!("glap-recording-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut harness = Harness::new();
+    let (recorder, path) = Recorder::create(dir.to_str().unwrap(), 1, config(), &load_definitions(None).unwrap(), None).unwrap();
+    harness.recorder = Some(recorder);
+    let alice = harness.spawn_player("alice", small_ship());
+    let bob = harness.spawn_player("bob", gunship());
+    harness.step(10);
+    harness.input(alice, ToServerMsg::SetThrusters { forward: true, backward: false, clockwise: true, counter_clockwise: false });
+    harness.input(bob, ToServerMsg::SetFiring { firing: true });
+    harness.step(30);
+    harness.command("/teleport bob 300 300");
+    harness.input(alice, ToServerMsg::SetThrusters { forward: false, backward: false, clockwise: false, counter_clockwise: false });
+    harness.step(20);
+    harness.quit(bob);
+    harness.step(20);
+    harness.recorder = None;
+
+    let mut replay = Replay::open(&path).unwrap();
+    let mut replayed = Harness::with_planets(&replay.header.planets);
+    replayed.replay(&mut replay);
+    assert_eq!(replay.checked(), (4, 0));
+    assert_eq!(replayed.game.simulation.checksum(), harness.game.simulation.checksum());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn air_slows_a_fall() {
+    //Falling at the spawn planet from just above its surface, for half a second
+    let fall = |harness: &mut Harness| {
+        let id = harness.spawn_player("alice", PartKind::Core.into());
+        drop_onto_spawn_planet(harness, id, 4.0, 6.0);
+        harness.step(10);
+        -relative_to_spawn_planet(harness, id).1
+    };
+    let with_air = fall(&mut Harness::new());
+    let mut airless = load_definitions(None).unwrap();
+    for definition in &mut airless { definition.atmosphere = None; }
+    let without_air = fall(&mut Harness::with_planets(&airless));
+    assert!(without_air > 6.0, "fell at {} without air", without_air);
+    assert!(with_air < 6.0 && with_air < without_air - 0.5, "fell at {} through air and {} without", with_air, without_air);
+}
+
+#[test]
+fn crashing_hurts_and_landing_gently_doesnt() {
+    let mut harness = Harness::new();
+    let gentle = harness.spawn_player("gentle", PartKind::Core.into());
+    drop_onto_spawn_planet(&mut harness, gentle, 0.6, 1.0);
+    harness.step(10);
+    assert_eq!(harness.core(gentle).health(), PartKind::Core.max_health());
+
+    let mut harness = Harness::new();
+    let crashing = harness.spawn_player("crashing", PartKind::Core.into());
+    drop_onto_spawn_planet(&mut harness, crashing, 0.6, 20.0);
+    harness.take_sent();
+    harness.step(10);
+    let health = harness.core(crashing).health();
+    assert!(health < PartKind::Core.max_health());
+    assert!(harness.take_sent().iter().any(|(to, msg)| to.is_none() && matches!(msg, ToClientMsg::UpdatePartHealth { health: sent, .. } if *sent == health)));
+}
+
+#[test]
+fn being_yanked_along_by_joints_doesnt_hurt() {
+    let mut harness = Harness::new();
+    //Packed tight, so the cargo on each hub is pressed up against the cargo on the hubs either side
+    let arm = RecursivePartDescription { kind: PartKind::Hub, attachments: vec![None, Some(PartKind::Cargo.into()), None, Some(PartKind::Cargo.into())] };
+    let ship = RecursivePartDescription { kind: PartKind::Core, attachments: vec![Some(arm.clone()), Some(arm.clone()), Some(arm.clone()), Some(arm)] };
+    let id = harness.spawn_player("alice", ship);
+    place_upright(&mut harness, id, open_space());
+    //As if the core alone got knocked; the joints have to drag everything else along with it in a hurry
+    let core = harness.game.players[&id].core;
+    harness.game.simulation.world.get_rigid_mut(core).unwrap().set_linear_velocity(Vector2::new(0.0, 120.0));
+    harness.step(20);
+
+    harness.game.simulation.world.recurse_part(core, Default::default(), &mut |handle| {
+        assert_eq!(handle.health(), handle.kind().max_health(), "{:?} got hurt", handle.kind());
+    });
+    assert_eq!(harness.part_count(id), 13);
+}
+
+#[test]
+fn parts_that_run_out_of_health_are_destroyed() {
+    let mut harness = Harness::new();
+    let id = harness.spawn_player("alice", small_ship());
+    let core = harness.game.players[&id].core;
+    harness.game.simulation.world.recurse_part_mut(core, Default::default(), &mut |mut handle: PartVisitHandleMut| {
+        if handle.kind() != PartKind::Core { handle.set_health(1); }
+    });
+    drop_onto_spawn_planet(&mut harness, id, 2.0, 15.0);
+    harness.take_sent();
+    harness.step(20);
+
+    //Whatever hit the ground first is gone, and the rest of the ship is still flying
+    assert!(harness.part_count(id) < 3);
+    assert!(harness.take_sent().iter().any(|(_, msg)| matches!(msg, ToClientMsg::RemovePart { .. })));
+
+    //Losing the core loses the whole ship
+    let mut harness = Harness::new();
+    let id = harness.spawn_player("alice", PartKind::Core.into());
+    let core = harness.game.players[&id].core;
+    let core_id = harness.core(id).id();
+    harness.game.simulation.world.get_part_mut(core).unwrap().set_health(1);
+    drop_onto_spawn_planet(&mut harness, id, 2.0, 15.0);
+    harness.take_sent();
+    harness.step(20);
+    assert!(harness.game.players.is_empty());
+    let sent = harness.take_sent();
+    assert!(sent.iter().any(|(_, msg)| matches!(msg, ToClientMsg::RemovePlayer { id: removed } if *removed == id)));
+    assert!(sent.iter().any(|(_, msg)| matches!(msg, ToClientMsg::RemovePart { id: removed } if *removed == core_id)));
+}
+
+#[test]
+fn resting_on_a_repairing_planet_heals_the_ship() {
+    let mut harness = Harness::new();
+    let id = harness.spawn_player("alice", PartKind::Core.into());
+    let core = harness.game.players[&id].core;
+    harness.game.simulation.world.get_part_mut(core).unwrap().set_health(50);
+    drop_onto_spawn_planet(&mut harness, id, 0.6, 0.0);
+    //Resting contact comes and goes, and it only counts towards repairs while it's there
+    harness.step(300);
+    assert!(harness.core(id).health() >= 50 + crate::config::config().repair_per_second);
+}
+
+fn gunship() -> RecursivePartDescription {
+    RecursivePartDescription { kind: PartKind::Core, attachments: vec![Some(PartKind::Cannon.into())] }
+}
+
+/// Moves the whole ship so its core sits still at `at`, turned so that anything on its top slot points straight up
+fn place_upright(harness: &mut Harness, id: u16, at: Vector2<f32>) {
+    let core = harness.game.players[&id].core;
+    let world = &mut harness.game.simulation.world;
+    let core_position = *world.get_rigid(core).unwrap().position();
+    world.recurse_part_mut(core, Default::default(), &mut |mut handle: PartVisitHandleMut| {
+        let body = handle.body_mut();
+        let position = *body.position();
+        let offset = core_position.rotation.inverse() * (position.translation.vector - core_position.translation.vector);
+        body.set_position(Isometry2::new(at + offset, position.rotation.angle() - core_position.rotation.angle()));
+        body.set_linear_velocity(Vector2::zeros());
+        body.set_angular_velocity(0.0);
+    });
+}
+
+/// Puts `shooter` just below `target` with its cannon aimed up at it, fires once and gives the shot time to land
+fn shoot_at(harness: &mut Harness, shooter: u16, target: u16, at: Vector2<f32>) {
+    place_upright(harness, target, at + Vector2::new(0.0, 8.0));
+    place_upright(harness, shooter, at);
+    harness.input(shooter, ToServerMsg::SetFiring { firing: true });
+    harness.step(1);
+    harness.input(shooter, ToServerMsg::SetFiring { firing: false });
+    harness.step(10);
+}
+
+/// Far from every planet, where nothing gets in the way
+fn open_space() -> Vector2<f32> { Vector2::new(300.0, 300.0) }
+
+#[test]
+fn cannons_fire_shots_that_fizzle_out() {
+    let mut harness = Harness::new();
+    let id = harness.spawn_player("alice", gunship());
+    place_upright(&mut harness, id, open_space());
+    let power = harness.game.players[&id].power;
+    harness.take_sent();
+    harness.input(id, ToServerMsg::SetFiring { firing: true });
+    harness.step(1);
+    harness.input(id, ToServerMsg::SetFiring { firing: false });
+
+    let sent = harness.take_sent();
+    let (shot, y, vel_y) = sent.iter().find_map(|(to, msg)| match msg {
+        ToClientMsg::AddProjectile { id, y, vel_y, .. } if to.is_none() => Some((*id, *y, *vel_y)),
+        _ => None,
+    }).expect("The cannon didn't fire");
+    assert!(y > open_space().y + 1.0 && vel_y > 30.0, "fired from {} at {}", y, vel_y);
+    assert_eq!(harness.game.players[&id].power, power - PartKind::Cannon.weapon().unwrap().power_cost);
+
+    harness.step(40);
+    assert!(harness.take_sent().iter().any(|(_, msg)| matches!(msg, ToClientMsg::RemoveProjectile { id } if *id == shot)));
+    assert_eq!(harness.game.simulation.world.iter_projectiles().count(), 0);
+}
+
+#[test]
+fn shots_only_hurt_with_pvp_on_and_away_from_safe_zones() {
+    let mut harness = Harness::new();
+    let shooter = harness.spawn_player("shooter", gunship());
+    let target = harness.spawn_player("target", PartKind::Core.into());
+    let full = PartKind::Core.max_health();
+    let damage = PartKind::Cannon.weapon().unwrap().damage;
+
+    shoot_at(&mut harness, shooter, target, open_space());
+    //Gone well before its range is up, so it hit
+    assert!(harness.take_sent().iter().any(|(_, msg)| matches!(msg, ToClientMsg::RemoveProjectile { .. })));
+    assert_eq!(harness.core(target).health(), full);
+
+    assert_eq!(harness.command("/pvp on"), "PvP is now on");
+    shoot_at(&mut harness, shooter, target, open_space());
+    assert_eq!(harness.core(target).health(), full - damage);
+    assert!(harness.take_sent().iter().any(|(_, msg)| matches!(msg, ToClientMsg::UpdatePartHealth { health, .. } if *health == full - damage)));
+
+    let earth = harness.game.simulation.planets.spawn_planet();
+    let above_earth = Vector2::new(earth.position.0, earth.position.1 + earth.radius + 20.0);
+    shoot_at(&mut harness, shooter, target, above_earth);
+    assert_eq!(harness.core(target).health(), full - damage);
+}
+
+#[test]
+fn shooting_down_a_ship_credits_the_shooter() {
+    let mut harness = Harness::new();
+    harness.command("/pvp on");
+    let shooter = harness.spawn_player("shooter", gunship());
+    let target = harness.spawn_player("target", PartKind::Core.into());
+    let core = harness.game.players[&target].core;
+    harness.game.simulation.world.get_part_mut(core).unwrap().set_health(1);
+    harness.take_sent();
+    shoot_at(&mut harness, shooter, target, open_space());
+
+    assert!(!harness.game.players.contains_key(&target));
+    let sent = harness.take_sent();
+    assert!(sent.iter().any(|(to, msg)| to.is_none() && matches!(msg, ToClientMsg::KillFeed { victim, killer: Some(killer) } if victim == "target" && killer == "shooter")));
+    assert!(sent.iter().any(|(_, msg)| matches!(msg, ToClientMsg::ChatMessage { msg, .. } if msg == "target was destroyed by shooter")));
+}
@@ -0,0 +1,88 @@
+use std::sync::atomic::{AtomicU8, AtomicBool, Ordering};
+use std::fmt::Write;
+use std::io::Write as IoWrite;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level { Error = 1, Warn, Info, Debug, Trace }
+impl Level {
+    pub fn name(&self) -> &'static str {
+        match self { Level::Error => "error", Level::Warn => "warn", Level::Info => "info", Level::Debug => "debug", Level::Trace => "trace" }
+    }
+    pub fn parse(name: &str) -> Option<Level> {
+        match name.to_ascii_lowercase().as_str() {
+            "error" => Some(Level::Error), "warn" | "warning" => Some(Level::Warn), "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug), "trace" => Some(Level::Trace),
+            _ => None
+        }
+    }
+}
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Sets the most verbose level that gets written (error, warn, info, debug, trace) and whether lines are text or json
+pub fn init(level: &str, format: &str) {
+    match Level::parse(level) {
+        Some(level) => MAX_LEVEL.store(level as u8, Ordering::Relaxed),
+        None => emit(Level::Warn, "logging", &[], format_args!("Unknown log level {:?}, keeping info", level)),
+    }
+    JSON.store(format.eq_ignore_ascii_case("json"), Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool { level as u8 <= MAX_LEVEL.load(Ordering::Relaxed) }
+
+/// Writes one log line to stderr; stdout is left to the operator console.
+/// Use the `error!`/`warn!`/`info!`/`debug!`/`trace!` macros rather than calling this directly.
+pub fn emit(level: Level, subsystem: &str, fields: &[(&str, String)], msg: std::fmt::Arguments) {
+    let timestamp = timestamp();
+    let mut line = String::new();
+    if JSON.load(Ordering::Relaxed) {
+        let mut obj = serde_json::Map::new();
+        obj.insert("ts".to_owned(), timestamp.into());
+        obj.insert("level".to_owned(), level.name().into());
+        obj.insert("subsystem".to_owned(), subsystem.into());
+        for (key, value) in fields { obj.insert((*key).to_owned(), value.clone().into()); }
+        obj.insert("msg".to_owned(), msg.to_string().into());
+        line = serde_json::Value::Object(obj).to_string();
+    } else {
+        let _ = write!(line, "{} {:5} [{}]", timestamp, level.name().to_ascii_uppercase(), subsystem);
+        for (key, value) in fields { let _ = write!(line, " {}={}", key, value); }
+        let _ = write!(line, " {}", msg);
+    }
+    line.push('\n');
+    let _ = std::io::stderr().write_all(line.as_bytes());
+}
+
+/// RFC 3339 UTC timestamp with millisecond precision
+fn timestamp() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = now.as_secs() as i64;
+    let (days, secs_of_day) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    //Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", year, month, day,
+        secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60, now.subsec_millis())
+}
+
+/// `log_event!(Level::Info, "subsystem", key = value, ...; "format {}", args)`
+macro_rules! log_event {
+    ($level:expr, $subsystem:expr $(, $key:ident = $value:expr)* ; $($arg:tt)+) => {
+        if $crate::logging::enabled($level) {
+            $crate::logging::emit($level, $subsystem, &[$((stringify!($key), format!("{}", $value))),*], format_args!($($arg)+));
+        }
+    };
+}
+macro_rules! error { ($($arg:tt)+) => { log_event!($crate::logging::Level::Error, $($arg)+) }; }
+macro_rules! warn { ($($arg:tt)+) => { log_event!($crate::logging::Level::Warn, $($arg)+) }; }
+macro_rules! info { ($($arg:tt)+) => { log_event!($crate::logging::Level::Info, $($arg)+) }; }
+macro_rules! debug { ($($arg:tt)+) => { log_event!($crate::logging::Level::Debug, $($arg)+) }; }
+macro_rules! trace { ($($arg:tt)+) => { log_event!($crate::logging::Level::Trace, $($arg)+) }; }
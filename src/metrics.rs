@@ -0,0 +1,151 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::fmt::Write;
+use std::time::Duration;
+use async_std::prelude::*;
+use async_std::net::{TcpListener, TcpStream};
+use crate::codec::ToClientMsg;
+use crate::session::SuspendedPlayers;
+
+/// How many of the most recent ticks the tick duration percentiles cover
+const TICK_HISTORY: usize = 1200;
+
+lazy_static! {
+    pub static ref METRICS: Metrics = Metrics::default();
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    pub connected_players: AtomicU64,
+    pub queued_players: AtomicU64,
+    pub free_parts: AtomicU64,
+    pub earth_cargos: AtomicU64,
+    pub game_queue_depth: AtomicU64,
+    pub serializer_queue_depth: AtomicU64,
+    pub writer_queue_depth_max: AtomicU64,
+    pub outbox_pending: AtomicU64,
+    pub outbox_failed: AtomicU64,
+    pub beamin_success: AtomicU64,
+    pub beamin_failure: AtomicU64,
+    pub beamout_success: AtomicU64,
+    pub beamout_failure: AtomicU64,
+    ticks: Mutex<TickStats>,
+    sent: Mutex<BTreeMap<&'static str, (u64, u64)>>,
+    dropped_connections: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+#[derive(Default)]
+struct TickStats {
+    recent: VecDeque<f64>,
+    count: u64,
+    sum: f64,
+}
+
+impl Metrics {
+    pub fn set(gauge: &AtomicU64, value: usize) { gauge.store(value as u64, Ordering::Relaxed); }
+    pub fn increment(counter: &AtomicU64) { counter.fetch_add(1, Ordering::Relaxed); }
+
+    pub fn record_tick(&self, duration: Duration) {
+        let mut ticks = self.ticks.lock().unwrap();
+        if ticks.recent.len() >= TICK_HISTORY { ticks.recent.pop_front(); }
+        ticks.recent.push_back(duration.as_secs_f64());
+        ticks.count += 1;
+        ticks.sum += duration.as_secs_f64();
+    }
+    /// Counts a message that was serialized once and queued for `recipients` clients
+    pub fn record_sent(&self, msg: &ToClientMsg, bytes: usize, recipients: usize) {
+        if recipients == 0 { return };
+        let mut sent = self.sent.lock().unwrap();
+        let entry = sent.entry(msg.name()).or_insert((0, 0));
+        entry.0 += recipients as u64;
+        entry.1 += (bytes * recipients) as u64;
+    }
+    pub fn dropped_connection(&self, reason: &'static str) {
+        *self.dropped_connections.lock().unwrap().entry(reason).or_insert(0) += 1;
+    }
+
+    pub fn render(&self, suspended_players: usize) -> String {
+        let mut out = String::new();
+        let gauges: [(&str, &str, &AtomicU64); 9] = [
+            ("glap_connected_players", "Players currently in the game", &self.connected_players),
+            ("glap_queued_players", "Connections waiting for a player slot", &self.queued_players),
+            ("glap_free_parts", "Parts not attached to any player", &self.free_parts),
+            ("glap_earth_cargos", "Cargo parts waiting around earth", &self.earth_cargos),
+            ("glap_game_queue_depth", "Events waiting for the game task", &self.game_queue_depth),
+            ("glap_serializer_queue_depth", "Event batches waiting for the serializer task", &self.serializer_queue_depth),
+            ("glap_writer_queue_depth_max", "Deepest outbound queue of any single connection", &self.writer_queue_depth_max),
+            ("glap_outbox_pending", "Beamouts journaled but not yet confirmed by the API", &self.outbox_pending),
+            ("glap_outbox_failed", "Beamouts the API refused, waiting for an admin", &self.outbox_failed),
+        ];
+        for (name, help, gauge) in gauges.iter() {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge\n{} {}", name, help, name, name, gauge.load(Ordering::Relaxed));
+        }
+        let _ = writeln!(out, "# HELP glap_suspended_players Disconnected players waiting to reconnect\n# TYPE glap_suspended_players gauge\nglap_suspended_players {}", suspended_players);
+
+        {
+            let ticks = self.ticks.lock().unwrap();
+            let mut sorted: Vec<f64> = ticks.recent.iter().copied().collect();
+            sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let _ = writeln!(out, "# HELP glap_tick_duration_seconds Time spent simulating one tick\n# TYPE glap_tick_duration_seconds summary");
+            for quantile in [0.5, 0.9, 0.99].iter() {
+                let value = if sorted.is_empty() { 0.0 } else { sorted[((sorted.len() - 1) as f64 * quantile).round() as usize] };
+                let _ = writeln!(out, "glap_tick_duration_seconds{{quantile=\"{}\"}} {}", quantile, value);
+            }
+            let _ = writeln!(out, "glap_tick_duration_seconds_sum {}\nglap_tick_duration_seconds_count {}", ticks.sum, ticks.count);
+        }
+
+        let _ = writeln!(out, "# HELP glap_beamin_total Beamin requests by result\n# TYPE glap_beamin_total counter");
+        let _ = writeln!(out, "glap_beamin_total{{result=\"success\"}} {}\nglap_beamin_total{{result=\"failure\"}} {}", self.beamin_success.load(Ordering::Relaxed), self.beamin_failure.load(Ordering::Relaxed));
+        let _ = writeln!(out, "# HELP glap_beamout_total Beamout requests by result\n# TYPE glap_beamout_total counter");
+        let _ = writeln!(out, "glap_beamout_total{{result=\"success\"}} {}\nglap_beamout_total{{result=\"failure\"}} {}", self.beamout_success.load(Ordering::Relaxed), self.beamout_failure.load(Ordering::Relaxed));
+
+        {
+            let sent = self.sent.lock().unwrap();
+            let _ = writeln!(out, "# HELP glap_messages_sent_total Messages queued for clients by type\n# TYPE glap_messages_sent_total counter");
+            for (kind, (messages, _bytes)) in sent.iter() { let _ = writeln!(out, "glap_messages_sent_total{{type=\"{}\"}} {}", kind, messages); }
+            let _ = writeln!(out, "# HELP glap_bytes_sent_total Bytes queued for clients by message type\n# TYPE glap_bytes_sent_total counter");
+            for (kind, (_messages, bytes)) in sent.iter() { let _ = writeln!(out, "glap_bytes_sent_total{{type=\"{}\"}} {}", kind, bytes); }
+        }
+
+        let dropped = self.dropped_connections.lock().unwrap();
+        let _ = writeln!(out, "# HELP glap_dropped_connections_total Connections that ended, by reason\n# TYPE glap_dropped_connections_total counter");
+        for (reason, count) in dropped.iter() { let _ = writeln!(out, "glap_dropped_connections_total{{reason=\"{}\"}} {}", reason, count); }
+        out
+    }
+}
+
+pub async fn metrics_server(addr: String, suspended_players: SuspendedPlayers) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(err) => { error!("metrics"; "Failed to bind metrics listener to {}: {}", addr, err); return; }
+    };
+    info!("metrics"; "Metrics listening on {}", addr);
+    let mut incoming = listener.incoming();
+    while let Some(Ok(stream)) = incoming.next().await {
+        let suspended_players = suspended_players.clone();
+        async_std::task::spawn(async move {
+            let _ = serve_scrape(stream, suspended_players).await;
+        });
+    }
+}
+
+async fn serve_scrape(mut stream: TcpStream, suspended_players: SuspendedPlayers) -> std::io::Result<()> {
+    //Only the request line matters, but read the whole header so the client doesn't see a reset
+    let mut request = Vec::new();
+    let mut buf = [0u8; 512];
+    while !request.ends_with(b"\r\n\r\n") && request.len() < 8192 {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 { break };
+        request.extend_from_slice(&buf[..read]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let response = if request.starts_with("GET /metrics ") || request.starts_with("GET / ") {
+        let body = METRICS.render(suspended_players.lock().await.len());
+        format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+    } else {
+        String::from("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await
+}
@@ -0,0 +1,239 @@
+//! A stand-in for the beamin/beamout web API, for tests and for running a server without the real backend.
+//! It speaks the same contract `store::HttpStore` expects:
+//! - `GET /ping` answers `PONG`
+//! - `GET /session/{session}/beamin` answers a `BeaminResponse` as JSON, or 404 for an unknown session
+//! - `POST /user/{beamout_token}/beamout` takes a layout as JSON and remembers it for the next beamin,
+//!   as the autosave if it has an `autosave: true` header. The `saved-at` header becomes the beamin's `saved_at`,
+//!   and a save older than the one it would replace is accepted but ignored, like `store::FileStore` does
+//! - every request but `/ping` must be signed with the shared secret (see `signing`), and each nonce is only accepted once
+//!
+//! Only depends on async-std, serde_json and `signing` so `src/bin/mock_api.rs` can include it as-is.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use async_std::task::JoinHandle;
+use serde_json::Value;
+use crate::signing;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Endpoint { Ping, Beamin, Beamout }
+
+#[derive(Clone, Debug)]
+pub struct MockUser {
+    pub beamout_token: String,
+    pub layout: Option<Value>,
+    pub saved_at: Option<u64>,
+    /// Layout and `saved_at`
+    pub autosave: Option<(Value, u64)>,
+    pub is_admin: bool,
+    /// Sent as the `role` field when set, e.g. "moderator"
+    pub role: Option<String>,
+}
+impl MockUser {
+    pub fn new(beamout_token: &str) -> MockUser { MockUser { beamout_token: beamout_token.to_owned(), layout: None, saved_at: None, autosave: None, is_admin: false, role: None } }
+}
+
+struct MockState {
+    secret: String,
+    /// Nonces seen recently, with their timestamps
+    nonces: BTreeMap<String, u64>,
+    /// Keyed by session
+    users: BTreeMap<String, MockUser>,
+    /// Beamins for sessions nobody added get a fresh user with token `token-<session>` instead of a 404
+    auto_register: bool,
+    /// Responses to give instead of the real thing, next one first
+    scripted: BTreeMap<Endpoint, VecDeque<(u16, String)>>,
+    latency: Duration,
+    /// Every request gets a 503
+    down: bool,
+    log_requests: bool,
+    requests: BTreeMap<Endpoint, usize>,
+    /// Every accepted beamout (autosaves included), oldest first
+    beamouts: Vec<(String, Value)>,
+}
+
+pub struct MockApi {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+    task: JoinHandle<()>,
+}
+
+impl MockApi {
+    /// Starts on a free local port
+    pub async fn start(secret: &str) -> std::io::Result<MockApi> {
+        MockApi::bind("127.0.0.1:0", secret).await
+    }
+
+    pub async fn bind(addr: &str, secret: &str) -> std::io::Result<MockApi> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(MockState {
+            secret: secret.to_owned(),
+            nonces: BTreeMap::new(),
+            users: BTreeMap::new(),
+            auto_register: false,
+            scripted: BTreeMap::new(),
+            latency: Duration::from_secs(0),
+            down: false,
+            log_requests: false,
+            requests: BTreeMap::new(),
+            beamouts: Vec::new(),
+        }));
+        let task_state = state.clone();
+        let task = async_std::task::spawn(async move {
+            let mut incoming = listener.incoming();
+            while let Some(Ok(stream)) = incoming.next().await {
+                let state = task_state.clone();
+                async_std::task::spawn(async move { let _ = serve(stream, state).await; });
+            }
+        });
+        Ok(MockApi { addr, state, task })
+    }
+
+    /// What to set `api` to
+    pub fn prefix(&self) -> String { format!("http://{}", self.addr) }
+    pub fn addr(&self) -> SocketAddr { self.addr }
+
+    pub fn add_user(&self, session: &str, user: MockUser) {
+        self.state.lock().unwrap().users.insert(session.to_owned(), user);
+    }
+    pub fn set_auto_register(&self, auto_register: bool) { self.state.lock().unwrap().auto_register = auto_register; }
+    /// The next request to `endpoint` gets this status and body instead of being handled. Queued responses are used in order.
+    pub fn respond_next(&self, endpoint: Endpoint, status: u16, body: &str) {
+        self.state.lock().unwrap().scripted.entry(endpoint).or_default().push_back((status, body.to_owned()));
+    }
+    /// Delay before every response
+    pub fn set_latency(&self, latency: Duration) { self.state.lock().unwrap().latency = latency; }
+    pub fn set_down(&self, down: bool) { self.state.lock().unwrap().down = down; }
+    /// Print each request and its status to stderr
+    pub fn set_log_requests(&self, log_requests: bool) { self.state.lock().unwrap().log_requests = log_requests; }
+
+    /// How many requests `endpoint` has seen, scripted and rejected ones included
+    pub fn requests(&self, endpoint: Endpoint) -> usize { self.state.lock().unwrap().requests.get(&endpoint).copied().unwrap_or(0) }
+    pub fn beamouts(&self) -> Vec<(String, Value)> { self.state.lock().unwrap().beamouts.clone() }
+    /// The layout a session would beam in with
+    pub fn layout(&self, session: &str) -> Option<Value> { self.state.lock().unwrap().users.get(session).and_then(|user| user.layout.clone()) }
+    pub fn autosave(&self, session: &str) -> Option<Value> { self.state.lock().unwrap().users.get(session).and_then(|user| user.autosave.as_ref().map(|(layout, _)| layout.clone())) }
+
+    /// Stops listening; anything connecting afterwards is refused
+    pub async fn stop(self) { self.task.cancel().await; }
+}
+
+struct Request { method: String, path: String, headers: BTreeMap<String, String>, body: Vec<u8> }
+
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Option<Request>> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 1024];
+    let header_end = loop {
+        if let Some(end) = data.windows(4).position(|window| window == b"\r\n\r\n") { break end + 4 };
+        if data.len() > 16384 { return Ok(None) };
+        let read = stream.read(&mut buf).await?;
+        if read == 0 { return Ok(None) };
+        data.extend_from_slice(&buf[..read]);
+    };
+    let head = String::from_utf8_lossy(&data[..header_end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+    let method = request_line.next().unwrap_or("").to_owned();
+    let path = request_line.next().unwrap_or("").to_owned();
+    let headers: BTreeMap<String, String> = lines.filter_map(|line| {
+        let colon = line.find(':')?;
+        Some((line[..colon].trim().to_ascii_lowercase(), line[colon + 1..].trim().to_owned()))
+    }).collect();
+    let length = headers.get("content-length").and_then(|length| length.parse::<usize>().ok()).unwrap_or(0);
+    if headers.get("expect").map(|expect| expect.eq_ignore_ascii_case("100-continue")).unwrap_or(false) {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+    }
+    let mut body = data[header_end..].to_vec();
+    while body.len() < length {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 { break };
+        body.extend_from_slice(&buf[..read]);
+    }
+    Ok(Some(Request { method, path, headers, body }))
+}
+
+async fn serve(mut stream: TcpStream, state: Arc<Mutex<MockState>>) -> std::io::Result<()> {
+    let request = if let Some(request) = read_request(&mut stream).await? { request } else { return Ok(()) };
+    let latency = state.lock().unwrap().latency;
+    if latency > Duration::from_secs(0) { async_std::task::sleep(latency).await; }
+    let (status, body) = respond(&request, &mut state.lock().unwrap());
+    if state.lock().unwrap().log_requests { eprintln!("{} {} -> {}", request.method, request.path, status); }
+    let reason = match status { 200 => "OK", 400 => "Bad Request", 401 => "Unauthorized", 403 => "Forbidden", 404 => "Not Found", 503 => "Service Unavailable", _ => "Scripted" };
+    let response = format!("HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, reason, if body.starts_with('{') { "application/json" } else { "text/plain" }, body.len(), body);
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await
+}
+
+fn check_signature(request: &Request, state: &mut MockState) -> Result<(), String> {
+    let header = |name: &str| request.headers.get(name).ok_or_else(|| format!("Missing {}", name));
+    let timestamp: u64 = header(signing::TIMESTAMP_HEADER)?.parse().map_err(|_| String::from("Bad timestamp"))?;
+    let nonce = header(signing::NONCE_HEADER)?;
+    let signature = header(signing::SIGNATURE_HEADER)?;
+    let now = signing::now();
+    if timestamp + signing::MAX_SKEW_SECONDS < now || timestamp > now + signing::MAX_SKEW_SECONDS { return Err(String::from("Stale timestamp")) };
+    let headers: Vec<(&str, &str)> = request.headers.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+    if !signing::verify(&state.secret, &request.method, &request.path, timestamp, nonce, &headers, &request.body, signature) { return Err(String::from("Bad signature")) };
+    //Anything older than the window would be turned away for its timestamp anyway
+    state.nonces.retain(|_nonce, seen| *seen + signing::MAX_SKEW_SECONDS >= now);
+    if state.nonces.insert(nonce.clone(), timestamp).is_some() { return Err(String::from("Replayed nonce")) };
+    Ok(())
+}
+
+fn respond(request: &Request, state: &mut MockState) -> (u16, String) {
+    let segments: Vec<&str> = request.path.trim_start_matches('/').split('/').collect();
+    let endpoint = match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["ping"]) => Endpoint::Ping,
+        ("GET", ["session", _, "beamin"]) => Endpoint::Beamin,
+        ("POST", ["user", _, "beamout"]) => Endpoint::Beamout,
+        _ => return (404, String::from("Not found")),
+    };
+    *state.requests.entry(endpoint).or_default() += 1;
+    if state.down { return (503, String::from("Down")) };
+    if let Some(scripted) = state.scripted.get_mut(&endpoint).and_then(|scripted| scripted.pop_front()) { return scripted };
+    if endpoint != Endpoint::Ping {
+        if let Err(reason) = check_signature(request, state) { return (401, reason) };
+    }
+
+    match endpoint {
+        Endpoint::Ping => (200, String::from("PONG")),
+        Endpoint::Beamin => {
+            let session = segments[1];
+            if !state.users.contains_key(session) && state.auto_register {
+                state.users.insert(session.to_owned(), MockUser::new(&format!("token-{}", session)));
+            }
+            match state.users.get(session) {
+                Some(user) => {
+                    let mut response = serde_json::json!({ "beamout_token": user.beamout_token, "layout": user.layout, "saved_at": user.saved_at, "is_admin": user.is_admin });
+                    if let Some(role) = &user.role { response["role"] = Value::from(role.clone()); }
+                    if let Some((layout, saved_at)) = &user.autosave { response["autosave"] = serde_json::json!({ "layout": layout, "saved_at": saved_at }); }
+                    (200, response.to_string())
+                },
+                None => (404, String::from("No such session")),
+            }
+        },
+        Endpoint::Beamout => {
+            let token = segments[1];
+            let layout: Value = match serde_json::from_slice(&request.body) { Ok(layout) => layout, Err(_) => return (400, String::from("Layout isn't JSON")) };
+            let saved_at = request.headers.get(signing::SAVED_AT_HEADER).and_then(|saved_at| saved_at.parse::<u64>().ok());
+            let autosave = request.headers.get(signing::AUTOSAVE_HEADER).map(|autosave| autosave == "true").unwrap_or(false);
+            match state.users.values_mut().find(|user| user.beamout_token == token) {
+                Some(user) => {
+                    let saved_at = saved_at.unwrap_or(0);
+                    let stale = saved_at < user.saved_at.unwrap_or(0) || (autosave && user.autosave.as_ref().map(|(_, autosaved_at)| saved_at < *autosaved_at).unwrap_or(false));
+                    if stale { return (200, String::from("OK")) };
+                    if autosave { user.autosave = Some((layout.clone(), saved_at)); }
+                    else { user.layout = Some(layout.clone()); user.saved_at = Some(saved_at); }
+                    state.beamouts.push((token.to_owned(), layout));
+                    (200, String::from("OK"))
+                },
+                None => (404, String::from("No such user")),
+            }
+        },
+    }
+}
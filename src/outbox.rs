@@ -0,0 +1,396 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use async_std::task::JoinHandle;
+use crate::beamout::{prune_for_beamout, BeamoutMeta};
+use crate::store::PlayerStore;
+use crate::world::parts::RecursivePartDescription;
+use crate::metrics::{METRICS, Metrics};
+use crate::config::config;
+
+/// A beamout as it sits in the journal, one JSON file per entry
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OutboxEntry {
+    pub id: u64,
+    #[serde(default)]
+    pub player: String,
+    pub beamout_token: String,
+    pub layout: RecursivePartDescription,
+    /// Unix time in seconds
+    pub created: u64,
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    #[serde(default)]
+    pub autosave: bool,
+}
+
+/// What admin tools get to see about an entry
+struct EntryStatus {
+    player: String,
+    beamout_token: String,
+    autosave: bool,
+    created: u64,
+    attempts: u32,
+    last_error: Option<String>,
+    next_attempt: Option<u64>,
+    failed: bool,
+}
+
+/// Beamouts are journaled to disk before the first attempt and only removed once the store confirms them,
+/// so an API outage or a crash can't lose a ship. Entries the store outright refuses are moved to `failed/`
+/// instead of being retried forever; `/outbox retry` puts them back in line.
+pub struct Outbox {
+    dir: PathBuf,
+    store: Arc<dyn PlayerStore>,
+    next_id: AtomicU64,
+    entries: Mutex<BTreeMap<u64, EntryStatus>>,
+    /// Newest entry delivered for each beamout token, and whether it was an autosave, so a stuck older one knows it's been overtaken
+    delivered: Mutex<BTreeMap<(String, bool), u64>>,
+}
+
+fn now() -> u64 { SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() }
+
+fn read_entries(dir: &Path) -> std::io::Result<Vec<OutboxEntry>> {
+    let mut entries = Vec::new();
+    for file in std::fs::read_dir(dir)? {
+        let path = file?.path();
+        if path.extension().map(|ext| ext != "json").unwrap_or(true) { continue };
+        match std::fs::read_to_string(&path).map_err(|err| err.to_string()).and_then(|file| serde_json::from_str(&file).map_err(|err| err.to_string())) {
+            Ok(entry) => entries.push(entry),
+            Err(err) => error!("outbox"; "Skipping unreadable journal entry {}: {}", path.display(), err),
+        }
+    }
+    Ok(entries)
+}
+
+impl Outbox {
+    /// Opens (creating if needed) the journal directory and starts redelivering anything left over from last run
+    pub fn open(dir: impl Into<PathBuf>, store: Arc<dyn PlayerStore>) -> std::io::Result<Arc<Outbox>> {
+        let dir = dir.into();
+        std::fs::create_dir_all(dir.join("failed"))?;
+        let pending = read_entries(&dir)?;
+        let failed = read_entries(&dir.join("failed"))?;
+        let next_id = pending.iter().chain(failed.iter()).map(|entry| entry.id + 1).max().unwrap_or(1);
+        let outbox = Arc::new(Outbox { dir, store, next_id: AtomicU64::new(next_id), entries: Mutex::new(BTreeMap::new()), delivered: Mutex::new(BTreeMap::new()) });
+        {
+            let mut entries = outbox.entries.lock().unwrap();
+            for entry in &failed {
+                entries.insert(entry.id, EntryStatus { player: entry.player.clone(), beamout_token: entry.beamout_token.clone(), autosave: entry.autosave, created: entry.created, attempts: entry.attempts, last_error: entry.last_error.clone(), next_attempt: None, failed: true });
+            }
+        }
+        if !pending.is_empty() { info!("outbox"; "Replaying {} beamouts from {}", pending.len(), outbox.dir.display()); }
+        //Newest first, so older entries for the same player already see what supersedes them when their delivery starts
+        let mut pending = pending;
+        pending.sort_by_key(|entry| std::cmp::Reverse(entry.id));
+        for entry in pending { outbox.spawn_delivery(entry); }
+        outbox.update_metrics();
+        Ok(outbox)
+    }
+
+    /// Journals a beamout and starts delivering it. The handle resolves to whether the store accepted it,
+    /// which may take a while if it's down. Pending entries are dropped rather than delivered over a newer one for the same player:
+    /// autosaves once anything newer comes along, deliberate beamouts once a newer deliberate beamout does.
+    pub fn submit(self: &Arc<Self>, player: &str, beamout_token: String, mut layout: RecursivePartDescription, autosave: bool) -> JoinHandle<bool> {
+        prune_for_beamout(&mut layout);
+        let entry = OutboxEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            player: player.to_owned(),
+            beamout_token, layout,
+            created: now(),
+            attempts: 0, last_error: None,
+            autosave,
+        };
+        //Still worth trying to deliver even if the disk is unhappy
+        if let Err(err) = self.persist(&entry) { error!("outbox", entry = entry.id; "Failed to journal the beamout for {}: {}", player, err); }
+        self.spawn_delivery(entry)
+    }
+
+    fn entry_path(&self, id: u64, failed: bool) -> PathBuf {
+        if failed { self.dir.join("failed").join(format!("{}.json", id)) } else { self.dir.join(format!("{}.json", id)) }
+    }
+
+    /// Writes to a temporary file and renames it over the old one, so a crash never leaves half an entry
+    fn persist(&self, entry: &OutboxEntry) -> std::io::Result<()> {
+        let tmp = self.dir.join(format!("{}.json.tmp", entry.id));
+        std::fs::write(&tmp, serde_json::to_vec(entry)?)?;
+        std::fs::rename(&tmp, self.entry_path(entry.id, false))
+    }
+
+    fn spawn_delivery(self: &Arc<Self>, mut entry: OutboxEntry) -> JoinHandle<bool> {
+        self.entries.lock().unwrap().insert(entry.id, EntryStatus {
+            player: entry.player.clone(), beamout_token: entry.beamout_token.clone(), autosave: entry.autosave, created: entry.created, attempts: entry.attempts, last_error: entry.last_error.clone(), next_attempt: None, failed: false
+        });
+        self.update_metrics();
+        let outbox = self.clone();
+        async_std::task::spawn(async move {
+            loop {
+                if outbox.is_superseded(&entry) {
+                    debug!("outbox", entry = entry.id; "Dropping {} for {}, there's a newer one", if entry.autosave { "autosave" } else { "beamout" }, entry.player);
+                    let _ = std::fs::remove_file(outbox.entry_path(entry.id, false));
+                    outbox.entries.lock().unwrap().remove(&entry.id);
+                    outbox.update_metrics();
+                    return false;
+                }
+                let result = outbox.store.beamout(&entry.beamout_token, &entry.layout, BeamoutMeta { autosave: entry.autosave, saved_at: entry.created }).await;
+                Metrics::increment(if result.is_ok() { &METRICS.beamout_success } else { &METRICS.beamout_failure });
+                match result {
+                    Ok(()) => {
+                        if entry.attempts > 0 { info!("outbox", entry = entry.id; "Beamout for {} delivered after {} retries", entry.player, entry.attempts); }
+                        if let Err(err) = std::fs::remove_file(outbox.entry_path(entry.id, false)) {
+                            //Worst case it gets delivered twice next startup, which beats never
+                            warn!("outbox", entry = entry.id; "Failed to remove delivered beamout from the journal: {}", err);
+                        }
+                        outbox.entries.lock().unwrap().remove(&entry.id);
+                        let mut delivered = outbox.delivered.lock().unwrap();
+                        let newest = delivered.entry((entry.beamout_token.clone(), entry.autosave)).or_insert(entry.id);
+                        *newest = entry.id.max(*newest);
+                        drop(delivered);
+                        outbox.update_metrics();
+                        return true;
+                    },
+                    Err(err) => {
+                        entry.attempts += 1;
+                        entry.last_error = Some(err.reason.clone());
+                        if !err.retryable {
+                            error!("outbox", entry = entry.id; "Beamout for {} refused, moving it to failed/: {}", entry.player, err.reason);
+                            outbox.mark_failed(&entry);
+                            return false;
+                        }
+                        let delay = backoff(entry.attempts, config().outbox_max_retry_seconds);
+                        warn!("outbox", entry = entry.id; "Beamout for {} failed (attempt {}), retrying in {:?}: {}", entry.player, entry.attempts, delay, err.reason);
+                        let _ = outbox.persist(&entry);
+                        if let Some(status) = outbox.entries.lock().unwrap().get_mut(&entry.id) {
+                            status.attempts = entry.attempts;
+                            status.last_error = entry.last_error.clone();
+                            status.next_attempt = Some(now() + delay.as_secs());
+                        }
+                        async_std::task::sleep(delay).await;
+                    }
+                }
+            }
+        })
+    }
+
+    /// An autosave only matters for the player's latest ship, but a deliberate beamout has to outlive any autosaves after it
+    fn is_superseded(&self, entry: &OutboxEntry) -> bool {
+        let overtaken_by = |autosave: bool| entry.autosave || !autosave;
+        let delivered = self.delivered.lock().unwrap();
+        let delivered_newer = |autosave: bool| delivered.get(&(entry.beamout_token.clone(), autosave)).map(|id| *id > entry.id).unwrap_or(false);
+        if delivered_newer(false) || (entry.autosave && delivered_newer(true)) { return true };
+        self.entries.lock().unwrap().range(entry.id + 1..)
+            .any(|(_id, status)| status.beamout_token == entry.beamout_token && !status.failed && overtaken_by(status.autosave))
+    }
+
+    fn mark_failed(&self, entry: &OutboxEntry) {
+        let moved = self.persist(entry).and_then(|_| std::fs::rename(self.entry_path(entry.id, false), self.entry_path(entry.id, true)));
+        if let Err(err) = moved { error!("outbox", entry = entry.id; "Failed to move refused beamout to failed/: {}", err); }
+        if let Some(status) = self.entries.lock().unwrap().get_mut(&entry.id) {
+            status.attempts = entry.attempts;
+            status.last_error = entry.last_error.clone();
+            status.next_attempt = None;
+            status.failed = true;
+        }
+        self.update_metrics();
+    }
+
+    /// Puts every refused beamout back in line, returning how many there were
+    pub fn retry_failed(self: &Arc<Self>) -> std::io::Result<usize> {
+        let failed = read_entries(&self.dir.join("failed"))?;
+        let count = failed.len();
+        for entry in failed {
+            std::fs::rename(self.entry_path(entry.id, true), self.entry_path(entry.id, false))?;
+            self.spawn_delivery(entry);
+        }
+        Ok(count)
+    }
+
+    /// One line per entry, for `/outbox`
+    pub fn describe(&self) -> Vec<String> {
+        let now = now();
+        self.entries.lock().unwrap().iter().map(|(id, status)| {
+            let state = if status.failed { String::from("FAILED") }
+                else if let Some(next_attempt) = status.next_attempt { format!("retrying in {}s", next_attempt.saturating_sub(now)) }
+                else { String::from("sending") };
+            format!("#{} {}{} ({}s old, {} attempts) {}{}", id, status.player, if status.autosave { " autosave" } else { "" }, now.saturating_sub(status.created), status.attempts, state,
+                status.last_error.as_ref().map(|err| format!(": {}", err)).unwrap_or_default())
+        }).collect()
+    }
+
+    fn update_metrics(&self) {
+        let entries = self.entries.lock().unwrap();
+        let failed = entries.values().filter(|status| status.failed).count();
+        Metrics::set(&METRICS.outbox_pending, entries.len() - failed);
+        Metrics::set(&METRICS.outbox_failed, failed);
+    }
+}
+
+/// Doubles from a second after the first failure, up to `max_seconds`
+fn backoff(attempts: u32, max_seconds: u64) -> Duration {
+    let max = max_seconds.max(1);
+    Duration::from_secs(1u64.checked_shl(attempts.saturating_sub(1)).unwrap_or(max).min(max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::HttpStore;
+    use crate::mock_api::{MockApi, MockUser, Endpoint};
+    use crate::world::parts::PartKind;
+
+    /// An empty directory of its own for each test
+    fn journal_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("glap-outbox-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    async fn api() -> (MockApi, Arc<dyn PlayerStore>) {
+        let api = MockApi::start("hunter2").await.unwrap();
+        api.add_user("a", MockUser::new("tok-a"));
+        let store = Arc::new(HttpStore::new(api.prefix(), String::from("hunter2")));
+        (api, store)
+    }
+
+    fn files_in(dir: &Path) -> Vec<String> {
+        let mut files = std::fs::read_dir(dir).unwrap().map(|file| file.unwrap().file_name().into_string().unwrap()).filter(|name| name.ends_with(".json")).collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    /// Waits up to a few seconds for the outbox to get somewhere in the background
+    async fn eventually(what: impl Fn() -> bool) {
+        for _ in 0..50 {
+            if what() { return };
+            async_std::task::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("Gave up waiting");
+    }
+
+    #[async_std::test]
+    async fn journals_before_sending() {
+        let dir = journal_dir("journal");
+        let (api, store) = api().await;
+        api.set_latency(Duration::from_millis(300));
+        let outbox = Outbox::open(&dir, store).unwrap();
+
+        let delivered = outbox.submit("alice", String::from("tok-a"), PartKind::Core.into(), false);
+        assert_eq!(files_in(&dir), vec!["1.json"]);
+        assert!(delivered.await);
+        assert!(files_in(&dir).is_empty());
+        assert_eq!(api.beamouts().len(), 1);
+        assert!(outbox.describe().is_empty());
+    }
+
+    #[async_std::test]
+    async fn retries_until_the_api_recovers() {
+        let dir = journal_dir("retry");
+        let (api, store) = api().await;
+        api.respond_next(Endpoint::Beamout, 503, "Maintenance");
+        let outbox = Outbox::open(&dir, store).unwrap();
+
+        let delivered = outbox.submit("alice", String::from("tok-a"), PartKind::Core.into(), false);
+        eventually(|| outbox.describe()[0].contains("retrying")).await;
+        let status = outbox.describe();
+        assert!(status[0].contains("1 attempts") && status[0].contains("retrying in 1s"), "{:?}", status);
+        let journaled: OutboxEntry = serde_json::from_str(&std::fs::read_to_string(dir.join("1.json")).unwrap()).unwrap();
+        assert_eq!(journaled.attempts, 1);
+
+        assert!(delivered.await);
+        assert_eq!(api.requests(Endpoint::Beamout), 2);
+        assert!(files_in(&dir).is_empty());
+    }
+
+    #[async_std::test]
+    async fn refused_beamouts_wait_in_failed_until_retried() {
+        let dir = journal_dir("failed");
+        let (api, store) = api().await;
+        api.respond_next(Endpoint::Beamout, 400, "Bad layout");
+        let outbox = Outbox::open(&dir, store).unwrap();
+
+        assert!(!outbox.submit("alice", String::from("tok-a"), PartKind::Core.into(), false).await);
+        assert!(files_in(&dir).is_empty());
+        assert_eq!(files_in(&dir.join("failed")), vec!["1.json"]);
+        assert!(outbox.describe()[0].contains("FAILED"));
+        assert!(api.beamouts().is_empty());
+
+        assert_eq!(outbox.retry_failed().unwrap(), 1);
+        eventually(|| api.beamouts().len() == 1).await;
+        eventually(|| outbox.describe().is_empty()).await;
+        assert!(files_in(&dir.join("failed")).is_empty());
+        assert!(files_in(&dir).is_empty());
+    }
+
+    #[async_std::test]
+    async fn replays_what_was_left_over_on_open() {
+        let dir = journal_dir("replay");
+        std::fs::create_dir_all(dir.join("failed")).unwrap();
+        let entry = |id: u64| OutboxEntry { id, player: String::from("alice"), beamout_token: String::from("tok-a"), layout: PartKind::Core.into(), created: 100, attempts: 3, last_error: None, autosave: false };
+        std::fs::write(dir.join("7.json"), serde_json::to_vec(&entry(7)).unwrap()).unwrap();
+        std::fs::write(dir.join("failed").join("9.json"), serde_json::to_vec(&entry(9)).unwrap()).unwrap();
+        std::fs::write(dir.join("8.json"), "{ not json").unwrap();
+        let (api, store) = api().await;
+
+        let outbox = Outbox::open(&dir, store).unwrap();
+        eventually(|| api.beamouts().len() == 1).await;
+        eventually(|| files_in(&dir) == vec!["8.json"]).await;
+        //Refused ones stay put until someone asks, and new entries don't reuse their ids
+        assert_eq!(files_in(&dir.join("failed")), vec!["9.json"]);
+        assert_eq!(outbox.describe().len(), 1);
+        assert!(outbox.submit("alice", String::from("tok-a"), PartKind::Core.into(), false).await);
+        assert_eq!(outbox.next_id.load(Ordering::Relaxed), 11);
+    }
+
+    #[async_std::test]
+    async fn a_stuck_beamout_never_lands_on_top_of_a_newer_one() {
+        let dir = journal_dir("order");
+        let (api, store) = api().await;
+        api.respond_next(Endpoint::Beamout, 503, "Maintenance");
+        let outbox = Outbox::open(&dir, store).unwrap();
+        let older = RecursivePartDescription { kind: PartKind::Core, attachments: vec![Some(PartKind::Cargo.into())] };
+
+        let stuck = outbox.submit("alice", String::from("tok-a"), older, false);
+        eventually(|| api.requests(Endpoint::Beamout) == 1).await;
+        //Comes along while the first is backing off, and gets there first
+        let autosave = outbox.submit("alice", String::from("tok-a"), PartKind::Hub.into(), true);
+        assert!(autosave.await);
+        assert!(outbox.submit("alice", String::from("tok-a"), PartKind::Core.into(), false).await);
+        assert!(!stuck.await);
+
+        assert_eq!(api.beamouts().len(), 2);
+        assert_eq!(api.layout("a"), Some(serde_json::json!({ "kind": 0, "attachments": [] })));
+        assert!(files_in(&dir).is_empty());
+        assert!(outbox.describe().is_empty());
+    }
+
+    #[async_std::test]
+    async fn older_journal_entries_are_dropped_on_replay() {
+        let dir = journal_dir("replay-order");
+        std::fs::create_dir_all(&dir).unwrap();
+        let entry = |id: u64, autosave: bool| OutboxEntry { id, player: String::from("alice"), beamout_token: String::from("tok-a"), layout: PartKind::Core.into(), created: 100 + id, attempts: 1, last_error: None, autosave };
+        for (id, autosave) in &[(1, false), (2, true), (3, false), (4, true)] {
+            std::fs::write(dir.join(format!("{}.json", id)), serde_json::to_vec(&entry(*id, *autosave)).unwrap()).unwrap();
+        }
+        let (api, store) = api().await;
+
+        let _outbox = Outbox::open(&dir, store).unwrap();
+        eventually(|| files_in(&dir).is_empty()).await;
+        //Only the newest beamout and the autosave after it are worth sending
+        assert_eq!(api.beamouts().len(), 2);
+        assert_eq!(api.requests(Endpoint::Beamout), 2);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let seconds = |attempts| backoff(attempts, 300).as_secs();
+        assert_eq!((1..=6).map(seconds).collect::<Vec<_>>(), vec![1, 2, 4, 8, 16, 32]);
+        assert_eq!(seconds(9), 256);
+        assert_eq!(seconds(10), 300);
+        assert_eq!(seconds(1000), 300);
+        assert_eq!(backoff(5, 10).as_secs(), 10);
+        assert_eq!(backoff(5, 0).as_secs(), 1);
+    }
+}
@@ -0,0 +1,294 @@
+//! Recording everything the game loop is told, so a run can be replayed offline with `--replay <file>`.
+//!
+//! A recording is JSON lines: a `Header` with what the world started from, then every `ToGameEvent`
+//! tagged with how many ticks had gone by when it was applied, and a checksum of the world every second.
+//! The simulation is deterministic given the same seed and inputs, so a replay that feeds the same events in
+//! on the same ticks should come up with the same checksums; the first one that doesn't is where it diverged.
+//! Sessions and beamout tokens are left out, replays don't need them.
+//!
+//! Replaying is a mode of the server binary rather than a tool of its own: it needs the whole game loop, which only the server has.
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufWriter, Write};
+use async_std::sync::{Sender, channel};
+use futures::FutureExt;
+use crate::codec::ToServerMsg;
+use crate::config::ServerConfig;
+use crate::roles::Role;
+use crate::session::{ToGameEvent, CommandIssuer};
+use crate::snapshot::WorldSnapshot;
+use crate::world::parts::RecursivePartDescription;
+use crate::world::planets::CelestialObjectDef;
+
+/// Bumped whenever an older recording can't be replayed anymore
+pub const VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+pub enum Entry {
+    Header(Box<Header>),
+    Event { tick: u64, event: RecordedEvent },
+    Checksum { tick: u64, checksum: u64 },
+}
+
+/// Everything the world is built from before the first tick
+#[derive(Serialize, Deserialize)]
+pub struct Header {
+    pub version: u32,
+    pub seed: u64,
+    pub config: ServerConfig,
+    pub planets: Vec<CelestialObjectDef>,
+    /// What the world was restored from, if anything
+    pub snapshot: Option<WorldSnapshot>,
+}
+
+/// A `ToGameEvent` as it's written down
+#[derive(Serialize, Deserialize)]
+pub enum RecordedEvent {
+    NewPlayer { id: u16, name: String, has_session: bool, parts: RecursivePartDescription, has_beamout_token: bool, role: Role },
+    SendEntireWorld { to_player: u16, send_self: bool },
+    /// The message as the client sent it, base64 encoded
+    PlayerMessage { id: u16, msg: String },
+    PlayerQuit { id: u16 },
+    /// `player` is `None` for the operator console
+    AdminCommand { player: Option<u16>, command: String },
+    PlayerSuspend { id: u16 },
+    PlayerReconnect { id: u16 },
+}
+
+impl From<&ToGameEvent> for RecordedEvent {
+    fn from(event: &ToGameEvent) -> RecordedEvent {
+        match event {
+            ToGameEvent::NewPlayer { id, name, session, parts, beamout_token, role } => RecordedEvent::NewPlayer { id: *id, name: name.clone(), has_session: session.is_some(), parts: parts.clone(), has_beamout_token: beamout_token.is_some(), role: *role },
+            ToGameEvent::SendEntireWorld { to_player, send_self } => RecordedEvent::SendEntireWorld { to_player: *to_player, send_self: *send_self },
+            ToGameEvent::PlayerMessage { id, msg } => {
+                let mut bytes = Vec::new();
+                msg.serialize(&mut bytes);
+                RecordedEvent::PlayerMessage { id: *id, msg: base64::encode(bytes) }
+            },
+            ToGameEvent::PlayerQuit { id } => RecordedEvent::PlayerQuit { id: *id },
+            ToGameEvent::AdminCommand { issuer, command } => RecordedEvent::AdminCommand {
+                player: if let CommandIssuer::Player(id) = issuer { Some(*id) } else { None },
+                command: command.clone(),
+            },
+            ToGameEvent::PlayerSuspend { id, ref_handle: _ } => RecordedEvent::PlayerSuspend { id: *id },
+            ToGameEvent::PlayerReconnect { id } => RecordedEvent::PlayerReconnect { id: *id },
+        }
+    }
+}
+
+impl RecordedEvent {
+    /// Console replies go to `console`
+    fn into_event(self, console: &Sender<String>) -> Result<ToGameEvent, String> {
+        Ok(match self {
+            RecordedEvent::NewPlayer { id, name, has_session, parts, has_beamout_token, role } => ToGameEvent::NewPlayer {
+                id, name, parts, role,
+                session: if has_session { Some(String::new()) } else { None },
+                beamout_token: if has_beamout_token { Some(String::new()) } else { None },
+            },
+            RecordedEvent::SendEntireWorld { to_player, send_self } => ToGameEvent::SendEntireWorld { to_player, send_self },
+            RecordedEvent::PlayerMessage { id, msg } => {
+                let bytes = base64::decode(&msg).map_err(|err| format!("Player {} sent a message that isn't base64: {}", id, err))?;
+                let msg = ToServerMsg::deserialize(&mut futures::stream::iter(bytes)).now_or_never()
+                    .and_then(Result::ok).ok_or_else(|| format!("Player {} sent a message that doesn't decode", id))?;
+                ToGameEvent::PlayerMessage { id, msg }
+            },
+            RecordedEvent::PlayerQuit { id } => ToGameEvent::PlayerQuit { id },
+            RecordedEvent::AdminCommand { player, command } => ToGameEvent::AdminCommand {
+                issuer: player.map(CommandIssuer::Player).unwrap_or_else(|| CommandIssuer::Console(console.clone())),
+                command,
+            },
+            RecordedEvent::PlayerSuspend { id } => ToGameEvent::PlayerSuspend { id, ref_handle: String::new() },
+            RecordedEvent::PlayerReconnect { id } => ToGameEvent::PlayerReconnect { id },
+        })
+    }
+}
+
+/// Writes a recording as the game runs
+pub struct Recorder {
+    out: BufWriter<std::fs::File>,
+    /// Ticks simulated so far
+    tick: u64,
+    /// Whether anything was written since the last flush
+    dirty: bool,
+}
+
+impl Recorder {
+    /// Starts `<dir>/<unix seconds>-<seed>.jsonl`
+    pub fn create(dir: &str, seed: u64, config: &ServerConfig, planets: &[CelestialObjectDef], snapshot: Option<&WorldSnapshot>) -> Result<(Recorder, String), String> {
+        std::fs::create_dir_all(dir).map_err(|err| format!("Failed to create {}: {}", dir, err))?;
+        let path = format!("{}/{}-{}.jsonl", dir, crate::signing::now(), seed);
+        let file = std::fs::File::create(&path).map_err(|err| format!("Failed to create {}: {}", path, err))?;
+        let mut recorder = Recorder { out: BufWriter::new(file), tick: 0, dirty: false };
+        let mut config = config.clone();
+        config.api_password = String::new();
+        //Snapshots hold sessions and beamout tokens too
+        let snapshot = snapshot.map(|snapshot| serde_json::to_value(snapshot).unwrap()).map(|mut snapshot| {
+            for player in snapshot["players"].as_array_mut().into_iter().flatten() {
+                if !player["session"].is_null() { player["session"] = "".into(); }
+                if !player["beamout_token"].is_null() { player["beamout_token"] = "".into(); }
+            }
+            serde_json::from_value(snapshot).unwrap()
+        });
+        recorder.write(&Entry::Header(Box::new(Header { version: VERSION, seed, config, planets: planets.to_vec(), snapshot })));
+        recorder.flush();
+        Ok((recorder, path))
+    }
+
+    pub fn record(&mut self, event: &ToGameEvent) {
+        self.write(&Entry::Event { tick: self.tick, event: event.into() });
+    }
+
+    /// Called after every tick
+    pub fn ticked(&mut self, checksum: u64) {
+        self.tick += 1;
+        if self.tick.is_multiple_of(crate::config::config().ticks_per_second as u64) {
+            self.write(&Entry::Checksum { tick: self.tick, checksum });
+        }
+    }
+
+    /// Called after every event, so a crash loses nothing
+    pub fn flush(&mut self) {
+        if std::mem::replace(&mut self.dirty, false) {
+            if let Err(err) = self.out.flush() { error!("recording"; "Failed to write the recording: {}", err); }
+        }
+    }
+
+    fn write(&mut self, entry: &Entry) {
+        self.dirty = true;
+        let result = serde_json::to_writer(&mut self.out, entry).map_err(std::io::Error::from).and_then(|()| self.out.write_all(b"\n"));
+        if let Err(err) = result { error!("recording"; "Failed to write the recording: {}", err); }
+    }
+}
+
+/// What a replay is fed instead of the network, ticker and signals
+pub enum ReplayEvent {
+    Inbound(ToGameEvent),
+    Simulate,
+}
+
+/// Plays a recording back, checking the world against it along the way
+pub struct Replay {
+    pub header: Header,
+    entries: VecDeque<Entry>,
+    /// Ticks simulated so far
+    tick: u64,
+    events: u64,
+    checked: u64,
+    diverged: u64,
+    console: Sender<String>,
+}
+
+impl Replay {
+    pub fn open(path: &str) -> Result<Replay, String> {
+        let file = std::fs::File::open(path).map_err(|err| format!("Failed to open {}: {}", path, err))?;
+        let mut entries = VecDeque::new();
+        for (number, line) in std::io::BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|err| format!("Failed to read {}: {}", path, err))?;
+            //The last line may have been cut off by a crash
+            match serde_json::from_str::<Entry>(&line) {
+                Ok(entry) => entries.push_back(entry),
+                Err(err) => { warn!("replay", line = number + 1; "Stopping early, the rest of {} is unreadable: {}", path, err); break; }
+            }
+        }
+        let header = match entries.pop_front() {
+            Some(Entry::Header(header)) => *header,
+            _ => return Err(format!("{} doesn't start with a header", path)),
+        };
+        if header.version != VERSION { return Err(format!("{} is a version {} recording, this server replays version {}", path, header.version, VERSION)); }
+        let (console, console_replies) = channel::<String>(16);
+        async_std::task::spawn(async move {
+            while let Ok(reply) = console_replies.recv().await { info!("replay"; "Console: {}", reply); }
+        });
+        Ok(Replay { header, entries, tick: 0, events: 0, checked: 0, diverged: 0, console })
+    }
+
+    /// The recorded config, minus anything that would reach outside the process
+    pub fn config(&self) -> ServerConfig {
+        let mut config = self.header.config.clone();
+        config.api = None;
+        config.store_dir = None;
+        config.roles = None;
+        config.console_socket = None;
+        config.metrics_addr = None;
+        config.snapshot_file = String::new();
+        config.record_dir = None;
+        config
+    }
+
+    /// How long the recording is
+    pub fn ticks(&self) -> u64 {
+        self.entries.iter().rev().find_map(|entry| match entry {
+            Entry::Event { tick, .. } | Entry::Checksum { tick, .. } => Some(*tick),
+            Entry::Header(_) => None,
+        }).unwrap_or(0)
+    }
+
+    pub fn next_event(&mut self) -> Option<ReplayEvent> {
+        loop {
+            let due = matches!(self.entries.front()?, Entry::Event { tick, .. } if *tick <= self.tick);
+            if !due { self.tick += 1; return Some(ReplayEvent::Simulate); }
+            if let Some(Entry::Event { event, .. }) = self.entries.pop_front() {
+                self.events += 1;
+                match event.into_event(&self.console) {
+                    Ok(event) => return Some(ReplayEvent::Inbound(event)),
+                    Err(err) => warn!("replay"; "Skipping an event on tick {}: {}", self.tick, err),
+                }
+            }
+        }
+    }
+
+    /// Called after every tick, compares against the recording if it has a checksum for this one
+    pub fn ticked(&mut self, checksum: u64) {
+        if let Some(Entry::Checksum { tick, checksum: expected }) = self.entries.front() {
+            if *tick == self.tick {
+                self.checked += 1;
+                if *expected != checksum {
+                    if self.diverged == 0 { error!("replay"; "Diverged from the recording on tick {}", self.tick); }
+                    self.diverged += 1;
+                }
+                self.entries.pop_front();
+            }
+        }
+    }
+
+    /// How many checksums were compared, and how many of those didn't match
+    pub fn checked(&self) -> (u64, u64) { (self.checked, self.diverged) }
+
+    /// Logs how it went and returns the exit code
+    pub fn report(&self) -> i32 {
+        info!("replay"; "Replayed {} ticks and {} events, {} of {} checksums matched", self.tick, self.events, self.checked - self.diverged, self.checked);
+        if self.diverged > 0 { 1 } else { 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::parts::PartKind;
+
+    #[test]
+    fn checksums_hash_the_same_on_every_build() {
+        use std::hash::Hasher;
+        //Test vectors from the FNV reference
+        let hash = |bytes: &[u8]| { let mut hasher = crate::world::Fnv1a::default(); hasher.write(bytes); hasher.finish() };
+        assert_eq!(hash(b""), 0xcbf29ce484222325);
+        assert_eq!(hash(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(hash(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn events_survive_being_written_down() {
+        let (console, _console_replies) = channel(1);
+        let thrust = ToGameEvent::PlayerMessage { id: 3, msg: ToServerMsg::SetThrusters { forward: true, backward: false, clockwise: false, counter_clockwise: true } };
+        let line = serde_json::to_string(&Entry::Event { tick: 40, event: (&thrust).into() }).unwrap();
+        let event = match serde_json::from_str::<Entry>(&line).unwrap() {
+            Entry::Event { tick: 40, event } => event.into_event(&console).unwrap(),
+            _ => panic!("Not an event"),
+        };
+        assert!(matches!(event, ToGameEvent::PlayerMessage { id: 3, msg: ToServerMsg::SetThrusters { forward: true, backward: false, clockwise: false, counter_clockwise: true } }));
+
+        let joined = ToGameEvent::NewPlayer { id: 1, name: String::from("alice"), session: Some(String::from("secret")), parts: PartKind::Core.into(), beamout_token: None, role: Role::Player };
+        let line = serde_json::to_string(&RecordedEvent::from(&joined)).unwrap();
+        assert!(!line.contains("secret"));
+    }
+}
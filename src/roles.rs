@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Role { #[default] Player, Moderator, Admin, Owner }
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Permission { ListPlayers, Kick, Broadcast, Teleport, BypassQueue, ManageOutbox, Shutdown, EmergencyStop, TogglePvp }
+
+impl Role {
+    pub fn permissions(&self) -> &'static [Permission] {
+        match self {
+            Role::Player => &[],
+            Role::Moderator => &[Permission::ListPlayers, Permission::Kick],
+            Role::Admin => &[Permission::ListPlayers, Permission::Kick, Permission::Broadcast, Permission::Teleport, Permission::BypassQueue, Permission::ManageOutbox, Permission::TogglePvp],
+            //Only the owner can take the whole server down
+            Role::Owner => &[Permission::ListPlayers, Permission::Kick, Permission::Broadcast, Permission::Teleport, Permission::BypassQueue, Permission::ManageOutbox, Permission::TogglePvp, Permission::Shutdown, Permission::EmergencyStop],
+        }
+    }
+    /// Its lowercase name, as in role tables and beamin responses. None for names this server doesn't know
+    pub fn from_name(name: &str) -> Option<Role> { serde_json::from_value(serde_json::Value::from(name)).ok() }
+    pub fn has(&self, permission: Permission) -> bool { self.permissions().contains(&permission) }
+
+    /// Whether someone with this role may act on (kick, etc) someone with the other role
+    pub fn outranks(&self, other: Role) -> bool { *self > other }
+
+    pub fn chat_tag(&self) -> Option<&'static str> {
+        match self {
+            Role::Player => None,
+            Role::Moderator => Some("[Mod]"),
+            Role::Admin => Some("[Admin]"),
+            Role::Owner => Some("[Owner]"),
+        }
+    }
+    pub fn decorate_name(&self, name: &str) -> String {
+        if let Some(tag) = self.chat_tag() { format!("{} {}", tag, name) } else { name.to_owned() }
+    }
+}
+
+/// The permission a slash command requires, or None if the command doesn't exist
+pub fn command_permission(command: &str) -> Option<Permission> {
+    match command {
+        "/list" => Some(Permission::ListPlayers),
+        "/kick" => Some(Permission::Kick),
+        "/broadcast" => Some(Permission::Broadcast),
+        "/teleport" => Some(Permission::Teleport),
+        "/outbox" => Some(Permission::ManageOutbox),
+        "/shutdown" => Some(Permission::Shutdown),
+        "/stop" => Some(Permission::EmergencyStop),
+        "/pvp" => Some(Permission::TogglePvp),
+        _ => None
+    }
+}
+
+/// Roles assigned locally, keyed by beamout token, on top of whatever the beamin API says
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct RoleTable {
+    #[serde(default)]
+    pub users: BTreeMap<String, Role>,
+}
+impl RoleTable {
+    pub fn load(path: &str) -> Result<RoleTable, Box<dyn std::error::Error>> {
+        let file = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&file)?)
+    }
+    pub fn role_for(&self, beamout_token: Option<&str>) -> Role {
+        beamout_token.and_then(|token| self.users.get(token).copied()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_higher_roles_outrank() {
+        assert!(Role::Owner.outranks(Role::Admin));
+        assert!(Role::Admin.outranks(Role::Moderator));
+        assert!(Role::Moderator.outranks(Role::Player));
+        assert!(!Role::Moderator.outranks(Role::Moderator));
+        assert!(!Role::Moderator.outranks(Role::Admin));
+        assert!(!Role::Player.outranks(Role::Player));
+    }
+
+    #[test]
+    fn commands_need_the_right_permission() {
+        assert_eq!(command_permission("/kick"), Some(Permission::Kick));
+        assert_eq!(command_permission("/pvp"), Some(Permission::TogglePvp));
+        assert_eq!(command_permission("/nope"), None);
+        assert_eq!(command_permission("kick"), None);
+        assert!(Role::Moderator.has(Permission::Kick));
+        assert!(!Role::Moderator.has(Permission::Shutdown));
+        assert!(!Role::Player.has(Permission::ListPlayers));
+        assert!(Role::Admin.has(Permission::TogglePvp));
+        assert!(!Role::Admin.has(Permission::Shutdown) && !Role::Admin.has(Permission::EmergencyStop));
+        for command in &["/list", "/kick", "/broadcast", "/teleport", "/outbox", "/shutdown", "/stop", "/pvp"] {
+            assert!(Role::Owner.has(command_permission(command).unwrap()), "{}", command);
+        }
+    }
+
+    #[test]
+    fn role_table_looks_up_beamout_tokens() {
+        let table: RoleTable = serde_json::from_str(r#"{ "users": { "tok-a": "admin", "tok-m": "moderator" } }"#).unwrap();
+        assert_eq!(table.role_for(Some("tok-a")), Role::Admin);
+        assert_eq!(table.role_for(Some("tok-m")), Role::Moderator);
+        assert_eq!(table.role_for(Some("someone")), Role::Player);
+        assert_eq!(table.role_for(None), Role::Player);
+        assert!(serde_json::from_str::<RoleTable>(r#"{ "users": { "tok": "god" } }"#).is_err());
+        assert!(serde_json::from_str::<RoleTable>("{}").unwrap().users.is_empty());
+        assert_eq!(Role::from_name("owner"), Some(Role::Owner));
+        assert_eq!(Role::from_name("god"), None);
+    }
+}
@@ -10,29 +10,41 @@ use nphysics2d::object::{Body, BodySet, RigidBody};
 use super::world::nphysics_types::{MyHandle, MyUnits};
 use super::world::parts::{Part, PartKind};
 use std::ops::{Deref, DerefMut};
-use crate::beamout::{BeaminResponse, beamin_request, spawn_beamout_request};
 use crate::world::parts::RecursivePartDescription;
-use crate::ApiDat;
+use crate::world::layout::validate_layout;
+use crate::store::PlayerStore;
 use std::sync::{Arc, Weak};
 use std::time::Duration;
 use async_std::sync::Mutex;
 use std::sync::atomic::{AtomicBool, Ordering};
-use crate::is_emergency_stop;
+use crate::{is_emergency_stop, is_shutting_down};
+use crate::metrics::{METRICS, Metrics};
+use crate::config::config;
+use crate::roles::{Role, RoleTable, Permission, command_permission};
 
 use crate::codec::*;
 
 pub mod websocket;
 use websocket::*;
+pub mod queue;
+use queue::{PlayerSlots, Admission, QueueTicket};
+pub mod names;
+use names::NameRegistry;
 
 pub enum ToGameEvent {
-    NewPlayer { id: u16, name: String, parts: RecursivePartDescription, beamout_token: Option<String> },
+    NewPlayer { id: u16, name: String, session: Option<String>, parts: RecursivePartDescription, beamout_token: Option<String>, role: Role },
     SendEntireWorld { to_player: u16, send_self: bool },
     PlayerMessage { id: u16, msg: ToServerMsg },
     PlayerQuit { id: u16 },
-    AdminCommand { id: u16, command: String },
+    AdminCommand { issuer: CommandIssuer, command: String },
     PlayerSuspend { id: u16, ref_handle: String, },
     PlayerReconnect { id: u16 },
 }
+pub enum CommandIssuer {
+    Player(u16),
+    /// The operator console, which wants its reply as plain text
+    Console(Sender<String>),
+}
 pub enum ToSerializerEvent {
     Message (u16, ToClientMsg),
     MulticastMessage (Vec<u16>, ToClientMsg),
@@ -44,6 +56,8 @@ pub enum ToSerializerEvent {
     SendPong (u16),
     DeleteWriter (u16),
     WriterDisconnect (u16, String),
+    /// Closes every connection as "server restarting" and acknowledges once they've all been queued
+    Shutdown (Sender<()>),
 }
 
 pub struct WorldUpdatePartMove {
@@ -58,6 +72,16 @@ pub type SuspendedPlayers = Arc<Mutex<VecDeque<Arc<SuspendedPlayer>>>>;
 pub struct SuspendedPlayer {
     pub id: u16,
     pub session: String,
+    pub role: Role,
+}
+
+/// State shared by every connection task
+pub struct SessionShared {
+    pub store: Option<Arc<dyn PlayerStore>>,
+    pub suspended_players: SuspendedPlayers,
+    pub roles: RoleTable,
+    pub slots: PlayerSlots,
+    pub names: NameRegistry,
 }
 
 pub enum GuarenteeOnePoll {
@@ -73,92 +97,140 @@ impl Future for GuarenteeOnePoll {
     }
 }
 
-pub async fn incoming_connection_acceptor(listener: TcpListener, to_game: Sender<ToGameEvent>, to_serializer: Sender<Vec<ToSerializerEvent>>, api: Option<Arc<ApiDat>>, suspended_players: SuspendedPlayers) {
-    println!("Hello from incomming connection acceptor");
-    let mut next_client_id: u16 = 1;
+/// Fetches a new player's ship, beamout token and role from the store. The role is the higher of what the store says
+/// and what the local role table gives the token. Without a session, a store or a successful beamin, they're a plain player with no ship.
+async fn beam_in(id: u16, name: &str, session: Option<&str>, shared: &SessionShared) -> (Option<RecursivePartDescription>, Option<String>, Role) {
+    let beamin_data = if let (Some(session), Some(store)) = (session, shared.store.as_ref()) {
+        match store.beamin(session).await {
+            Ok(beamin_data) => { info!("beamin", id = id, session = session; "Beamed in {}", name); Metrics::increment(&METRICS.beamin_success); beamin_data },
+            Err(err) => { warn!("beamin", id = id, session = session; "Failed to beam in {}: {}", name, err); Metrics::increment(&METRICS.beamin_failure); return (None, None, Role::Player) }
+        }
+    } else { return (None, None, Role::Player) };
+    let role = beamin_data.role().max(shared.roles.role_for(Some(&beamin_data.beamout_token)));
+    let layout = if beamin_data.autosave_is_newest() {
+        info!("beamin", id = id; "Restoring {}'s autosave", name);
+        beamin_data.autosave.map(|autosave| autosave.layout)
+    } else { beamin_data.layout };
+    (layout, Some(beamin_data.beamout_token), role)
+}
+
+/// Client ids start at `first_client_id`, which is past any player restored from a snapshot
+pub async fn incoming_connection_acceptor(listener: TcpListener, to_game: Sender<ToGameEvent>, to_serializer: Sender<Vec<ToSerializerEvent>>, shared: Arc<SessionShared>, first_client_id: u16) {
+    debug!("session"; "Accepting connections");
+    let mut next_client_id: u16 = first_client_id;
     while let Ok((socket, addr)) = listener.accept().await {
+        if is_shutting_down() {
+            debug!("session", ip = addr; "Refused connection during shutdown");
+            drop(socket);
+            continue;
+        }
         let client_id = next_client_id;
         next_client_id += 1;
 
         let to_game = to_game.clone();
         let to_serializer = to_serializer.clone();
-        let api = api.clone();
+        let shared = shared.clone();
 
         async_std::task::Builder::new()
             .name(format!("inbound_{:?}", addr).to_string())
-            .spawn(socket_reader(client_id, socket, addr, to_game, to_serializer, api, suspended_players.clone())).expect("Failed to launch inbound");
+            .spawn(async move {
+                //Ok is an orderly disconnect and Err is something going wrong, but both say why
+                match socket_reader(client_id, socket, addr, to_game, to_serializer, shared).await {
+                    Ok(reason) => { debug!("session", ip = addr; "Connection closed: {}", reason); METRICS.dropped_connection(reason) },
+                    Err(reason) => { info!("session", ip = addr; "Connection dropped: {}", reason); METRICS.dropped_connection(reason) },
+                }
+            }).expect("Failed to launch inbound");
     }
     panic!("Incoming connections closed");
 }
 
-async fn socket_reader(suggested_id: u16, socket: TcpStream, addr: async_std::net::SocketAddr, to_game: Sender<ToGameEvent>, to_serializer: Sender<Vec<ToSerializerEvent>>, api: Option<Arc<ApiDat>>, suspended_players: SuspendedPlayers) -> Result<(),()> {
-    println!("New socket from {:?}", addr);
-    let (mut socket_in, mut socket_out) = accept_websocket(socket).await?;
-    println!("Accepted websocket");
+async fn socket_reader(suggested_id: u16, socket: TcpStream, addr: async_std::net::SocketAddr, to_game: Sender<ToGameEvent>, to_serializer: Sender<Vec<ToSerializerEvent>>, shared: Arc<SessionShared>) -> Result<&'static str, &'static str> {
+    debug!("session", ip = addr; "New socket");
+    let (mut socket_in, mut socket_out) = accept_websocket(socket).await.map_err(|_| "websocket_handshake")?;
+    debug!("session", ip = addr; "Accepted websocket");
     let mut first_msg = loop {
         match read_ws_message(&mut socket_in).await {
             Ok(WsEvent::Ping) => { socket_out.queue_send(pong_message().0); },
             Ok(WsEvent::Message(msg)) => break Ok(msg),
-            Ok(WsEvent::Pong) | Err(_) => break Err(()),
+            Ok(WsEvent::Pong) | Err(_) => break Err("handshake"),
         }
     }?;
-    let first_msg = ToServerMsg::deserialize(&mut first_msg).await?;
+    let first_msg = ToServerMsg::deserialize(&mut first_msg).await.map_err(|_| "handshake")?;
     let (session, name, client) = if let ToServerMsg::Handshake{ session, client, name } = first_msg { (session, name, client) }
-    else { return Err(()) };
-    let name = {
-        let tmp_name = name.trim();
-        if tmp_name.is_empty() { "Unnamed".to_owned() }
-        else { tmp_name.to_owned() }
+    else { return Err("handshake") };
+    let mut name = match names::validate_name(&name) {
+        Ok(name) => name,
+        Err(reason) => {
+            info!("session", ip = addr; "Rejected name {:?}: {}", name, reason);
+            let mut msg = Vec::new();
+            ToClientMsg::HandshakeRejected { reason }.serialize(&mut msg);
+            socket_out.queue_send(OutboundWsMessage::from(&msg).0);
+            socket_out.queue_send(close_message().0);
+            let _ = socket_out.await;
+            return Ok("name_rejected");
+        }
     };
-    println!("{} joined; Ip: {}; Session: {:?}; Client {}", name, addr, session, client);
+    let session_field = session.clone().unwrap_or_else(|| "-".to_owned());
+    info!("session", ip = addr, session = session_field, client = client; "{} joined", name);
 
-    let new_id = if let Some(session) = session.as_ref() {
-        let mut suspended_players = suspended_players.lock().await;
-        let mut new_id = None;
+    let reconnect = if let Some(session) = session.as_ref() {
+        let mut suspended_players = shared.suspended_players.lock().await;
+        let mut reconnect = None;
         for i in 0..suspended_players.len() {
             let player = &suspended_players[i];
             if &player.session == session {
-                new_id = Some(player.id);
-                println!("Reconnected {} with id {}", name, player.id);
+                reconnect = Some((player.id, player.role));
+                info!("session", id = player.id, ip = addr, session = session_field; "Reconnected {}", name);
                 suspended_players.remove(i);
                 break;
             }
         }
-        new_id
+        reconnect
     } else { None };
 
     if is_emergency_stop() { futures::future::pending().await };
+    if is_shutting_down() { return Ok("shutdown") };
 
     let id;
-    let is_admin: bool;
-    if let Some(new_id) = new_id {
+    let role: Role;
+    let mut renamed_from = None;
+    let mut layout_repaired = None;
+    if let Some((new_id, old_role)) = reconnect {
         id = new_id;
-        is_admin = false; //TODO: Fix
+        role = old_role;
         to_game.send(ToGameEvent::PlayerReconnect { id }).await;
         to_game.send(ToGameEvent::SendEntireWorld { to_player: id, send_self: true }).await;
     } else {
         id = suggested_id;
-        println!("Beamin in {} with id {}", name, id);
-        let beamin_data = if let (Some(session), Some(api)) = (session.clone(), api.clone()) {
-            match beamin_request(session.clone(), api.clone()).await {
-                Ok(beamin_data) => { println!("Successfully beamed in {} ( session: {:?} )", name, session); Some(beamin_data) },
-                Err(err) => { println!("Failed to beam in {} (session: {:?})\n{}", name, session, err); None }
+        debug!("session", id = id, ip = addr, session = session_field; "Beaming in {}", name);
+        let (layout, beamout_token, beamed_in_role) = beam_in(id, &name, session.as_deref(), &shared).await;
+        role = beamed_in_role;
+        if role != Role::Player { info!("session", id = id; "{} has role {:?}", name, role); }
+
+        let requested_name = name;
+        name = shared.names.claim(id, &requested_name);
+        if name != requested_name {
+            info!("session", id = id; "{} was taken, renamed to {}", requested_name, name);
+            renamed_from = Some(requested_name);
+        }
+
+        if let Admission::Queued(ticket) = shared.slots.join(id, role.has(Permission::BypassQueue)) {
+            info!("session", id = id, ip = addr; "Server full, {} is number {} in the queue", name, ticket.position);
+            if let Err(reason) = wait_in_queue(ticket, &mut socket_in, &mut socket_out).await {
+                shared.slots.release(id);
+                shared.names.release(id);
+                return Ok(reason);
             }
-        } else { None };
-        let layout: Option<RecursivePartDescription>;
-        let beamout_token: Option<String>;
-        if let Some(beamin_data) = beamin_data {
-            layout = beamin_data.layout;
-            is_admin = beamin_data.is_admin;
-            beamout_token = Some(beamin_data.beamout_token);
-        } else {
-            layout = None;
-            is_admin = false;
-            beamout_token = None;
+            info!("session", id = id, ip = addr; "{} made it through the queue", name);
         }
 
-        let layout = layout.unwrap_or( RecursivePartDescription { kind: PartKind::Core, attachments: Vec::new() } );                                   
-        to_game.send(ToGameEvent::NewPlayer { id, name: name.clone(), parts: layout, beamout_token }).await;
+        let layout = layout.map(|layout| {
+            let (layout, problems) = validate_layout(layout);
+            for problem in &problems { warn!("beamin", id = id; "{}'s ship is invalid: {}", name, problem); }
+            if !problems.is_empty() { layout_repaired = Some(layout.attachments.iter().any(Option::is_some)); }
+            layout
+        }).unwrap_or( RecursivePartDescription { kind: PartKind::Core, attachments: Vec::new() } );
+        to_game.send(ToGameEvent::NewPlayer { id, name: name.clone(), session: session.clone(), parts: layout, beamout_token, role }).await;
         to_game.send(ToGameEvent::SendEntireWorld { to_player: id, send_self: false }).await;
     }
     let (to_writer, from_serializer) = channel::<Vec<OutboundWsMessage>>(50);
@@ -166,8 +238,17 @@ async fn socket_reader(suggested_id: u16, socket: TcpStream, addr: async_std::ne
         .name(format!("outbound_${}", id))
         .spawn(socket_writer(id, socket_out, from_serializer)).expect("Failed to launch outbound");
     to_serializer.send(vec! [ToSerializerEvent::NewWriter(id, to_writer)]).await;
+    if let Some(requested_name) = renamed_from {
+        let msg = format!("Someone is already called {}, so you're playing as {}", requested_name, name);
+        to_serializer.send(vec! [ToSerializerEvent::Message(id, ToClientMsg::ChatMessage { username: String::from("Server"), msg, color: String::from("#e270ff") })]).await;
+    }
+    if let Some(kept_parts) = layout_repaired {
+        let msg = String::from(if kept_parts { "Parts of your ship were invalid and have been removed" } else { "Your ship was invalid, so you're starting over with a fresh core" });
+        to_serializer.send(vec! [ToSerializerEvent::Message(id, ToClientMsg::ChatMessage { username: String::from("Server"), msg, color: String::from("#e270ff") })]).await;
+    }
+    let chat_name = role.decorate_name(&name);
 
-    loop {
+    let reason = loop {
         match read_ws_message(&mut socket_in).await {
             Ok(WsEvent::Message(mut msg)) => {
                 let msg = ToServerMsg::deserialize(&mut msg).await;
@@ -177,42 +258,42 @@ async fn socket_reader(suggested_id: u16, socket: TcpStream, addr: async_std::ne
                             let chunks: Vec<String> = msg.split_whitespace().map(|s| s.to_string()).collect();
                             match chunks[0].as_str() {
                                 "/shrug" => {
-                                    to_serializer.send(vec! [ToSerializerEvent::Broadcast(ToClientMsg::ChatMessage{ username: name.clone(), msg: String::from("¯\\_(ツ)_/¯"), color: String::from("#dd55ff") })]).await;
+                                    to_serializer.send(vec! [ToSerializerEvent::Broadcast(ToClientMsg::ChatMessage{ username: chat_name.clone(), msg: String::from("¯\\_(ツ)_/¯"), color: String::from("#dd55ff") })]).await;
                                 },
                                 "/disconnect" => {
                                     to_serializer.send(vec! [ToSerializerEvent::DeleteWriter(id)]).await;
-                                    break;
+                                    break "disconnect_command";
                                 },
                                 
-                                _ => {
-                                    if is_admin {
-                                        to_game.send(ToGameEvent::AdminCommand { id, command: msg.clone() }).await;
+                                command => {
+                                    if command_permission(command).map(|permission| role.has(permission)).unwrap_or(false) {
+                                        to_game.send(ToGameEvent::AdminCommand { issuer: CommandIssuer::Player(id), command: msg.clone() }).await;
                                     } else {
                                         to_serializer.send(vec! [ToSerializerEvent::Message(id, ToClientMsg::ChatMessage{ username: String::from("Server"), msg: String::from("You cannot use that command"), color: String::from("#FF0000") })]).await;
                                     }
                                 }
                             }
                         } else {
-                            to_serializer.send(vec! [ToSerializerEvent::Broadcast(ToClientMsg::ChatMessage{ username: name.clone(), msg, color: String::from("#dd55ff") })]).await;
+                            to_serializer.send(vec! [ToSerializerEvent::Broadcast(ToClientMsg::ChatMessage{ username: chat_name.clone(), msg, color: String::from("#dd55ff") })]).await;
                         }
                     },
                     Ok(ToServerMsg::RequestUpdate) => { to_serializer.send(vec! [ToSerializerEvent::RequestUpdate(id)]).await; },
                     Ok(msg) => { to_game.send(ToGameEvent::PlayerMessage { id, msg }).await; },
-                    Err(_) => break,
+                    Err(_) => break "bad_message",
                 };
             },
-            Ok(WsEvent::Ping) => { println!("Ponged"); to_serializer.send(vec! [ToSerializerEvent::SendPong(id)]).await; },
-            Ok(WsEvent::Pong) | Err(_) => break,
+            Ok(WsEvent::Ping) => { trace!("session", id = id; "Ponged"); to_serializer.send(vec! [ToSerializerEvent::SendPong(id)]).await; },
+            Ok(WsEvent::Pong) | Err(_) => break "closed",
         };
     };
 
     if let Some(session) = session { to_serializer.send(vec![ ToSerializerEvent::WriterDisconnect(id, session) ]).await; }
     else { to_serializer.send(vec![ ToSerializerEvent::DeleteWriter(id) ]).await; };
-    Ok(())
+    Ok(reason)
 }
 
 pub async fn serializer(mut to_me: Receiver<Vec<ToSerializerEvent>>, to_game: Sender<ToGameEvent>, suspended_players: SuspendedPlayers, send_to_me: Sender<Vec<ToSerializerEvent>>) {
-    println!("Hello from serializer task");
+    debug!("serializer"; "Serializer started");
     let mut writers: BTreeMap<u16, (Sender<Vec<OutboundWsMessage>>, Vec<OutboundWsMessage>, bool)> = BTreeMap::new();
     while let Some(events) = to_me.next().await {
         for event in events {
@@ -221,7 +302,7 @@ pub async fn serializer(mut to_me: Receiver<Vec<ToSerializerEvent>>, to_game: Se
                     writers.insert(id, (to_writer, Vec::new(), false));
                 },
                 ToSerializerEvent::DeleteWriter(id) => {
-                    println!("Deleted writer {}", id);
+                    debug!("serializer", id = id; "Deleted writer");
                     let mut suspended_players = suspended_players.lock().await;
                     for i in 0..suspended_players.len() {
                         if suspended_players[i].id == id {
@@ -251,6 +332,7 @@ pub async fn serializer(mut to_me: Receiver<Vec<ToSerializerEvent>>, to_game: Se
                     if let Some((_writer, queue, _request_update)) = writers.get_mut(&id) {
                         let mut out = Vec::new();
                         msg.serialize(&mut out);
+                        METRICS.record_sent(&msg, out.len(), 1);
                         let out = (&out).into();
                         queue.push(out);
                     }
@@ -258,16 +340,21 @@ pub async fn serializer(mut to_me: Receiver<Vec<ToSerializerEvent>>, to_game: Se
                 ToSerializerEvent::MulticastMessage(ids, msg) => {
                     let mut out = Vec::new();
                     msg.serialize(&mut out);
+                    let bytes = out.len();
                     let out = OutboundWsMessage::from(&out);
+                    let mut recipients = 0;
                     for id in ids {
                         if let Some((_writer, queue, _request_update)) = writers.get_mut(&id) {
                             queue.push(out.clone());
+                            recipients += 1;
                         }
                     }
+                    METRICS.record_sent(&msg, bytes, recipients);
                 },
                 ToSerializerEvent::Broadcast(msg) => {
                     let mut out = Vec::new();
                     msg.serialize(&mut out);
+                    METRICS.record_sent(&msg, out.len(), writers.len());
                     let out = OutboundWsMessage::from(&out);
                     for (_writer, queue, _request_update) in writers.values_mut() {
                         queue.push(out.clone());
@@ -284,20 +371,25 @@ pub async fn serializer(mut to_me: Receiver<Vec<ToSerializerEvent>>, to_game: Se
                                 rotation_n: part.rot_cos, rotation_i: part.rot_sin,
                             }.serialize(&mut msg);
                         };
+                        let bytes = msg.len();
                         let msg = OutboundWsMessage::from(&msg);
+                        let mut recipients = 0;
                         for (id, (_to_writer, queue, request_update)) in &mut writers {
                             if *request_update {
                                 if let Some(((player_x, player_y), (_vel_x, _vel_y), _parts, _post_simulation)) = players.get(&id) {
-                                    if (player_x - x).abs() <= 200.0 && (player_y - y).abs() <= 200.0 {
+                                    if (player_x - x).abs() <= config().view_distance && (player_y - y).abs() <= config().view_distance {
                                         queue.push(msg.clone());
+                                        recipients += 1;
                                     }
                                 }
                             }
                         };
+                        METRICS.record_sent(&ToClientMsg::MessagePack { count: 0 }, bytes, recipients);
                         if let Some((_to_writer, queue, request_update)) = writers.get_mut(id) {
                             if *request_update {
                                 let mut msg = Vec::new();
                                 post_simulation.serialize(&mut msg);
+                                METRICS.record_sent(post_simulation, msg.len(), 1);
                                 queue.push(OutboundWsMessage::from(&msg));
                             }
                         };
@@ -310,30 +402,74 @@ pub async fn serializer(mut to_me: Receiver<Vec<ToSerializerEvent>>, to_game: Se
                             rotation_n: part.rot_cos, rotation_i: part.rot_sin,
                         }.serialize(&mut msg);
                     };
+                    let bytes = msg.len();
                     let msg = OutboundWsMessage::from(&msg);
+                    let mut recipients = 0;
                     for (_to_writer, queue, request_update) in writers.values_mut() {
                         if *request_update {
                             queue.push(msg.clone());
+                            recipients += 1;
                         }
                         *request_update = false;
                     };
+                    METRICS.record_sent(&ToClientMsg::MessagePack { count: 0 }, bytes, recipients);
+                },
+                ToSerializerEvent::Shutdown(ack) => {
+                    let close = websocket::close_message_with_code(websocket::CLOSE_SERVICE_RESTART, "Server restarting");
+                    //Dropping each sender lets its writer task flush and hang up
+                    for (_id, (to_writer, mut queue, _request_update)) in std::mem::take(&mut writers) {
+                        queue.push(close.clone());
+                        to_writer.send(queue).await;
+                    }
+                    ack.send(()).await;
                 },
                 ToSerializerEvent::WriterDisconnect(id, ref_handle) => {
-                    println!("Disconnected writer {} (ref_handle: {}", id, ref_handle);
+                    debug!("serializer", id = id, session = ref_handle; "Writer disconnected");
                     if let Some(_) = writers.remove(&id) {
                         to_game.send(ToGameEvent::PlayerSuspend { id, ref_handle }).await;
                     }
                 }
             }
         }
+        let mut deepest_writer_queue = 0;
         for (to_writer, queue, _needs_update) in writers.values_mut() {
+            deepest_writer_queue = deepest_writer_queue.max(to_writer.len());
             //TODO: Maybe replace the queue system with unboundded channels?
             //Maybe return the Vecs somehow to not do constant memory allocations?
             to_writer.send(std::mem::replace(queue, Vec::new())).await;
         }
+        Metrics::set(&METRICS.writer_queue_depth_max, deepest_writer_queue);
     };
 }
 
+/// Keeps a queued connection informed of its position until a slot opens up.
+/// Errs with a disconnect reason if the client leaves first.
+async fn wait_in_queue(mut ticket: QueueTicket, socket_in: &mut TcpReader, socket_out: &mut TcpWriter) -> Result<(), &'static str> {
+    let mut position = Some(ticket.position);
+    loop {
+        if let Some(position) = position.take() {
+            let mut msg = Vec::new();
+            ToClientMsg::QueuePosition { position }.serialize(&mut msg);
+            METRICS.record_sent(&ToClientMsg::QueuePosition { position }, msg.len(), 1);
+            socket_out.queue_send(OutboundWsMessage::from(&msg).0);
+        }
+        (&mut *socket_out).await.map_err(|_| "closed")?;
+        select_biased! {
+            _ = ticket.admitted.next().fuse() => return Ok(()),
+            new_position = ticket.positions.next().fuse() => position = new_position,
+            readable = socket_in.readable().fuse() => {
+                if !readable { return Err("left_queue") };
+                match read_ws_message(socket_in).await {
+                    Ok(WsEvent::Ping) => socket_out.queue_send(pong_message().0),
+                    //Nothing a queued client says matters until it's in the game
+                    Ok(WsEvent::Message(_)) => (),
+                    Ok(WsEvent::Pong) | Err(_) => return Err("left_queue"),
+                }
+            },
+        };
+    }
+}
+
 async fn socket_writer(_id: u16, mut out: TcpWriter, mut from_serializer: Receiver<Vec<OutboundWsMessage>>) {
     loop {
         select_biased! {
@@ -358,3 +494,61 @@ async fn socket_writer(_id: u16, mut out: TcpWriter, mut from_serializer: Receiv
     async_std::task::sleep(Duration::from_secs(5)).await;
     drop(from_serializer);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beamout::BeamoutMeta;
+    use crate::mock_api::{MockApi, MockUser};
+    use crate::store::http::HttpStore;
+
+    fn shared_with(store: Option<Arc<dyn PlayerStore>>, roles: RoleTable) -> SessionShared {
+        SessionShared { store, suspended_players: Default::default(), roles, slots: Default::default(), names: Default::default() }
+    }
+
+    fn ship() -> serde_json::Value { serde_json::json!({ "kind": 0, "attachments": [{ "kind": 4, "attachments": [] }] }) }
+
+    #[async_std::test]
+    async fn beamins_take_the_higher_of_the_api_and_local_roles() {
+        let api = MockApi::start("").await.unwrap();
+        let mut moderator = MockUser::new("tok-mod");
+        moderator.role = Some(String::from("moderator"));
+        api.add_user("mod", moderator);
+        let mut admin = MockUser::new("tok-admin");
+        admin.is_admin = true;
+        api.add_user("admin", admin);
+        api.add_user("player", MockUser::new("tok-player"));
+        let mut roles = RoleTable::default();
+        roles.users.insert(String::from("tok-player"), Role::Owner);
+        //Lower than the API says, which doesn't take anything away
+        roles.users.insert(String::from("tok-admin"), Role::Moderator);
+        let shared = shared_with(Some(Arc::new(HttpStore::new(api.prefix(), String::new()))), roles);
+
+        let (_, token, role) = beam_in(1, "mod", Some("mod"), &shared).await;
+        assert_eq!((token.as_deref(), role), (Some("tok-mod"), Role::Moderator));
+        assert_eq!(beam_in(2, "admin", Some("admin"), &shared).await.2, Role::Admin);
+        assert_eq!(beam_in(3, "player", Some("player"), &shared).await.2, Role::Owner);
+        //Nothing to look anyone up by
+        assert_eq!(beam_in(4, "guest", None, &shared).await.2, Role::Player);
+        assert!(beam_in(5, "stranger", Some("stranger"), &shared).await.1.is_none());
+    }
+
+    #[async_std::test]
+    async fn beamins_restore_whichever_save_is_newest() {
+        let api = MockApi::start("").await.unwrap();
+        api.add_user("a", MockUser::new("tok-a"));
+        let store = Arc::new(HttpStore::new(api.prefix(), String::new()));
+        let shared = shared_with(Some(store.clone()), RoleTable::default());
+        let core = RecursivePartDescription::from(PartKind::Core);
+        let bigger: RecursivePartDescription = serde_json::from_value(ship()).unwrap();
+
+        assert!(beam_in(1, "a", Some("a"), &shared).await.0.is_none());
+        store.beamout("tok-a", &core, BeamoutMeta { autosave: false, saved_at: 100 }).await.unwrap();
+        store.beamout("tok-a", &bigger, BeamoutMeta { autosave: true, saved_at: 200 }).await.unwrap();
+        assert_eq!(serde_json::to_value(beam_in(1, "a", Some("a"), &shared).await.0.unwrap()).unwrap(), ship());
+        store.beamout("tok-a", &core, BeamoutMeta { autosave: false, saved_at: 300 }).await.unwrap();
+        assert!(beam_in(1, "a", Some("a"), &shared).await.0.unwrap().attachments.is_empty());
+        //Without a store everyone starts afresh
+        assert!(beam_in(1, "a", Some("a"), &shared_with(None, RoleTable::default())).await.0.is_none());
+    }
+}
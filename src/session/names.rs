@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use crate::config::config;
+
+/// Checks a handshake name, returning the trimmed name or a reason the player can act on.
+/// The wire format only carries single-byte characters, so names stick to plain ASCII.
+pub fn validate_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() { return Ok(String::from("Unnamed")) };
+    let max_length = config().max_name_length as usize;
+    if name.chars().count() > max_length {
+        return Err(format!("Names can be at most {} characters long", max_length));
+    }
+    if let Some(bad) = name.chars().find(|c| !(c.is_ascii_alphanumeric() || *c == ' ' || *c == '_' || *c == '-' || *c == '.')) {
+        return Err(if bad.is_control() { String::from("Names can't contain control characters") }
+            else { format!("Names can't contain {:?}, only letters, numbers, spaces, _ - and .", bad) });
+    }
+    if name.contains("  ") { return Err(String::from("Names can't contain more than one space in a row")) };
+    if config().reserved_names.iter().any(|reserved| reserved.eq_ignore_ascii_case(name)) {
+        return Err(format!("The name {} is reserved", name));
+    }
+    Ok(name.to_owned())
+}
+
+/// Names currently in use, compared case-insensitively, so no two players look the same in chat
+#[derive(Default)]
+pub struct NameRegistry {
+    names: Mutex<BTreeMap<String, u16>>,
+}
+impl NameRegistry {
+    /// Claims `name` for `id`, adding a number to the end if someone already has it.
+    /// Long names are cut short to make room for the number, so they stay within `max_name_length`.
+    pub fn claim(&self, id: u16, name: &str) -> String {
+        let mut names = self.names.lock().unwrap();
+        let mut candidate = name.to_owned();
+        let mut suffix = 2;
+        while names.contains_key(&candidate.to_ascii_lowercase()) {
+            let suffix_text = suffix.to_string();
+            let room = (config().max_name_length as usize).saturating_sub(suffix_text.len() + 1);
+            let base: String = name.chars().take(room).collect();
+            candidate = if base.trim_end().is_empty() { suffix_text } else { format!("{} {}", base.trim_end(), suffix_text) };
+            suffix += 1;
+        }
+        names.insert(candidate.to_ascii_lowercase(), id);
+        candidate
+    }
+    pub fn release(&self, id: u16) {
+        self.names.lock().unwrap().retain(|_name, owner| *owner != id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_checked() {
+        assert_eq!(validate_name("  alice ").unwrap(), "alice");
+        assert_eq!(validate_name("").unwrap(), "Unnamed");
+        assert!(validate_name(&"a".repeat(config().max_name_length as usize + 1)).is_err());
+        assert!(validate_name("al\u{7}ice").unwrap_err().contains("control"));
+        assert!(validate_name("al  ice").is_err());
+        assert!(validate_name("aDmIn").unwrap_err().contains("reserved"));
+    }
+
+    #[test]
+    fn taken_names_get_a_number() {
+        let names = NameRegistry::default();
+        assert_eq!(names.claim(1, "alice"), "alice");
+        assert_eq!(names.claim(2, "Alice"), "Alice 2");
+        assert_eq!(names.claim(3, "alice"), "alice 3");
+        //Someone already called what the next number would make
+        assert_eq!(names.claim(4, "bob 2"), "bob 2");
+        assert_eq!(names.claim(5, "bob"), "bob");
+        assert_eq!(names.claim(6, "bob"), "bob 3");
+        names.release(1);
+        assert_eq!(names.claim(7, "ALICE"), "ALICE");
+    }
+
+    #[test]
+    fn numbered_names_fit_the_length_limit() {
+        let max = config().max_name_length as usize;
+        let names = NameRegistry::default();
+        let long = "a".repeat(max);
+        assert_eq!(names.claim(1, &long), long);
+        let mut claimed = vec![long.clone()];
+        for id in 2..13 {
+            let name = names.claim(id, &long);
+            assert!(name.len() <= max, "{:?} is longer than {}", name, max);
+            assert!(name.starts_with("aaaa"));
+            assert!(!claimed.contains(&name));
+            claimed.push(name);
+        }
+        assert_eq!(claimed[1], format!("{} 2", "a".repeat(max - 2)));
+        assert_eq!(claimed[9], format!("{} 10", "a".repeat(max - 3)));
+        //Cutting a name short right after a space doesn't leave two in a row
+        let spaced = format!("{} b", "a".repeat(max - 3));
+        names.claim(20, &spaced);
+        assert_eq!(names.claim(21, &spaced), format!("{} 2", "a".repeat(max - 3)));
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::Mutex;
+use async_std::sync::{Sender, Receiver, TrySendError, channel};
+use crate::config::config;
+
+/// Which player ids hold one of the server's `max_players` slots, and who is waiting in line for one.
+/// A slot is held from admission until the game lets go of the player (`PlayerQuit`), so suspended players keep theirs.
+pub struct PlayerSlots {
+    /// 0 means no limit
+    max_players: u16,
+    inner: Mutex<SlotsInner>,
+}
+#[derive(Default)]
+struct SlotsInner {
+    occupied: BTreeSet<u16>,
+    waiting: VecDeque<Waiter>,
+}
+struct Waiter {
+    id: u16,
+    positions: Sender<u16>,
+    admitted: Sender<()>,
+}
+
+pub enum Admission {
+    Admitted,
+    Queued(QueueTicket),
+}
+/// Held by a queued connection. Positions are 1-based; `admitted` fires once a slot is theirs.
+pub struct QueueTicket {
+    pub position: u16,
+    pub positions: Receiver<u16>,
+    pub admitted: Receiver<()>,
+}
+
+impl Default for PlayerSlots {
+    fn default() -> PlayerSlots { PlayerSlots::new(config().max_players) }
+}
+
+impl PlayerSlots {
+    pub fn new(max_players: u16) -> PlayerSlots { PlayerSlots { max_players, inner: Default::default() } }
+
+    fn has_room(&self, inner: &SlotsInner) -> bool {
+        self.max_players == 0 || inner.occupied.len() < self.max_players as usize
+    }
+
+    /// Takes a slot if there is one (or `bypass` is set), otherwise joins the back of the queue
+    pub fn join(&self, id: u16, bypass: bool) -> Admission {
+        let mut inner = self.inner.lock().unwrap();
+        if bypass || (inner.waiting.is_empty() && self.has_room(&inner)) {
+            inner.occupied.insert(id);
+            return Admission::Admitted;
+        }
+        let (positions, positions_rx) = channel(16);
+        let (admitted, admitted_rx) = channel(1);
+        inner.waiting.push_back(Waiter { id, positions, admitted });
+        Admission::Queued(QueueTicket { position: inner.waiting.len() as u16, positions: positions_rx, admitted: admitted_rx })
+    }
+
+    /// Gives up `id`'s slot or place in line, whichever it has, and lets the queue move up
+    pub fn release(&self, id: u16) {
+        let mut inner = self.inner.lock().unwrap();
+        let was_queued = inner.waiting.iter().position(|waiter| waiter.id == id).map(|i| inner.waiting.remove(i)).is_some();
+        let was_admitted = inner.occupied.remove(&id);
+        if was_queued || was_admitted { self.promote(&mut inner); }
+    }
+
+    fn promote(&self, inner: &mut SlotsInner) {
+        while self.has_room(inner) {
+            let waiter = if let Some(waiter) = inner.waiting.pop_front() { waiter } else { break };
+            //A closed ticket means the connection is already gone. async-std only notices once the ticket is full too,
+            //so mostly it's the connection releasing its own place on the way out that keeps dead ones from holding a slot
+            match waiter.admitted.try_send(()) {
+                Ok(()) | Err(TrySendError::Full(_)) => { inner.occupied.insert(waiter.id); },
+                Err(TrySendError::Disconnected(_)) => (),
+            }
+        }
+        for (i, waiter) in inner.waiting.iter().enumerate() {
+            let _ = waiter.positions.try_send(i as u16 + 1);
+        }
+    }
+
+    pub fn queued(&self) -> usize { self.inner.lock().unwrap().waiting.len() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ticket(admission: Admission) -> QueueTicket {
+        match admission { Admission::Queued(ticket) => ticket, Admission::Admitted => panic!("admitted rather than queued") }
+    }
+    fn occupied(slots: &PlayerSlots) -> Vec<u16> { slots.inner.lock().unwrap().occupied.iter().copied().collect() }
+    /// The last position sent, if any
+    fn position(ticket: &QueueTicket) -> Option<u16> { std::iter::from_fn(|| ticket.positions.try_recv().ok()).last() }
+
+    #[test]
+    fn players_past_the_limit_wait_their_turn() {
+        let slots = PlayerSlots::new(2);
+        assert!(matches!(slots.join(1, false), Admission::Admitted));
+        assert!(matches!(slots.join(2, false), Admission::Admitted));
+        let third = ticket(slots.join(3, false));
+        let fourth = ticket(slots.join(4, false));
+        assert_eq!((third.position, fourth.position, slots.queued()), (1, 2, 2));
+
+        slots.release(1);
+        assert!(third.admitted.try_recv().is_ok());
+        assert!(fourth.admitted.try_recv().is_err());
+        assert_eq!(position(&fourth), Some(1));
+        assert_eq!(occupied(&slots), vec![2, 3]);
+        //Releasing someone who holds nothing changes nothing
+        slots.release(1);
+        assert!(fourth.admitted.try_recv().is_err());
+
+        slots.release(3);
+        assert!(fourth.admitted.try_recv().is_ok());
+        assert_eq!((occupied(&slots), slots.queued()), (vec![2, 4], 0));
+        //With room again, nobody has to queue
+        slots.release(4);
+        assert!(matches!(slots.join(5, false), Admission::Admitted));
+    }
+
+    #[test]
+    fn bypassing_players_skip_the_queue() {
+        let slots = PlayerSlots::new(1);
+        slots.join(1, false);
+        let waiting = ticket(slots.join(2, false));
+        assert!(matches!(slots.join(3, true), Admission::Admitted));
+        assert_eq!(occupied(&slots), vec![1, 3]);
+        //Over the limit, so the queue only moves once enough have left
+        slots.release(1);
+        assert!(waiting.admitted.try_recv().is_err());
+        slots.release(3);
+        assert!(waiting.admitted.try_recv().is_ok());
+        assert_eq!(occupied(&slots), vec![2]);
+    }
+
+    #[test]
+    fn the_queue_moves_up_in_order() {
+        let slots = PlayerSlots::new(1);
+        slots.join(1, false);
+        let tickets: Vec<QueueTicket> = (2..6).map(|id| ticket(slots.join(id, false))).collect();
+        assert_eq!(tickets.iter().map(|ticket| ticket.position).collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+
+        //Leaving the line moves everyone behind up
+        slots.release(3);
+        assert_eq!(tickets.iter().map(position).collect::<Vec<_>>(), vec![Some(1), None, Some(2), Some(3)]);
+        assert_eq!(slots.queued(), 3);
+        //The front of the line is next, however far back they started
+        slots.release(1);
+        assert!(tickets[0].admitted.try_recv().is_ok());
+        assert_eq!(tickets.iter().skip(2).map(position).collect::<Vec<_>>(), vec![Some(1), Some(2)]);
+        slots.release(2);
+        assert!(tickets[2].admitted.try_recv().is_ok());
+        assert!(tickets[3].admitted.try_recv().is_err());
+        assert_eq!(occupied(&slots), vec![4]);
+    }
+
+    #[test]
+    fn no_limit_admits_everyone() {
+        let slots = PlayerSlots::new(0);
+        for id in 0..100 { assert!(matches!(slots.join(id, false), Admission::Admitted)); }
+        assert_eq!(slots.queued(), 0);
+    }
+}
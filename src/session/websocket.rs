@@ -59,6 +59,21 @@ impl Stream for TcpReader {
     }
 }
 
+impl TcpReader {
+    /// Resolves once there are bytes waiting without consuming any, or to false if the socket closed.
+    /// Unlike reading a whole message this is safe to abandon partway, e.g. inside a select.
+    pub fn readable(&mut self) -> impl Future<Output=bool> + '_ {
+        futures::future::poll_fn(move |cx| {
+            if self.input_slice.is_some() { return Poll::Ready(true) };
+            match Pin::new(&mut self.socket).poll_read(cx, &mut self.input_buf) {
+                Poll::Ready(Ok(0)) | Poll::Ready(Err(_)) => Poll::Ready(false),
+                Poll::Ready(Ok(bytes_read)) => { self.input_slice = Some((0, bytes_read)); Poll::Ready(true) },
+                Poll::Pending => Poll::Pending,
+            }
+        })
+    }
+}
+
 pub struct TcpWriter {
     socket: TcpStream,
     output: VecDeque<Arc<Vec<u8>>>,
@@ -364,3 +379,15 @@ pub fn close_message() -> OutboundWsMessage {
         0b00000000,
     ]) )
 }
+
+/// "Service Restart" from RFC 6455's close code registry; clients should reconnect later
+pub const CLOSE_SERVICE_RESTART: u16 = 1012;
+pub fn close_message_with_code(code: u16, reason: &str) -> OutboundWsMessage {
+    //Control frame payloads can't exceed 125 bytes, two of which are the code
+    let mut reason_len = reason.len().min(123);
+    while !reason.is_char_boundary(reason_len) { reason_len -= 1; }
+    let mut out = vec! [ 0b10001000, (2 + reason_len) as u8 ];
+    out.extend_from_slice(&code.to_be_bytes());
+    out.extend_from_slice(&reason.as_bytes()[..reason_len]);
+    OutboundWsMessage ( Arc::new(out) )
+}
@@ -0,0 +1,102 @@
+//! Request signing for the beamin/beamout API. Rather than sending the shared secret, every request carries
+//! an HMAC-SHA256 (keyed with `api_password`) over
+//!
+//! ```text
+//! METHOD\nPATH\nTIMESTAMP\nNONCE\nsaved-at:SAVED_AT\nautosave:AUTOSAVE\nhex(sha256(BODY))
+//! ```
+//!
+//! in the headers below, all hex encoded. `SAVED_AT` and `AUTOSAVE` are the values of the beamout's `SIGNED_HEADERS`,
+//! empty when the request doesn't carry them, so those can't be rewritten in transit either. The API should reject signatures that don't match, timestamps more than
+//! `MAX_SKEW_SECONDS` away from its clock and nonces it has already seen within that window.
+//!
+//! Only depends on sha and rand so `src/bin/mock_api.rs` can include it alongside the mock API.
+
+use sha::utils::{Digest, DigestExt};
+use sha::sha256::Sha256;
+
+pub const TIMESTAMP_HEADER: &str = "x-glap-timestamp";
+pub const NONCE_HEADER: &str = "x-glap-nonce";
+pub const SIGNATURE_HEADER: &str = "x-glap-signature";
+pub const SAVED_AT_HEADER: &str = "saved-at";
+pub const AUTOSAVE_HEADER: &str = "autosave";
+/// Headers besides the signature's own that the signature covers, in the order they're signed
+pub const SIGNED_HEADERS: [&str; 2] = [SAVED_AT_HEADER, AUTOSAVE_HEADER];
+pub const MAX_SKEW_SECONDS: u64 = 300;
+
+const BLOCK_LEN: usize = 64;
+
+fn sha256(bytes: &[u8]) -> Vec<u8> { Sha256::default().digest(bytes).to_bytes() }
+
+fn to_hex(bytes: &[u8]) -> String { bytes.iter().map(|byte| format!("{:02x}", byte)).collect() }
+
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut key_block = if key.len() > BLOCK_LEN { sha256(key) } else { key.to_vec() };
+    key_block.resize(BLOCK_LEN, 0);
+    let mut inner: Vec<u8> = key_block.iter().map(|byte| byte ^ 0x36).collect();
+    inner.extend_from_slice(message);
+    let mut outer: Vec<u8> = key_block.iter().map(|byte| byte ^ 0x5c).collect();
+    outer.extend_from_slice(&sha256(&inner));
+    sha256(&outer)
+}
+
+/// The headers to attach to a request
+pub struct Signature { pub timestamp: u64, pub nonce: String, pub signature: String }
+
+/// `headers` are the request's other headers; only the `SIGNED_HEADERS` among them are looked at
+pub fn signature(secret: &str, method: &str, path: &str, timestamp: u64, nonce: &str, headers: &[(&str, &str)], body: &[u8]) -> String {
+    let mut message = format!("{}\n{}\n{}\n{}\n", method.to_ascii_uppercase(), path, timestamp, nonce);
+    for name in SIGNED_HEADERS.iter() {
+        let value = headers.iter().find(|(header, _)| header.eq_ignore_ascii_case(name)).map(|(_, value)| *value).unwrap_or("");
+        message += &format!("{}:{}\n", name, value);
+    }
+    message += &to_hex(&sha256(body));
+    to_hex(&hmac_sha256(secret.as_bytes(), message.as_bytes()))
+}
+
+pub fn now() -> u64 { std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() }
+
+/// Signs a request made now, with a fresh nonce
+pub fn sign(secret: &str, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> Signature {
+    let timestamp = now();
+    let nonce = to_hex(&rand::random::<[u8; 16]>());
+    let signature = signature(secret, method, path, timestamp, &nonce, headers, body);
+    Signature { timestamp, nonce, signature }
+}
+
+/// Checks the signature only; the timestamp window and nonce reuse are up to the caller
+#[allow(clippy::too_many_arguments)]
+pub fn verify(secret: &str, method: &str, path: &str, timestamp: u64, nonce: &str, headers: &[(&str, &str)], body: &[u8], signature_hex: &str) -> bool {
+    let expected = signature(secret, method, path, timestamp, nonce, headers, body);
+    //Constant time, so the comparison doesn't leak how much of a forged signature was right
+    expected.len() == signature_hex.len() && expected.bytes().zip(signature_hex.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        assert_eq!(to_hex(&hmac_sha256(&[0x0b; 20], b"Hi There")), "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7");
+        assert_eq!(to_hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+        //Keys longer than a block are hashed first
+        assert_eq!(to_hex(&hmac_sha256(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First")), "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54");
+    }
+
+    #[test]
+    fn signatures_cover_every_part_of_the_request() {
+        let headers = [("saved-at", "100"), ("autosave", "true")];
+        let signed = sign("secret", "POST", "/user/tok/beamout", &headers, b"{}");
+        assert!(verify("secret", "POST", "/user/tok/beamout", signed.timestamp, &signed.nonce, &headers, b"{}", &signed.signature));
+        assert!(!verify("wrong", "POST", "/user/tok/beamout", signed.timestamp, &signed.nonce, &headers, b"{}", &signed.signature));
+        assert!(!verify("secret", "GET", "/user/tok/beamout", signed.timestamp, &signed.nonce, &headers, b"{}", &signed.signature));
+        assert!(!verify("secret", "POST", "/user/other/beamout", signed.timestamp, &signed.nonce, &headers, b"{}", &signed.signature));
+        assert!(!verify("secret", "POST", "/user/tok/beamout", signed.timestamp + 1, &signed.nonce, &headers, b"{}", &signed.signature));
+        assert!(!verify("secret", "POST", "/user/tok/beamout", signed.timestamp, "00", &headers, b"{}", &signed.signature));
+        assert!(!verify("secret", "POST", "/user/tok/beamout", signed.timestamp, &signed.nonce, &headers, b"{\"a\":1}", &signed.signature));
+        assert!(!verify("secret", "POST", "/user/tok/beamout", signed.timestamp, &signed.nonce, &[("saved-at", "99"), ("autosave", "true")], b"{}", &signed.signature));
+        assert!(!verify("secret", "POST", "/user/tok/beamout", signed.timestamp, &signed.nonce, &[("saved-at", "100")], b"{}", &signed.signature));
+        //Other headers are free to change on the way
+        assert!(verify("secret", "POST", "/user/tok/beamout", signed.timestamp, &signed.nonce, &[("Autosave", "true"), ("user-agent", "x"), ("Saved-At", "100")], b"{}", &signed.signature));
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicU32, Ordering};
+use crate::world::Simulation;
+use crate::world::snapshot::PartSnapshot;
+use crate::world::planets::PlanetsSnapshot;
+use crate::{FreePart, PlayerMeta};
+use crate::config::config;
+use crate::roles::Role;
+
+/// Bumped whenever an older snapshot can't be read as the current kind
+pub const VERSION: u32 = 1;
+
+/// Everything needed to bring the world back after a restart: where the planets are along their orbits,
+/// every ship and loose part, and who was playing. Taken on a timer and on shutdown, loaded at startup.
+#[derive(Serialize, Deserialize)]
+pub struct WorldSnapshot {
+    pub version: u32,
+    /// Unix seconds
+    pub saved_at: u64,
+    /// The world seed, so a generated solar system comes back the same
+    pub seed: u64,
+    pub planets: PlanetsSnapshot,
+    pub players: Vec<PlayerSnapshot>,
+    pub free_parts: Vec<FreePartSnapshot>,
+    pub earth_cargos: u8,
+    pub ticks_til_earth_cargo_spawn: u16,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PlayerSnapshot {
+    pub id: u16,
+    pub name: String,
+    /// Restored players wait to be reconnected to with this, and are dropped if they have none
+    pub session: Option<String>,
+    pub beamout_token: Option<String>,
+    pub role: Role,
+    pub power: u32,
+    pub ship: PartSnapshot,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FreePartSnapshot {
+    /// Whether it's one of the earth cargos rather than a part that's falling apart
+    pub earth_cargo: bool,
+    pub ticks: u16,
+    pub part: PartSnapshot,
+}
+
+impl WorldSnapshot {
+    pub(crate) fn capture(simulation: &Simulation, players: &BTreeMap<u16, PlayerMeta>, free_parts: &BTreeMap<u16, FreePart>, earth_cargos: u8, ticks_til_earth_cargo_spawn: u16, seed: u64) -> WorldSnapshot {
+        WorldSnapshot {
+            version: VERSION,
+            saved_at: crate::signing::now(),
+            seed,
+            planets: simulation.planets.snapshot(),
+            players: players.values().map(|player| PlayerSnapshot {
+                id: player.id,
+                name: player.name.clone(),
+                session: player.session.clone(),
+                beamout_token: player.beamout_token.clone(),
+                role: player.role,
+                power: player.power,
+                ship: simulation.snapshot_part(player.core),
+            }).collect(),
+            free_parts: free_parts.values().filter_map(|free_part| {
+                //Whoever was holding a part lets go of it on the way back
+                let (earth_cargo, ticks) = match free_part {
+                    FreePart::Decaying(_, ticks) => (false, *ticks),
+                    FreePart::EarthCargo(_, ticks) => (true, *ticks),
+                    FreePart::Grabbed(_) => (false, config().ticks(config().part_decay_seconds)),
+                    FreePart::PlaceholderLol => return None,
+                };
+                Some(FreePartSnapshot { earth_cargo, ticks, part: simulation.snapshot_part(**free_part) })
+            }).collect(),
+            earth_cargos,
+            ticks_til_earth_cargo_spawn,
+        }
+    }
+
+    /// Puts the planets, ships and loose parts back into a freshly made simulation.
+    /// Players without a session can never reconnect, so their ships come back in pieces to decay, as if they'd just quit.
+    pub(crate) fn restore(&self, simulation: &mut Simulation) -> (BTreeMap<u16, PlayerMeta>, BTreeMap<u16, FreePart>) {
+        simulation.planets.restore(&self.planets, &mut simulation.world);
+        let mut players = BTreeMap::new();
+        let mut free_parts = BTreeMap::new();
+        for saved in &self.free_parts {
+            let handle = simulation.restore_part(&saved.part);
+            let id = simulation.world.get_part(handle).unwrap().id();
+            free_parts.insert(id, if saved.earth_cargo { FreePart::EarthCargo(handle, saved.ticks) } else { FreePart::Decaying(handle, saved.ticks) });
+        }
+        for saved in &self.players {
+            let core = simulation.restore_part(&saved.ship);
+            if saved.session.is_none() {
+                let mut detached = BTreeSet::new();
+                simulation.world.recursive_detach_all(core, &mut None, &mut simulation.joints, &mut detached);
+                for handle in detached {
+                    let id = simulation.world.get_part(handle).unwrap().id();
+                    free_parts.insert(id, FreePart::Decaying(handle, config().ticks(config().part_decay_seconds)));
+                }
+                //Nobody is connected yet to be told it's gone
+                simulation.delete_parts_recursive(core);
+                continue;
+            }
+            let mut player = PlayerMeta::new(saved.id, core, saved.name.clone(), saved.session.clone(), saved.beamout_token.clone(), saved.role);
+            simulation.world.recurse_part_mut(core, Default::default(), &mut |mut handle| handle.join_to(&mut player));
+            player.power = saved.power.min(player.max_power);
+            players.insert(saved.id, player);
+        }
+        (players, free_parts)
+    }
+}
+
+static NEXT_SAVE: AtomicU32 = AtomicU32::new(0);
+
+/// Written next to the destination and renamed over it, so a crash mid-write leaves the last snapshot intact.
+/// Every save gets its own temporary file, so a periodic save still running when the shutdown one starts can't write into it
+pub fn save(path: &str, snapshot: &WorldSnapshot) -> Result<(), String> {
+    let json = serde_json::to_vec(snapshot).map_err(|err| format!("Failed to serialize the snapshot: {}", err))?;
+    let tmp = format!("{}.{}.{}.tmp", path, std::process::id(), NEXT_SAVE.fetch_add(1, Ordering::Relaxed));
+    std::fs::write(&tmp, json).map_err(|err| format!("Failed to write {}: {}", tmp, err))?;
+    std::fs::rename(&tmp, path).map_err(|err| format!("Failed to replace {}: {}", path, err))
+}
+
+/// `None` if there's no snapshot yet
+pub fn load(path: &str) -> Result<Option<WorldSnapshot>, String> {
+    let json = match std::fs::read_to_string(path) {
+        Ok(json) => json,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(format!("Failed to read {}: {}", path, err)),
+    };
+    let value: serde_json::Value = serde_json::from_str(&json).map_err(|err| format!("Failed to parse {}: {}", path, err))?;
+    let version = value.get("version").and_then(serde_json::Value::as_u64).unwrap_or(0);
+    if version != VERSION as u64 { return Err(format!("{} is a version {} snapshot, this server reads version {}", path, version, VERSION)); }
+    serde_json::from_value(value).map(Some).map_err(|err| format!("Failed to parse {}: {}", path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task::block_on;
+    use crate::codec::ToServerMsg;
+    use crate::game::harness::Harness;
+    use crate::world::parts::{PartKind, RecursivePartDescription};
+
+    fn small_ship() -> RecursivePartDescription {
+        RecursivePartDescription { kind: PartKind::Core, attachments: vec![Some(PartKind::Hub.into()), None, Some(PartKind::Cargo.into())] }
+    }
+
+    fn restored(snapshot: &WorldSnapshot) -> Harness {
+        let mut harness = Harness::new();
+        block_on(harness.game.restore(snapshot));
+        harness
+    }
+
+    #[test]
+    fn players_with_a_session_come_back_waiting_to_reconnect() {
+        let mut harness = Harness::new();
+        let id = harness.spawn_player("alice", small_ship());
+        let player = harness.game.players.get_mut(&id).unwrap();
+        player.session = Some(String::from("session-a"));
+        player.beamout_token = Some(String::from("tok-a"));
+        player.power = 123;
+        let ship = serde_json::to_value(harness.ship(id)).unwrap();
+        let snapshot = harness.game.snapshot();
+
+        let harness = restored(&snapshot);
+        let player = &harness.game.players[&id];
+        assert_eq!((player.name.as_str(), player.session.as_deref(), player.beamout_token.as_deref(), player.power), ("alice", Some("session-a"), Some("tok-a"), 123));
+        assert_eq!(harness.part_count(id), 3);
+        assert_eq!(serde_json::to_value(harness.ship(id)).unwrap(), ship);
+    }
+
+    #[test]
+    fn sessionless_players_come_back_as_decaying_parts() {
+        let mut harness = Harness::new();
+        let hub = RecursivePartDescription { kind: PartKind::Hub, attachments: vec![None, None, Some(PartKind::Cargo.into())] };
+        let id = harness.spawn_player("alice", RecursivePartDescription { kind: PartKind::Core, attachments: vec![Some(hub), None, Some(PartKind::Cargo.into())] });
+        let snapshot = harness.game.snapshot();
+        assert_eq!(snapshot.players.len(), 1);
+
+        let mut harness = restored(&snapshot);
+        assert!(!harness.game.players.contains_key(&id));
+        //The core goes, like it does when a player quits, and everything that was on it floats free to be grabbed
+        let world = &harness.game.simulation.world;
+        let kinds: Vec<PartKind> = harness.game.free_parts.values().map(|part| world.get_part(**part).unwrap().kind()).collect();
+        assert_eq!(kinds.len(), 3);
+        assert!(!kinds.contains(&PartKind::Core));
+        for part in harness.game.free_parts.values() {
+            assert!(matches!(part, FreePart::Decaying(_, ticks) if *ticks == config().ticks(config().part_decay_seconds)));
+            assert!(world.get_part(**part).unwrap().attachments().iter().all(Option::is_none));
+        }
+        let other = harness.spawn_player("bob", PartKind::Core.into());
+        let hub = harness.game.free_parts.iter().find(|(_, part)| harness.game.simulation.world.get_part(***part).unwrap().kind() == PartKind::Hub).map(|(id, _)| *id).unwrap();
+        harness.input(other, ToServerMsg::CommitGrab { grabbed_id: hub, x: 0.0, y: 5.0 });
+        assert!(matches!(harness.game.free_parts.get(&hub), Some(FreePart::Grabbed(_))));
+    }
+
+    #[test]
+    fn grabbed_parts_are_let_go() {
+        let mut harness = Harness::new();
+        let id = harness.spawn_player("alice", small_ship());
+        let simulation = &harness.game.simulation;
+        let hub = simulation.world.get_part(harness.game.players[&id].core).unwrap().attachments()[0].as_ref().map(|hub| simulation.world.get_part(**hub).unwrap().id()).unwrap();
+        harness.input(id, ToServerMsg::CommitGrab { grabbed_id: hub, x: 0.0, y: 5.0 });
+        assert!(matches!(harness.game.free_parts.get(&hub), Some(FreePart::Grabbed(_))));
+        harness.game.players.get_mut(&id).unwrap().session = Some(String::from("session-a"));
+        let snapshot = harness.game.snapshot();
+        assert!(snapshot.free_parts.iter().any(|part| !part.earth_cargo && part.ticks == config().ticks(config().part_decay_seconds)));
+
+        let harness = restored(&snapshot);
+        assert!(matches!(harness.game.free_parts.get(&hub), Some(FreePart::Decaying(_, _))));
+        assert!(harness.game.players[&id].grabbed_part.is_none());
+        assert_eq!(harness.part_count(id), 2);
+    }
+
+    #[test]
+    fn earth_cargos_are_counted_back_in() {
+        let mut harness = Harness::new();
+        harness.step(config().ticks(config().earth_cargo_spawn_seconds) as u32 * 3 + 1);
+        assert_eq!(harness.game.earth_cargos, 3);
+        let snapshot = harness.game.snapshot();
+        assert_eq!(snapshot.free_parts.iter().filter(|part| part.earth_cargo).count(), 3);
+
+        let mut harness = restored(&snapshot);
+        assert_eq!(harness.game.earth_cargos, 3);
+        assert_eq!(harness.game.ticks_til_earth_cargo_spawn, snapshot.ticks_til_earth_cargo_spawn.max(1));
+        assert_eq!(harness.game.free_parts.values().filter(|part| matches!(part, FreePart::EarthCargo(_, _))).count(), 3);
+        //And keeps counting from there rather than from nothing
+        harness.step(config().ticks(config().earth_cargo_spawn_seconds) as u32);
+        assert_eq!(harness.game.earth_cargos, 4);
+    }
+
+    #[test]
+    fn only_this_version_is_loaded() {
+        let dir = std::env::temp_dir().join(format!("glap-snapshot-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("snapshot.json").to_str().unwrap().to_owned();
+        assert!(load(&path).unwrap().is_none());
+
+        let mut harness = Harness::new();
+        harness.spawn_player("alice", small_ship());
+        save(&path, &harness.game.snapshot()).unwrap();
+        save(&path, &harness.game.snapshot()).unwrap();
+        assert_eq!(load(&path).unwrap().unwrap().players.len(), 1);
+        //Nothing left behind from writing it
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let mut old: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        old["version"] = serde_json::json!(VERSION - 1);
+        std::fs::write(&path, old.to_string()).unwrap();
+        assert!(load(&path).err().unwrap().contains("version"));
+        old.as_object_mut().unwrap().remove("version");
+        std::fs::write(&path, old.to_string()).unwrap();
+        assert!(load(&path).is_err());
+        std::fs::write(&path, "{").unwrap();
+        assert!(load(&path).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,190 @@
+use std::path::{Path, PathBuf};
+use async_std::sync::Mutex;
+use sha::utils::{Digest, DigestExt};
+use super::{PlayerStore, StoreFuture};
+use crate::beamout::{BeaminResponse, BeamoutError, BeamoutMeta, Checkpoint};
+use crate::world::parts::RecursivePartDescription;
+
+/// Keeps each player's ship as a JSON file in a local directory, for servers without the web backend.
+/// The session a client sends is its identity; the beamout token (and file name) is a hash of it,
+/// so the directory never contains anything a player could log in with.
+pub struct FileStore {
+    dir: PathBuf,
+    /// Autosaves and beamouts for the same player read and rewrite the same file
+    writing: Mutex<()>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct SavedShip {
+    #[serde(default)]
+    layout: Option<RecursivePartDescription>,
+    /// When `layout` was saved, in Unix seconds
+    #[serde(default)]
+    saved: u64,
+    #[serde(default)]
+    autosave: Option<Checkpoint>,
+}
+
+async fn read_saved(path: &Path) -> Result<Option<SavedShip>, String> {
+    match async_std::fs::read_to_string(path).await {
+        Ok(file) => serde_json::from_str(&file).map(Some).map_err(|err| format!("{} is corrupt: {}", path.display(), err)),
+        //First time we've seen them
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(format!("Can't read {}: {}", path.display(), err)),
+    }
+}
+
+impl FileStore {
+    pub fn new(dir: impl Into<PathBuf>) -> FileStore { FileStore { dir: dir.into(), writing: Mutex::new(()) } }
+
+    pub fn token_for(session: &str) -> String {
+        sha::sha256::Sha256::default().digest(session.as_bytes()).to_hex()
+    }
+    fn path_for(&self, beamout_token: &str) -> Option<PathBuf> {
+        //Tokens come back from our own beamins, but never let one point outside the directory
+        if beamout_token.is_empty() || !beamout_token.chars().all(|c| c.is_ascii_hexdigit()) { return None };
+        Some(self.dir.join(format!("{}.json", beamout_token)))
+    }
+}
+
+impl PlayerStore for FileStore {
+    fn describe(&self) -> String { format!("files in {}", self.dir.display()) }
+
+    fn ping(&self) -> StoreFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            async_std::fs::create_dir_all(&self.dir).await.map_err(|err| format!("Can't create {}: {}", self.dir.display(), err))
+        })
+    }
+
+    fn beamin<'a>(&'a self, session: &'a str) -> StoreFuture<'a, Result<BeaminResponse, String>> {
+        Box::pin(async move {
+            let beamout_token = Self::token_for(session);
+            let path = self.path_for(&beamout_token).unwrap();
+            let saved = read_saved(&path).await?.unwrap_or_default();
+            let saved_at = saved.layout.as_ref().map(|_| saved.saved);
+            Ok(BeaminResponse { is_admin: false, role: None, beamout_token, layout: saved.layout, saved_at, autosave: saved.autosave })
+        })
+    }
+
+    fn beamout<'a>(&'a self, beamout_token: &'a str, layout: &'a RecursivePartDescription, meta: BeamoutMeta) -> StoreFuture<'a, Result<(), BeamoutError>> {
+        Box::pin(async move {
+            let path = self.path_for(beamout_token).ok_or_else(|| BeamoutError { retryable: false, reason: String::from("Malformed beamout token") })?;
+            let _writing = self.writing.lock().await;
+            let mut saved = read_saved(&path).await.unwrap_or_else(|err| { warn!("store"; "Overwriting: {}", err); None }).unwrap_or_default();
+            //Whatever is already there may be newer than this, if this one spent a while in the outbox
+            if meta.saved_at < saved.saved || saved.autosave.as_ref().map(|autosave| meta.autosave && meta.saved_at < autosave.saved_at).unwrap_or(false) { return Ok(()) };
+            if meta.autosave {
+                saved.autosave = Some(Checkpoint { layout: layout.clone(), saved_at: meta.saved_at });
+            } else {
+                saved.layout = Some(layout.clone());
+                saved.saved = meta.saved_at;
+                if saved.autosave.as_ref().map(|autosave| autosave.saved_at <= meta.saved_at).unwrap_or(false) { saved.autosave = None; }
+            }
+            let json = serde_json::to_vec(&saved).unwrap();
+            let tmp = path.with_extension("json.tmp");
+            let io_error = |err: std::io::Error| BeamoutError { retryable: true, reason: format!("Can't write {}: {}", path.display(), err) };
+            async_std::fs::write(&tmp, json).await.map_err(io_error)?;
+            async_std::fs::rename(&tmp, &path).await.map_err(io_error)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ship(parts: usize) -> RecursivePartDescription {
+        use crate::world::parts::PartKind;
+        RecursivePartDescription { kind: PartKind::Core, attachments: (0..parts).map(|_| Some(PartKind::Cargo.into())).collect() }
+    }
+    fn parts(layout: &RecursivePartDescription) -> usize { crate::world::layout::count_parts(layout) }
+
+    /// A fresh, empty store in its own directory
+    async fn store(name: &str) -> (FileStore, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("glap-file-store-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = FileStore::new(&dir);
+        store.ping().await.unwrap();
+        (store, dir)
+    }
+
+    #[async_std::test]
+    async fn new_sessions_start_with_nothing() {
+        let (store, dir) = store("new").await;
+        let beamin = store.beamin("someone new").await.unwrap();
+        assert_eq!(beamin.beamout_token, FileStore::token_for("someone new"));
+        assert!(beamin.layout.is_none() && beamin.saved_at.is_none() && beamin.autosave.is_none());
+        //Different sessions never share a ship, and the session itself isn't kept anywhere
+        assert_ne!(store.beamin("someone else").await.unwrap().beamout_token, beamin.beamout_token);
+        assert!(!beamin.beamout_token.contains("someone"));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[async_std::test]
+    async fn beamouts_come_back_on_the_next_beamin() {
+        let (store, dir) = store("round-trip").await;
+        let token = store.beamin("a").await.unwrap().beamout_token;
+        store.beamout(&token, &ship(2), BeamoutMeta { autosave: false, saved_at: 100 }).await.unwrap();
+        let beamin = store.beamin("a").await.unwrap();
+        assert_eq!(parts(beamin.layout.as_ref().unwrap()), 3);
+        assert_eq!(beamin.saved_at, Some(100));
+        assert!(!beamin.autosave_is_newest());
+
+        //Tokens that couldn't have come from a beamin aren't written anywhere
+        for token in &["", "../escape", "zz"] {
+            assert!(!store.beamout(token, &ship(1), BeamoutMeta { autosave: false, saved_at: 100 }).await.unwrap_err().retryable);
+        }
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[async_std::test]
+    async fn autosaves_are_kept_alongside_beamouts() {
+        let (store, dir) = store("autosave").await;
+        let token = FileStore::token_for("a");
+        let save = |parts: usize, autosave: bool, saved_at: u64| {
+            let (store, token) = (&store, &token);
+            async move { store.beamout(token, &ship(parts), BeamoutMeta { autosave, saved_at }).await.unwrap() }
+        };
+
+        save(1, false, 100).await;
+        save(2, true, 200).await;
+        let beamin = store.beamin("a").await.unwrap();
+        assert!(beamin.autosave_is_newest());
+        assert_eq!(parts(&beamin.autosave.as_ref().unwrap().layout), 3);
+        assert_eq!(parts(beamin.layout.as_ref().unwrap()), 2);
+
+        //Anything older than what's there turned up late, from the outbox
+        save(3, true, 150).await;
+        save(3, false, 50).await;
+        let beamin = store.beamin("a").await.unwrap();
+        assert_eq!((parts(beamin.layout.as_ref().unwrap()), beamin.saved_at), (2, Some(100)));
+        assert_eq!(beamin.autosave.as_ref().unwrap().saved_at, 200);
+
+        //A deliberate beamout supersedes the autosaves before it
+        save(4, false, 300).await;
+        let beamin = store.beamin("a").await.unwrap();
+        assert!(beamin.autosave.is_none());
+        assert_eq!((parts(beamin.layout.as_ref().unwrap()), beamin.saved_at), (5, Some(300)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[async_std::test]
+    async fn files_are_replaced_whole() {
+        let (store, dir) = store("atomic").await;
+        let token = FileStore::token_for("a");
+        for saved_at in 0..5 { store.beamout(&token, &ship(1), BeamoutMeta { autosave: saved_at % 2 == 1, saved_at }).await.unwrap(); }
+        //Written to a temporary file and renamed over the old one, which leaves nothing else behind
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+        assert_eq!(files, vec![format!("{}.json", token)]);
+
+        //A file that's been mangled anyway fails the beamin rather than handing out an empty ship, and the next beamout replaces it
+        let path = dir.join(&files[0]);
+        std::fs::write(&path, "{\"layout\": ").unwrap();
+        assert!(store.beamin("a").await.is_err());
+        store.beamout(&token, &ship(3), BeamoutMeta { autosave: false, saved_at: 10 }).await.unwrap();
+        assert_eq!(parts(store.beamin("a").await.unwrap().layout.as_ref().unwrap()), 4);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,236 @@
+use super::{PlayerStore, StoreFuture};
+use crate::beamout::{BeaminResponse, BeamoutError, BeamoutMeta};
+use crate::world::parts::RecursivePartDescription;
+use crate::signing;
+
+/// The web backend: `GET <prefix>/ping`, `GET <prefix>/session/<session>/beamin` and `POST <prefix>/user/<token>/beamout`.
+/// Beamouts carry a `saved-at` header (Unix seconds) and autosaves an `autosave: true` header.
+/// Everything but the ping is signed with the shared secret, see `signing`.
+pub struct HttpStore { prefix: String, beamout: String, beamin: String, secret: String }
+
+impl HttpStore {
+    pub fn new(prefix: String, secret: String) -> HttpStore {
+        HttpStore {
+            beamout: prefix.clone() + "/user/^^^^/beamout",
+            beamin: prefix.clone() + "/session/^^^^/beamin",
+            prefix, secret,
+        }
+    }
+
+    /// Attaches `headers` along with the signature over them
+    fn signed(&self, mut request: surf::RequestBuilder, method: &str, uri: &str, headers: &[(&str, &str)], body: &[u8]) -> Result<surf::RequestBuilder, String> {
+        let path = surf::url::Url::parse(uri).map_err(|err| format!("Bad API url {}: {}", uri, err))?.path().to_owned();
+        let signed = signing::sign(&self.secret, method, &path, headers, body);
+        for (name, value) in headers { request = request.header(*name, *value); }
+        Ok(request
+            .header(signing::TIMESTAMP_HEADER, signed.timestamp.to_string())
+            .header(signing::NONCE_HEADER, signed.nonce)
+            .header(signing::SIGNATURE_HEADER, signed.signature))
+    }
+}
+
+impl PlayerStore for HttpStore {
+    fn describe(&self) -> String { format!("API at {}", self.prefix) }
+
+    fn ping(&self) -> StoreFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            let mut res = surf::get(self.prefix.clone() + "/ping").await.map_err(|err| format!("Ping failed to connect: {}", err))?;
+            if res.status().is_success() && res.body_string().await.map(|body| body == "PONG").unwrap_or(false) { Ok(()) }
+            else { Err(format!("Ping did not PONG: {}", res.status())) }
+        })
+    }
+
+    fn beamin<'a>(&'a self, session: &'a str) -> StoreFuture<'a, Result<BeaminResponse, String>> {
+        Box::pin(async move {
+            let uri = self.beamin.replacen("^^^^", session, 1);
+            let mut response = self.signed(surf::get(&uri), "GET", &uri, &[], &[])?.await.map_err(|err| err.to_string())?;
+            if response.status().is_success() {
+                let body_json = response.body_json().await.map_err(|err| err.to_string())?;
+                serde_json::from_value::<BeaminResponse>(body_json).map_err(|err| err.to_string())
+            } else { Err(format!("Beamin response does not indicate success: {}", response.status())) }
+        })
+    }
+
+    fn beamout<'a>(&'a self, beamout_token: &'a str, layout: &'a RecursivePartDescription, meta: BeamoutMeta) -> StoreFuture<'a, Result<(), BeamoutError>> {
+        Box::pin(async move {
+            let uri = self.beamout.replacen("^^^^", beamout_token, 1);
+            let body = serde_json::to_string(layout).unwrap();
+            let saved_at = meta.saved_at.to_string();
+            let mut headers = vec![(signing::SAVED_AT_HEADER, saved_at.as_str())];
+            if meta.autosave { headers.push((signing::AUTOSAVE_HEADER, "true")); }
+            let request = self.signed(surf::post(&uri), "POST", &uri, &headers, body.as_bytes())
+                .map_err(|reason| BeamoutError { retryable: false, reason })?;
+            match request.body(body).await {
+                Ok(res) if res.status().is_success() => Ok(()),
+                //The API understood and refused, so the same request will be refused again. Timeouts and rate limits are worth retrying
+                Ok(res) if res.status().is_client_error() && res.status() != surf::StatusCode::RequestTimeout && res.status() != surf::StatusCode::TooManyRequests =>
+                    Err(BeamoutError { retryable: false, reason: format!("API refused the beamout: {}", res.status()) }),
+                Ok(res) => Err(BeamoutError { retryable: true, reason: format!("API responded {}", res.status()) }),
+                Err(err) => Err(BeamoutError { retryable: true, reason: format!("Post failed: {}", err) }),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_api::{MockApi, MockUser, Endpoint};
+    use crate::roles::Role;
+
+    const BEAMOUT: BeamoutMeta = BeamoutMeta { autosave: false, saved_at: 100 };
+
+    fn ship() -> serde_json::Value { serde_json::json!({ "kind": 0, "attachments": [{ "kind": 4, "attachments": [null, null, null] }, null, null, null] }) }
+
+    #[async_std::test]
+    async fn beamin_returns_the_saved_ship() {
+        let api = MockApi::start("hunter2").await.unwrap();
+        let mut user = MockUser::new("tok-a");
+        user.layout = Some(ship());
+        api.add_user("a", user);
+        let store = HttpStore::new(api.prefix(), String::from("hunter2"));
+        store.ping().await.unwrap();
+
+        let beamin = store.beamin("a").await.unwrap();
+        assert_eq!(beamin.beamout_token, "tok-a");
+        assert_eq!(serde_json::to_value(beamin.layout.unwrap()).unwrap(), ship());
+        assert!(store.beamin("nobody").await.is_err());
+        assert!(HttpStore::new(api.prefix(), String::from("wrong")).beamin("a").await.is_err());
+    }
+
+    #[async_std::test]
+    async fn replayed_and_stale_requests_are_refused() {
+        let api = MockApi::start("hunter2").await.unwrap();
+        api.add_user("a", MockUser::new("tok-a"));
+        let uri = api.prefix() + "/session/a/beamin";
+        let send = |timestamp: u64, nonce: &'static str| {
+            let signature = signing::signature("hunter2", "GET", "/session/a/beamin", timestamp, nonce, &[], &[]);
+            surf::get(&uri).header(signing::TIMESTAMP_HEADER, timestamp.to_string()).header(signing::NONCE_HEADER, nonce).header(signing::SIGNATURE_HEADER, signature)
+        };
+        let now = signing::now();
+        assert!(send(now, "aa").await.unwrap().status().is_success());
+        //A captured request can't be sent again
+        assert_eq!(send(now, "aa").await.unwrap().status(), surf::StatusCode::Unauthorized);
+        assert_eq!(send(now - signing::MAX_SKEW_SECONDS - 10, "bb").await.unwrap().status(), surf::StatusCode::Unauthorized);
+        assert!(send(now, "bb").await.unwrap().status().is_success());
+    }
+
+    #[async_std::test]
+    async fn rewritten_beamout_headers_are_refused() {
+        let api = MockApi::start("hunter2").await.unwrap();
+        api.add_user("a", MockUser::new("tok-a"));
+        let uri = api.prefix() + "/user/tok-a/beamout";
+        let body = ship().to_string();
+        let signed = signing::sign("hunter2", "POST", "/user/tok-a/beamout", &[(signing::SAVED_AT_HEADER, "100")], body.as_bytes());
+        let send = |saved_at: &str, autosave: bool| {
+            let mut request = surf::post(&uri).header(signing::TIMESTAMP_HEADER, signed.timestamp.to_string())
+                .header(signing::NONCE_HEADER, signed.nonce.clone()).header(signing::SIGNATURE_HEADER, signed.signature.clone())
+                .header(signing::SAVED_AT_HEADER, saved_at.to_owned());
+            if autosave { request = request.header(signing::AUTOSAVE_HEADER, "true"); }
+            request.body(body.clone())
+        };
+        //Turned into an autosave, or backdated, on the way
+        assert_eq!(send("100", true).await.unwrap().status(), surf::StatusCode::Unauthorized);
+        assert_eq!(send("1", false).await.unwrap().status(), surf::StatusCode::Unauthorized);
+        assert!(send("100", false).await.unwrap().status().is_success());
+        assert_eq!(api.layout("a"), Some(ship()));
+    }
+
+    #[async_std::test]
+    async fn beamin_survives_latency() {
+        let api = MockApi::start("").await.unwrap();
+        api.set_auto_register(true);
+        api.set_latency(std::time::Duration::from_millis(200));
+        let beamin = HttpStore::new(api.prefix(), String::new()).beamin("slow").await.unwrap();
+        assert_eq!(beamin.beamout_token, "token-slow");
+        assert!(beamin.layout.is_none());
+    }
+
+    #[async_std::test]
+    async fn beamout_is_saved_for_the_next_beamin() {
+        let api = MockApi::start("hunter2").await.unwrap();
+        api.add_user("a", MockUser::new("tok-a"));
+        let store = HttpStore::new(api.prefix(), String::from("hunter2"));
+        let layout: RecursivePartDescription = serde_json::from_value(ship()).unwrap();
+
+        store.beamout("tok-a", &layout, BEAMOUT).await.unwrap();
+        assert_eq!(api.layout("a"), Some(ship()));
+        assert_eq!(api.beamouts(), vec![(String::from("tok-a"), ship())]);
+        let beamin = store.beamin("a").await.unwrap();
+        assert_eq!(serde_json::to_value(beamin.layout.unwrap()).unwrap(), ship());
+    }
+
+    #[async_std::test]
+    async fn beamin_picks_the_newest_save() {
+        let api = MockApi::start("").await.unwrap();
+        api.add_user("a", MockUser::new("tok-a"));
+        let store = HttpStore::new(api.prefix(), String::new());
+        let core: RecursivePartDescription = serde_json::from_value(serde_json::json!({ "kind": 0, "attachments": [] })).unwrap();
+        let layout: RecursivePartDescription = serde_json::from_value(ship()).unwrap();
+
+        store.beamout("tok-a", &core, BEAMOUT).await.unwrap();
+        store.beamout("tok-a", &layout, BeamoutMeta { autosave: true, saved_at: 200 }).await.unwrap();
+        assert_eq!(api.autosave("a"), Some(ship()));
+        let beamin = store.beamin("a").await.unwrap();
+        assert!(beamin.autosave_is_newest());
+        assert_eq!(serde_json::to_value(beamin.autosave.unwrap().layout).unwrap(), ship());
+
+        store.beamout("tok-a", &core, BeamoutMeta { autosave: false, saved_at: 300 }).await.unwrap();
+        assert!(!store.beamin("a").await.unwrap().autosave_is_newest());
+    }
+
+    #[async_std::test]
+    async fn beamout_failures_say_whether_to_retry() {
+        let api = MockApi::start("").await.unwrap();
+        api.add_user("a", MockUser::new("tok-a"));
+        let store = HttpStore::new(api.prefix(), String::new());
+        let layout: RecursivePartDescription = serde_json::from_value(ship()).unwrap();
+
+        api.respond_next(Endpoint::Beamout, 503, "Maintenance");
+        api.respond_next(Endpoint::Beamout, 429, "Slow down");
+        api.respond_next(Endpoint::Beamout, 400, "Bad layout");
+        assert!(store.beamout("tok-a", &layout, BEAMOUT).await.unwrap_err().retryable);
+        assert!(store.beamout("tok-a", &layout, BEAMOUT).await.unwrap_err().retryable);
+        assert!(!store.beamout("tok-a", &layout, BEAMOUT).await.unwrap_err().retryable);
+        assert!(!store.beamout("tok-unknown", &layout, BEAMOUT).await.unwrap_err().retryable);
+        store.beamout("tok-a", &layout, BEAMOUT).await.unwrap();
+        assert_eq!(api.requests(Endpoint::Beamout), 5);
+        assert_eq!(api.beamouts().len(), 1);
+    }
+
+    #[async_std::test]
+    async fn beamin_grants_roles() {
+        let api = MockApi::start("").await.unwrap();
+        let mut admin = MockUser::new("tok-admin");
+        admin.is_admin = true;
+        api.add_user("admin", admin);
+        let mut moderator = MockUser::new("tok-mod");
+        moderator.role = Some(String::from("moderator"));
+        api.add_user("mod", moderator);
+        api.add_user("player", MockUser::new("tok-player"));
+        let store = HttpStore::new(api.prefix(), String::new());
+
+        assert_eq!(store.beamin("admin").await.unwrap().role(), Role::Admin);
+        assert_eq!(store.beamin("mod").await.unwrap().role(), Role::Moderator);
+        assert_eq!(store.beamin("player").await.unwrap().role(), Role::Player);
+    }
+
+    #[async_std::test]
+    async fn unknown_roles_keep_the_ship() {
+        let api = MockApi::start("").await.unwrap();
+        let mut newer = MockUser::new("tok-a");
+        newer.role = Some(String::from("superuser"));
+        newer.layout = Some(ship());
+        api.add_user("a", newer);
+        let mut admin = MockUser::new("tok-b");
+        admin.role = Some(String::from("superuser"));
+        admin.is_admin = true;
+        api.add_user("b", admin);
+        let store = HttpStore::new(api.prefix(), String::new());
+
+        let beamin = store.beamin("a").await.unwrap();
+        assert_eq!(beamin.role(), Role::Player);
+        assert_eq!(serde_json::to_value(beamin.layout.unwrap()).unwrap(), ship());
+        assert_eq!(store.beamin("b").await.unwrap().role(), Role::Admin);
+    }
+}
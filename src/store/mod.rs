@@ -0,0 +1,78 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use crate::config::ServerConfig;
+use crate::beamout::{BeaminResponse, BeamoutError, BeamoutMeta};
+use crate::world::parts::RecursivePartDescription;
+
+pub mod http;
+pub mod file;
+pub use http::HttpStore;
+pub use file::FileStore;
+
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Where players' ships live between sessions. Beamin trades the session a client hands us for its saved
+/// layout and a beamout token; beamout saves a layout under that token.
+pub trait PlayerStore: Send + Sync {
+    /// For logs, e.g. "API at https://..."
+    fn describe(&self) -> String;
+    /// Checked once at startup, so an outage shows up in the logs straight away. A store that fails it is still used;
+    /// beamins fail until it's back, and the outbox holds on to beamouts until then
+    fn ping(&self) -> StoreFuture<'_, Result<(), String>>;
+    fn beamin<'a>(&'a self, session: &'a str) -> StoreFuture<'a, Result<BeaminResponse, String>>;
+    /// Stores should keep autosaves apart from deliberate beamouts, and hand back the newest of each on beamin
+    fn beamout<'a>(&'a self, beamout_token: &'a str, layout: &'a RecursivePartDescription, meta: BeamoutMeta) -> StoreFuture<'a, Result<(), BeamoutError>>;
+}
+
+/// The store `config` asks for, whether or not it's up right now. Without one, players aren't beamed in or out.
+pub async fn connect(config: &ServerConfig) -> Option<Arc<dyn PlayerStore>> {
+    let store: Arc<dyn PlayerStore> = if let Some(prefix) = &config.api {
+        Arc::new(HttpStore::new(prefix.clone(), config.api_password.clone()))
+    } else if let Some(dir) = &config.store_dir {
+        Arc::new(FileStore::new(dir))
+    } else {
+        warn!("store"; "No API or store_dir configured, players will not be beamed in or out");
+        return None;
+    };
+    info!("store"; "Checking {}", store.describe());
+    match store.ping().await {
+        Ok(()) => info!("store"; "Store ready"),
+        Err(err) => error!("store"; "{}, beamins will fail and beamouts wait in the outbox until it's back", err),
+    }
+    Some(store)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_api::{MockApi, Endpoint};
+
+    fn api_config(prefix: String) -> ServerConfig { ServerConfig { api: Some(prefix), ..Default::default() } }
+
+    #[async_std::test]
+    async fn connects_to_a_live_api() {
+        let api = MockApi::start("").await.unwrap();
+        assert!(connect(&api_config(api.prefix())).await.is_some());
+        assert_eq!(api.requests(Endpoint::Ping), 1);
+    }
+
+    #[async_std::test]
+    async fn keeps_the_store_when_the_api_is_down() {
+        let api = MockApi::start("").await.unwrap();
+        api.set_down(true);
+        assert!(connect(&api_config(api.prefix())).await.is_some());
+        api.set_down(false);
+        api.respond_next(Endpoint::Ping, 200, "NOT PONG");
+        assert!(connect(&api_config(api.prefix())).await.is_some());
+
+        let prefix = api.prefix();
+        api.stop().await;
+        assert!(connect(&api_config(prefix)).await.is_some());
+    }
+
+    #[async_std::test]
+    async fn no_store_configured() {
+        assert!(connect(&ServerConfig::default()).await.is_none());
+    }
+}
@@ -0,0 +1,92 @@
+use nalgebra::Vector2;
+
+/// Air around a celestial object that drags on anything moving through it, so ships coming down slow to a landing
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Atmosphere {
+    /// How far above the surface it reaches
+    pub thickness: f32,
+    /// Drag force per unit of speed at the surface
+    pub density: f32,
+    /// Density falls off as `(1 - height / thickness)^falloff`, so 1 thins out evenly and higher is thin for most of the way up
+    #[serde(default = "linear_falloff")]
+    pub falloff: f32,
+}
+fn linear_falloff() -> f32 { 1.0 }
+
+impl Atmosphere {
+    /// Density `height` above the surface. Below the surface is as thick as it gets
+    pub fn density_at(&self, height: f32) -> f32 {
+        if height >= self.thickness { return 0.0 };
+        self.density * (1.0 - height.max(0.0) / self.thickness).powf(self.falloff)
+    }
+
+    pub fn problems(&self) -> Option<&'static str> {
+        if !(self.thickness > 0.0 && self.thickness.is_finite()) { Some("a positive atmosphere thickness") }
+        else if !(self.density >= 0.0 && self.density.is_finite()) { Some("an atmosphere density that isn't negative") }
+        else if !(self.falloff > 0.0 && self.falloff.is_finite()) { Some("a positive atmosphere falloff") }
+        else { None }
+    }
+}
+
+/// An atmosphere where it is this tick
+#[derive(Copy, Clone, Debug)]
+pub struct Air {
+    pub center: Vector2<f32>,
+    pub radius: f32,
+    /// The air moves along with its planet, so only speed relative to it is slowed
+    pub velocity: Vector2<f32>,
+    pub atmosphere: Atmosphere,
+}
+
+/// Drag on a part of `mass` at `at` going `velocity`: `-density * relative velocity` from every atmosphere it's in.
+/// It's capped at what would stop the part dead within `timestep`, so thick air can't fling it back the other way.
+pub fn drag(at: Vector2<f32>, velocity: Vector2<f32>, mass: f32, timestep: f32, airs: &[Air]) -> Vector2<f32> {
+    let mut total = Vector2::zeros();
+    for air in airs {
+        let height = (at - air.center).norm() - air.radius;
+        let density = air.atmosphere.density_at(height);
+        if density <= 0.0 { continue };
+        let relative = velocity - air.velocity;
+        total -= relative * density.min(mass / timestep);
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn air() -> Air {
+        Air { center: Vector2::new(50.0, 0.0), radius: 20.0, velocity: Vector2::new(0.0, 3.0), atmosphere: Atmosphere { thickness: 10.0, density: 0.5, falloff: 2.0 } }
+    }
+
+    #[test]
+    fn thins_out_towards_the_top() {
+        let atmosphere = air().atmosphere;
+        assert_eq!(atmosphere.density_at(-5.0), 0.5);
+        assert_eq!(atmosphere.density_at(0.0), 0.5);
+        assert!((atmosphere.density_at(5.0) - 0.125).abs() < 1e-6);
+        assert_eq!(atmosphere.density_at(10.0), 0.0);
+        assert_eq!(atmosphere.density_at(500.0), 0.0);
+    }
+
+    #[test]
+    fn drags_against_movement_relative_to_the_planet() {
+        let air = air();
+        let surface = air.center + Vector2::new(air.radius, 0.0);
+        //Keeping pace with the planet is no different from sitting still
+        assert_eq!(drag(surface, air.velocity, 1.0, 0.05, &[air]), Vector2::zeros());
+        let falling = drag(surface, air.velocity + Vector2::new(-4.0, 0.0), 1.0, 0.05, &[air]);
+        assert!((falling - Vector2::new(2.0, 0.0)).norm() < 1e-6);
+        assert_eq!(drag(surface + Vector2::new(15.0, 0.0), Vector2::zeros(), 1.0, 0.05, &[air]), Vector2::zeros());
+    }
+
+    #[test]
+    fn never_more_than_stops_a_part() {
+        let thick = Air { atmosphere: Atmosphere { density: 1000.0, ..air().atmosphere }, ..air() };
+        let force = drag(thick.center, thick.velocity + Vector2::new(2.0, 0.0), 0.5, 0.05, &[thick]);
+        //Taking away all of the 2 units of speed in one tick, and no more
+        assert!((force * 0.05 / 0.5 - Vector2::new(-2.0, 0.0)).norm() < 1e-5);
+    }
+}
@@ -0,0 +1,327 @@
+use rand::Rng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::collections::BTreeSet;
+use super::parts::PartKind;
+use super::planets::{CelestialObjectDef, Hazards};
+use super::atmosphere::Atmosphere;
+
+/// Bounds for something picked at random
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Range { pub min: f32, pub max: f32 }
+impl Range {
+    fn uniform(&self, rng: &mut impl Rng) -> f32 {
+        if self.max > self.min { rng.gen_range(self.min, self.max) } else { self.min }
+    }
+    /// Evenly spread across orders of magnitude, so small bodies come up as often as big ones
+    fn log_uniform(&self, rng: &mut impl Rng) -> f32 {
+        Range { min: self.min.ln(), max: self.max.ln() }.uniform(rng).exp()
+    }
+    fn is_valid(&self) -> bool { self.min.is_finite() && self.max.is_finite() && self.min >= 0.0 && self.max >= self.min }
+}
+
+/// How `generate` builds a solar system: one star, `planets` planets around it and up to `max_moons` moons around each
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeneratorSettings {
+    pub planets: u32,
+    pub star_mass: f32,
+    pub star_radius: f32,
+    /// Distance of the innermost planet from the star
+    pub first_orbit: f32,
+    /// Empty space between neighbouring planets' systems (the planet along with its moons)
+    pub orbit_gap: Range,
+    pub planet_mass: Range,
+    /// Radii grow with the cube root of mass, `radius_scale * mass^(1/3)`, give or take `radius_jitter` of that
+    pub radius_scale: f32,
+    pub radius_jitter: f32,
+    /// The starting planet is always this size so that taking off from it works the same on every map
+    pub home_mass: f32,
+    pub home_radius: f32,
+    /// Only the starting planet gets air, so first landings are gentle and the rest of the system is bare rock
+    pub home_atmosphere: Option<Atmosphere>,
+    /// How far above the starting planet weapons can't hurt anyone, so new players get a chance to find their feet
+    pub home_safe_zone: f32,
+    pub max_moons: u32,
+    /// A moon's mass as a fraction of its planet's
+    pub moon_mass_fraction: Range,
+    /// Empty space between a planet's surface and its first moon's, and between neighbouring moons
+    pub moon_gap: Range,
+    /// Cargo upgrades from the first players should reach to the last, by part kind name. They're handed out by distance
+    /// from the starting planet, one per body; any left once every body has one go unused
+    #[serde(with = "part_kind_names")]
+    pub upgrades: Vec<PartKind>,
+    /// How many bodies besides the starting planet and its moons can be beamed out from, picked at random
+    pub beamout_bodies: u32,
+}
+impl Default for GeneratorSettings {
+    fn default() -> GeneratorSettings {
+        GeneratorSettings {
+            planets: 9,
+            star_mass: 30000.0,
+            star_radius: 117.5,
+            first_orbit: 500.0,
+            orbit_gap: Range { min: 150.0, max: 700.0 },
+            planet_mass: Range { min: 40.0, max: 6000.0 },
+            radius_scale: 2.95,
+            radius_jitter: 0.15,
+            home_mass: 600.0,
+            home_radius: 25.0,
+            home_atmosphere: Some(Atmosphere { thickness: 8.0, density: 0.5, falloff: 2.0 }),
+            home_safe_zone: 50.0,
+            max_moons: 2,
+            moon_mass_fraction: Range { min: 0.02, max: 0.06 },
+            moon_gap: Range { min: 40.0, max: 80.0 },
+            upgrades: vec![
+                PartKind::LandingThruster, PartKind::Hub, PartKind::EcoThruster, PartKind::SolarPanel, PartKind::Thruster,
+                PartKind::SuperThruster, PartKind::PowerHub, PartKind::HubThruster, PartKind::LandingWheel, PartKind::Cannon,
+            ],
+            beamout_bodies: 1,
+        }
+    }
+}
+impl GeneratorSettings {
+    pub fn problems(&self) -> Vec<&'static str> {
+        let mut problems = Vec::new();
+        let positive = |value: f32| value.is_finite() && value > 0.0;
+        if self.planets == 0 { problems.push("generate_planets needs at least one planet"); }
+        if !positive(self.star_mass) || !positive(self.star_radius) { problems.push("generate_planets needs a positive star_mass and star_radius"); }
+        if !positive(self.home_mass) || !positive(self.home_radius) { problems.push("generate_planets needs a positive home_mass and home_radius"); }
+        if self.home_atmosphere.as_ref().and_then(Atmosphere::problems).is_some() { problems.push("generate_planets home_atmosphere needs a positive thickness and falloff and a density that isn't negative"); }
+        if !(self.home_safe_zone >= 0.0 && self.home_safe_zone.is_finite()) { problems.push("generate_planets home_safe_zone can't be negative"); }
+        if !positive(self.radius_scale) { problems.push("generate_planets needs a positive radius_scale"); }
+        if !(self.radius_jitter >= 0.0 && self.radius_jitter < 1.0) { problems.push("generate_planets radius_jitter must be at least 0 and less than 1"); }
+        if !self.first_orbit.is_finite() { problems.push("generate_planets first_orbit must be a number"); }
+        if !self.orbit_gap.is_valid() || !self.moon_gap.is_valid() { problems.push("generate_planets gaps can't be negative, and min can't be more than max"); }
+        if !self.planet_mass.is_valid() || self.planet_mass.min <= 0.0 { problems.push("generate_planets planet_mass must be positive, and min can't be more than max"); }
+        if !self.moon_mass_fraction.is_valid() || self.moon_mass_fraction.min <= 0.0 || self.moon_mass_fraction.max >= 1.0 {
+            problems.push("generate_planets moon_mass_fraction must be between 0 and 1, and min can't be more than max");
+        }
+        problems
+    }
+
+    fn radius(&self, mass: f32, rng: &mut impl Rng) -> f32 {
+        let jitter = if self.radius_jitter > 0.0 { rng.gen_range(-self.radius_jitter, self.radius_jitter) } else { 0.0 };
+        self.radius_scale * mass.cbrt() * (1.0 + jitter)
+    }
+}
+
+/// A planet and its moons, before they're put in orbit
+struct PlanetSystem {
+    mass: f32,
+    radius: f32,
+    /// Mass, radius and orbit radius of each
+    moons: Vec<(f32, f32, f32)>,
+    /// How far from the planet's center its furthest moon reaches
+    extent: f32,
+}
+
+/// Builds a solar system from `seed`. Planets are spaced so that no two systems' orbits cross, whatever their phase,
+/// and the starting planet is a fixed, ordinary size and never the innermost, so the sun isn't right there to fall into.
+pub fn generate(settings: &GeneratorSettings, seed: u64) -> Vec<CelestialObjectDef> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut names = BTreeSet::new();
+    let planets = settings.planets as usize;
+    let home = if planets > 1 { rng.gen_range(1, planets / 2 + 1) } else { 0 };
+
+    let systems: Vec<PlanetSystem> = (0..planets).map(|index| {
+        let (mass, radius) = if index == home { (settings.home_mass, settings.home_radius) } else {
+            let mass = settings.planet_mass.log_uniform(&mut rng);
+            (mass, settings.radius(mass, &mut rng))
+        };
+        let mut moon_count = rng.gen_range(0, settings.max_moons + 1);
+        //A close first stop for new players, like the Moon
+        if index == home && settings.max_moons > 0 { moon_count = moon_count.max(1); }
+        let mut extent = radius;
+        let moons = (0..moon_count).map(|_| {
+            let moon_mass = mass * settings.moon_mass_fraction.uniform(&mut rng);
+            let moon_radius = settings.radius(moon_mass, &mut rng);
+            let orbit_radius = extent + settings.moon_gap.uniform(&mut rng) + moon_radius;
+            extent = orbit_radius + moon_radius;
+            (moon_mass, moon_radius, orbit_radius)
+        }).collect();
+        PlanetSystem { mass, radius, moons, extent }
+    }).collect();
+
+    let mut orbits: Vec<f32> = Vec::with_capacity(planets);
+    for (index, system) in systems.iter().enumerate() {
+        orbits.push(match index {
+            0 => settings.first_orbit.max(settings.star_radius + settings.orbit_gap.min + system.extent),
+            _ => orbits[index - 1] + systems[index - 1].extent + settings.orbit_gap.uniform(&mut rng) + system.extent,
+        });
+    }
+
+    let star_name = make_name(&mut rng, &mut names);
+    let mut definitions = vec![CelestialObjectDef {
+        hazards: Hazards { incinerates: true },
+        ..definition(&star_name, None, 0.0, settings.star_mass, settings.star_radius)
+    }];
+    //How far each body is from the starting planet, to hand out upgrades by
+    let mut distances = vec![f32::INFINITY];
+    for (index, system) in systems.iter().enumerate() {
+        let name = make_name(&mut rng, &mut names);
+        let from_home = (orbits[index] - orbits[home]).abs();
+        definitions.push(CelestialObjectDef {
+            spawn: index == home,
+            can_beamout: index == home,
+            repairs: index == home,
+            safe_zone: if index == home { settings.home_safe_zone } else { 0.0 },
+            atmosphere: if index == home { settings.home_atmosphere } else { None },
+            ..definition(&name, Some(&star_name), orbits[index], system.mass, system.radius)
+        });
+        distances.push(if index == home { f32::INFINITY } else { from_home });
+        for (number, (mass, radius, orbit_radius)) in system.moons.iter().enumerate() {
+            let mut moon = definition(&format!("{}-{}", name, number + 1), Some(&name), *orbit_radius, *mass, *radius);
+            moon.display_name = format!("{} {}", capitalize(&name), ROMAN_NUMERALS.get(number).copied().unwrap_or("+"));
+            moon.can_beamout = index == home;
+            definitions.push(moon);
+            distances.push(from_home + orbit_radius);
+        }
+    }
+
+    let mut by_distance: Vec<usize> = (0..definitions.len()).filter(|index| distances[*index].is_finite()).collect();
+    by_distance.sort_by(|a, b| distances[*a].partial_cmp(&distances[*b]).unwrap());
+    for (index, upgrade) in by_distance.iter().zip(&settings.upgrades) {
+        definitions[*index].cargo_upgrade = Some(*upgrade);
+    }
+    let beamout_candidates: Vec<usize> = by_distance.into_iter().filter(|index| !definitions[*index].can_beamout).collect();
+    for index in beamout_candidates.choose_multiple(&mut rng, settings.beamout_bodies as usize).copied().collect::<Vec<_>>() {
+        definitions[index].can_beamout = true;
+    }
+    definitions
+}
+
+fn definition(name: &str, parent: Option<&str>, orbit_radius: f32, mass: f32, radius: f32) -> CelestialObjectDef {
+    CelestialObjectDef {
+        name: name.to_owned(),
+        display_name: capitalize(name),
+        parent: parent.map(str::to_owned),
+        orbit_radius,
+        position: (0.0, 0.0),
+        mass,
+        radius,
+        cargo_upgrade: None,
+        can_beamout: false,
+        repairs: false,
+        spawn: false,
+        hazards: Hazards::default(),
+        safe_zone: 0.0,
+        atmosphere: None,
+        enabled: true,
+    }
+}
+
+const SYLLABLES: &[&str] = &["ka", "ri", "zo", "mu", "te", "lo", "vin", "dar", "en", "sa", "qua", "bel", "or", "ix", "nu", "pha", "tor", "lys", "ce", "ran"];
+const ROMAN_NUMERALS: &[&str] = &["I", "II", "III", "IV", "V", "VI", "VII", "VIII", "IX", "X"];
+
+fn make_name(rng: &mut impl Rng, taken: &mut BTreeSet<String>) -> String {
+    loop {
+        let syllables = rng.gen_range(2, 4);
+        let mut name: String = (0..syllables).map(|_| *SYLLABLES.choose(rng).unwrap()).collect();
+        if taken.contains(&name) { name += &taken.len().to_string(); }
+        if taken.insert(name.clone()) { return name };
+    }
+}
+
+fn capitalize(name: &str) -> String {
+    let mut chars = name.chars();
+    chars.next().map(|first| first.to_uppercase().chain(chars).collect()).unwrap_or_default()
+}
+
+mod part_kind_names {
+    use serde::{Serialize, Serializer, Deserialize, Deserializer};
+    use serde::de::Error;
+    use super::PartKind;
+
+    pub fn serialize<S: Serializer>(kinds: &[PartKind], serializer: S) -> Result<S::Ok, S::Error> {
+        kinds.iter().map(|kind| format!("{:?}", kind)).collect::<Vec<_>>().serialize(serializer)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<PartKind>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?.iter()
+            .map(|name| PartKind::from_name(name).ok_or_else(|| D::Error::custom(format!("no part kind is named {}", name))))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::planets::validate_definitions;
+
+    fn find<'a>(definitions: &'a [CelestialObjectDef], name: &str) -> &'a CelestialObjectDef {
+        definitions.iter().find(|definition| definition.name == name).unwrap()
+    }
+
+    /// Furthest any part of the body (or its moons) gets from the center of what it orbits
+    fn reach(definitions: &[CelestialObjectDef], definition: &CelestialObjectDef) -> f32 {
+        definitions.iter().filter(|moon| moon.parent.as_deref() == Some(definition.name.as_str()))
+            .map(|moon| moon.orbit_radius + moon.radius).fold(definition.radius, f32::max)
+    }
+
+    #[test]
+    fn same_seed_same_system() {
+        let settings = GeneratorSettings::default();
+        let describe = |seed| generate(&settings, seed).iter().map(|definition| (definition.name.clone(), definition.orbit_radius, definition.mass)).collect::<Vec<_>>();
+        assert_eq!(describe(11), describe(11));
+        assert_ne!(describe(11), describe(12));
+    }
+
+    #[test]
+    fn generated_systems_are_valid_and_never_overlap() {
+        let settings = GeneratorSettings::default();
+        for seed in 0..200 {
+            let definitions = generate(&settings, seed);
+            validate_definitions(&definitions).unwrap_or_else(|problems| panic!("seed {}: {}", seed, problems));
+            //Siblings on circular orbits only ever touch if their bands overlap
+            for parent in &definitions {
+                let children: Vec<&CelestialObjectDef> = definitions.iter().filter(|child| child.parent.as_deref() == Some(parent.name.as_str())).collect();
+                for child in &children {
+                    assert!(child.orbit_radius - reach(&definitions, child) > parent.radius, "seed {}: {} touches {}", seed, child.name, parent.name);
+                    for other in &children {
+                        if child.name == other.name { continue };
+                        let gap = (child.orbit_radius - other.orbit_radius).abs();
+                        assert!(gap > reach(&definitions, child) + reach(&definitions, other), "seed {}: {} and {} overlap", seed, child.name, other.name);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn starting_planet_is_ordinary_and_upgrades_start_nearby() {
+        let settings = GeneratorSettings::default();
+        for seed in 0..50 {
+            let definitions = generate(&settings, seed);
+            let home = definitions.iter().find(|definition| definition.spawn).unwrap();
+            let star = &definitions[0];
+            assert_eq!((home.mass, home.radius), (settings.home_mass, settings.home_radius));
+            assert!(home.can_beamout && home.cargo_upgrade.is_none() && !home.hazards.incinerates);
+            assert_eq!(home.safe_zone, settings.home_safe_zone);
+            assert_eq!(home.parent.as_deref(), Some(star.name.as_str()));
+            let innermost = definitions.iter().filter(|definition| definition.parent.as_deref() == Some(star.name.as_str()))
+                .min_by(|a, b| a.orbit_radius.partial_cmp(&b.orbit_radius).unwrap()).unwrap();
+            assert_ne!(innermost.name, home.name);
+            //Home always has a moon, which is as close as anything gets, so it has the first upgrade
+            let moon = definitions.iter().find(|definition| definition.parent.as_deref() == Some(home.name.as_str())).unwrap();
+            assert_eq!(find(&definitions, &moon.name).cargo_upgrade, Some(settings.upgrades[0]));
+            assert!(moon.can_beamout);
+            let upgrades = definitions.iter().filter(|definition| definition.cargo_upgrade.is_some()).count();
+            assert_eq!(upgrades, settings.upgrades.len().min(definitions.len() - 2));
+            let beamout = definitions.iter().filter(|definition| definition.can_beamout).count();
+            let home_system = 1 + definitions.iter().filter(|definition| definition.parent.as_deref() == Some(home.name.as_str())).count();
+            assert_eq!(beamout, home_system + settings.beamout_bodies as usize);
+        }
+    }
+
+    #[test]
+    fn settings_round_trip_with_part_names() {
+        let json = serde_json::to_value(GeneratorSettings::default()).unwrap();
+        assert_eq!(json["upgrades"][0], "LandingThruster");
+        let settings: GeneratorSettings = serde_json::from_value(serde_json::json!({ "planets": 3, "upgrades": ["Hub"] })).unwrap();
+        assert_eq!((settings.planets, settings.upgrades.clone()), (3, vec![PartKind::Hub]));
+        assert!(serde_json::from_value::<GeneratorSettings>(serde_json::json!({ "upgrades": ["Warp"] })).is_err());
+        assert!(GeneratorSettings { planets: 0, ..settings }.problems().len() == 1);
+    }
+}
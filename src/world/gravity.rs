@@ -0,0 +1,132 @@
+use nalgebra::Vector2;
+
+/// Something that pulls parts towards it
+#[derive(Copy, Clone, Debug)]
+pub struct Attractor {
+    pub position: Vector2<f32>,
+    pub mass: f32,
+    /// Softening is a fraction of this
+    pub radius: f32,
+    /// Beyond this distance it doesn't pull at all, if `sphere_of_influence_cutoff` is on
+    pub sphere_of_influence: Option<f32>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct GravitySettings {
+    pub constant: f32,
+    /// Plummer softening length as a fraction of the attractor's radius, which keeps the pull finite near (and inside) a planet
+    pub softening: f32,
+    pub sphere_of_influence_cutoff: bool,
+}
+impl GravitySettings {
+    pub fn from_config() -> GravitySettings {
+        let config = crate::config::config();
+        GravitySettings { constant: config.gravitational_constant, softening: config.gravity_softening, sphere_of_influence_cutoff: config.gravity_soi_cutoff }
+    }
+}
+
+/// Gravitational acceleration at `at`: the sum of `G * M * r / (|r|² + ε²)^(3/2)` over every attractor in range
+pub fn acceleration(at: Vector2<f32>, attractors: &[Attractor], settings: &GravitySettings) -> Vector2<f32> {
+    let mut total = Vector2::zeros();
+    for attractor in attractors {
+        let offset = attractor.position - at;
+        let distance_squared = offset.norm_squared();
+        if settings.sphere_of_influence_cutoff {
+            if let Some(sphere_of_influence) = attractor.sphere_of_influence {
+                if distance_squared > sphere_of_influence * sphere_of_influence { continue };
+            }
+        }
+        let softening = settings.softening * attractor.radius;
+        let softened = distance_squared + softening * softening;
+        if softened <= 0.0 { continue };
+        total += offset * (settings.constant * attractor.mass / (softened * softened.sqrt()));
+    }
+    total
+}
+
+/// Laplace's sphere of influence, `a * (m / M)^(2/5)`, of a body of mass `mass` orbiting one of `parent_mass` at `distance`
+pub fn sphere_of_influence(distance: f32, mass: f32, parent_mass: f32) -> f32 {
+    distance * (mass / parent_mass).powf(0.4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nphysics2d::object::{DefaultBodySet, DefaultColliderSet, RigidBodyDesc, Body};
+    use nphysics2d::joint::DefaultJointConstraintSet;
+    use nphysics2d::force_generator::DefaultForceGeneratorSet;
+    use nphysics2d::world::{DefaultMechanicalWorld, DefaultGeometricalWorld};
+    use nphysics2d::algebra::{Force2, ForceType};
+
+    const SETTINGS: GravitySettings = GravitySettings { constant: 1.0, softening: 0.1, sphere_of_influence_cutoff: false };
+
+    fn planet() -> Attractor { Attractor { position: Vector2::new(100.0, -50.0), mass: 600.0, radius: 25.0, sphere_of_influence: Some(300.0) } }
+
+    #[test]
+    fn pulls_radially_with_the_inverse_square() {
+        let settings = GravitySettings { softening: 0.0, ..SETTINGS };
+        let planet = planet();
+        for direction in &[Vector2::new(1.0, 0.0), Vector2::new(0.6, 0.8), Vector2::new(-0.28, 0.96)] {
+            let near = acceleration(planet.position + direction * 50.0, &[planet], &settings);
+            let far = acceleration(planet.position + direction * 100.0, &[planet], &settings);
+            assert!((near.norm() - 600.0 / 2500.0).abs() < 1e-5);
+            assert!((near.norm() / far.norm() - 4.0).abs() < 1e-3);
+            //Straight back towards the planet
+            assert!((near.normalize() + direction).norm() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn softening_keeps_the_center_finite() {
+        let planet = planet();
+        assert_eq!(acceleration(planet.position, &[planet], &SETTINGS), Vector2::zeros());
+        let just_inside = acceleration(planet.position + Vector2::new(0.01, 0.0), &[planet], &SETTINGS);
+        assert!(just_inside.norm().is_finite() && just_inside.norm() < 1.0);
+    }
+
+    #[test]
+    fn sphere_of_influence_cutoff() {
+        let planet = planet();
+        let outside = planet.position + Vector2::new(301.0, 0.0);
+        assert!(acceleration(outside, &[planet], &SETTINGS).norm() > 0.0);
+        assert_eq!(acceleration(outside, &[planet], &GravitySettings { sphere_of_influence_cutoff: true, ..SETTINGS }), Vector2::zeros());
+        assert!((sphere_of_influence(1500.0, 600.0, 30000.0) - 1500.0 * 0.02f32.powf(0.4)).abs() < 1e-3);
+    }
+
+    /// Runs a part in a circular orbit through nphysics the way the game does, one force application per tick
+    #[test]
+    fn circular_orbit_is_stable() {
+        let planet = Attractor { sphere_of_influence: None, ..planet() };
+        let orbit_radius = 80.0;
+        let speed = (SETTINGS.constant * planet.mass / orbit_radius).sqrt();
+
+        let mut mechanics = DefaultMechanicalWorld::new(Vector2::zeros());
+        mechanics.set_timestep(1.0 / 20.0);
+        let mut geometry = DefaultGeometricalWorld::<f32>::new();
+        let mut bodies = DefaultBodySet::<f32>::new();
+        let mut colliders = DefaultColliderSet::<f32>::new();
+        let mut joints = DefaultJointConstraintSet::<f32>::new();
+        let mut forces = DefaultForceGeneratorSet::<f32>::new();
+        let handle = bodies.insert(RigidBodyDesc::new()
+            .translation(planet.position + Vector2::new(orbit_radius, 0.0))
+            .velocity(nphysics2d::math::Velocity::linear(0.0, speed))
+            .mass(1.5)
+            .build());
+
+        let period_ticks = (2.0 * std::f32::consts::PI * orbit_radius / speed * 20.0) as usize;
+        let ticks = 20_000;
+        assert!(ticks > period_ticks * 5, "should cover several orbits");
+        for _ in 0..ticks {
+            let body = bodies.rigid_body_mut(handle).unwrap();
+            let mass = body.augmented_mass().linear;
+            let pull = acceleration(body.position().translation.vector, &[planet], &SETTINGS) * mass;
+            body.apply_force(0, &Force2::linear(pull), ForceType::Force, true);
+            mechanics.step(&mut geometry, &mut bodies, &mut colliders, &mut joints, &mut forces);
+
+            let body = bodies.rigid_body(handle).unwrap();
+            let radius = (body.position().translation.vector - planet.position).norm();
+            //The softening makes the true circular speed a hair slower, so allow a little eccentricity
+            assert!((radius - orbit_radius).abs() < orbit_radius * 0.02, "orbit drifted to radius {}", radius);
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! How hard things hit each other, straight from the contact solver. nphysics keeps the impulse each contact ended up applying
+//! only to warmstart the next step, so the solver's contact model is wrapped to note those down per body as they're cached.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use nalgebra::DVector;
+use ncollide2d::query::ContactId;
+use nphysics2d::detection::ColliderContactManifold;
+use nphysics2d::material::MaterialsCoefficientsTable;
+use nphysics2d::object::BodySet;
+use nphysics2d::solver::{ConstraintSet, ContactModel, IntegrationParameters, SignoriniCoulombPyramidModel};
+use super::nphysics_types::*;
+
+/// Total normal impulse between each pair of bodies since it was last emptied
+pub type Impacts = Arc<Mutex<BTreeMap<(MyHandle, MyHandle), f32>>>;
+
+/// nphysics' default contact model, noting down every non-penetration impulse it settles on
+pub struct ImpactRecorder {
+    model: SignoriniCoulombPyramidModel<MyUnits>,
+    /// Bodies on either side of each contact in this solve
+    contacts: HashMap<ContactId, (MyHandle, MyHandle)>,
+    impacts: Impacts,
+}
+
+impl ImpactRecorder {
+    pub fn new(impacts: Impacts) -> ImpactRecorder {
+        ImpactRecorder { model: SignoriniCoulombPyramidModel::new(), contacts: HashMap::new(), impacts }
+    }
+}
+
+impl ContactModel<MyUnits, MyHandle, MyColliderHandle> for ImpactRecorder {
+    fn num_velocity_constraints(&self, manifold: &ColliderContactManifold<MyUnits, MyHandle, MyColliderHandle>) -> usize {
+        self.model.num_velocity_constraints(manifold)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn constraints(
+        &mut self,
+        parameters: &IntegrationParameters<MyUnits>,
+        material_coefficients: &MaterialsCoefficientsTable<MyUnits>,
+        bodies: &dyn BodySet<MyUnits, Handle = MyHandle>,
+        ext_vels: &DVector<MyUnits>,
+        manifolds: &[ColliderContactManifold<MyUnits, MyHandle, MyColliderHandle>],
+        ground_j_id: &mut usize,
+        j_id: &mut usize,
+        jacobians: &mut [MyUnits],
+        constraints: &mut ConstraintSet<MyUnits, MyHandle, MyColliderHandle, ContactId>,
+    ) {
+        self.contacts.clear();
+        for manifold in manifolds {
+            let pair = (manifold.collider1.body(), manifold.collider2.body());
+            for contact in manifold.manifold.contacts() { self.contacts.insert(contact.id, pair); }
+        }
+        self.model.constraints(parameters, material_coefficients, bodies, ext_vels, manifolds, ground_j_id, j_id, jacobians, constraints);
+    }
+
+    fn cache_impulses(&mut self, constraints: &ConstraintSet<MyUnits, MyHandle, MyColliderHandle, ContactId>) {
+        self.model.cache_impulses(constraints);
+        //Friction is bilateral, so these are only ever the push apart along the contact normal
+        let normals = constraints.velocity.unilateral.iter().map(|constraint| (constraint.impulse_id, constraint.impulse))
+            .chain(constraints.velocity.unilateral_ground.iter().map(|constraint| (constraint.impulse_id, constraint.impulse)));
+        let mut impacts = self.impacts.lock().unwrap();
+        for (contact, impulse) in normals {
+            if let Some(bodies) = self.contacts.get(&contact) { *impacts.entry(*bodies).or_default() += impulse; }
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::{BTreeMap, VecDeque};
+use nalgebra::{Isometry2, Point2};
+use super::parts::{RecursivePartDescription, PartKind};
+use crate::config::config;
+
+/// Parts of a ship sit on a grid of this spacing: every attachment point is 0.6 from its part's origin
+/// and every attached part's body is centered 0.5 past that
+const GRID_SPACING: f32 = 1.1;
+
+/// Where a part sits in a layout, as the attachment slots taken from the core to reach it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartPath(pub Vec<usize>);
+impl std::fmt::Display for PartPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.0.is_empty() { return write!(f, "the root") };
+        write!(f, "root")?;
+        for slot in &self.0 { write!(f, ".{}", slot)?; }
+        Ok(())
+    }
+}
+
+/// Something wrong with a layout. Everything but `RootNotCore` is repaired by removing the offending part along with whatever hangs off it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LayoutProblem {
+    RootNotCore(PartKind),
+    NestedCore(PartPath),
+    NoSuchSlot { path: PartPath, kind: PartKind },
+    Overlapping { path: PartPath, kind: PartKind, occupied_by: PartPath },
+    TooManyParts { parts: usize, max: usize },
+}
+impl std::fmt::Display for LayoutProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LayoutProblem::RootNotCore(kind) => write!(f, "the root is a {:?}, not a Core", kind),
+            LayoutProblem::NestedCore(path) => write!(f, "a Core is attached at {}", path),
+            LayoutProblem::NoSuchSlot { path, kind } => write!(f, "a {:?} is attached at {}, but its parent has no slot {}", kind, path, path.0.last().unwrap()),
+            LayoutProblem::Overlapping { path, kind, occupied_by } => write!(f, "a {:?} at {} overlaps the part at {}", kind, path, occupied_by),
+            LayoutProblem::TooManyParts { parts, max } => write!(f, "the ship has {} parts, more than the limit of {}", parts, max),
+        }
+    }
+}
+
+/// Checks a beamed-in layout and returns one that is safe to inflate, along with everything that was wrong with the original.
+/// With `repair_layouts` on, offending parts are cut off (and anything past `max_ship_parts` is trimmed, furthest from the core first);
+/// otherwise, or if the root isn't a core, the player gets a plain core.
+pub fn validate_layout(mut layout: RecursivePartDescription) -> (RecursivePartDescription, Vec<LayoutProblem>) {
+    let plain_core = || RecursivePartDescription::from(PartKind::Core);
+    if layout.kind != PartKind::Core { return (plain_core(), vec![LayoutProblem::RootNotCore(layout.kind)]) };
+
+    let mut problems = Vec::new();
+    let mut occupied = BTreeMap::new();
+    occupied.insert((0, 0), PartPath(Vec::new()));
+    repair_children(&mut layout, Isometry2::identity(), &mut Vec::new(), &mut occupied, &mut problems);
+
+    let max = config().max_ship_parts as usize;
+    let parts = count_parts(&layout);
+    if parts > max {
+        problems.push(LayoutProblem::TooManyParts { parts, max });
+        trim_to(&mut layout, max);
+    }
+    if problems.is_empty() || config().repair_layouts { (layout, problems) } else { (plain_core(), problems) }
+}
+
+/// Depth first, so where two parts fight over a spot, the one reached first keeps it
+fn repair_children(part: &mut RecursivePartDescription, location: Isometry2<f32>, path: &mut Vec<usize>, occupied: &mut BTreeMap<(i32, i32), PartPath>, problems: &mut Vec<LayoutProblem>) {
+    let slots = part.kind.attachment_locations();
+    //Only four slots exist on any kind, so anything past them can't be attached to anything
+    if part.attachments.len() > slots.len() {
+        for (slot, extra) in part.attachments.drain(slots.len()..).enumerate() {
+            if let Some(extra) = extra {
+                path.push(slots.len() + slot);
+                problems.push(LayoutProblem::NoSuchSlot { path: PartPath(path.clone()), kind: extra.kind });
+                path.pop();
+            }
+        }
+    }
+    for (slot, attachment) in part.attachments.iter_mut().enumerate() {
+        let child = if let Some(child) = attachment { child } else { continue };
+        path.push(slot);
+        let child_path = PartPath(path.clone());
+        let problem = if let Some(point) = slots[slot] {
+            let child_location = Isometry2::new(location.transform_point(&Point2::new(point.x, point.y)).coords, point.facing.part_rotation() + location.rotation.angle());
+            let cell = grid_cell(&child_location);
+            if child.kind == PartKind::Core { Some(LayoutProblem::NestedCore(child_path)) }
+            else if let Some(occupied_by) = occupied.get(&cell) {
+                Some(LayoutProblem::Overlapping { path: child_path, kind: child.kind, occupied_by: occupied_by.clone() })
+            } else {
+                occupied.insert(cell, child_path);
+                repair_children(child, child_location, path, occupied, problems);
+                None
+            }
+        } else { Some(LayoutProblem::NoSuchSlot { path: child_path, kind: child.kind }) };
+        if let Some(problem) = problem {
+            problems.push(problem);
+            *attachment = None;
+        }
+        path.pop();
+    }
+}
+
+/// The grid square an attached part's body covers
+fn grid_cell(location: &Isometry2<f32>) -> (i32, i32) {
+    let center = location.transform_point(&Point2::new(0.0, 0.5));
+    ((center.x / GRID_SPACING).round() as i32, (center.y / GRID_SPACING).round() as i32)
+}
+
+pub fn count_parts(part: &RecursivePartDescription) -> usize {
+    1 + part.attachments.iter().flatten().map(count_parts).sum::<usize>()
+}
+
+/// Keeps the `max` parts closest to the core (in attachments), breadth first
+fn trim_to(layout: &mut RecursivePartDescription, max: usize) {
+    let mut kept = 0;
+    let mut queue: VecDeque<&mut RecursivePartDescription> = VecDeque::new();
+    queue.push_back(layout);
+    while let Some(part) = queue.pop_front() {
+        kept += 1;
+        for attachment in part.attachments.iter_mut() {
+            if attachment.is_none() { continue };
+            if kept + queue.len() >= max { *attachment = None; }
+            else { queue.push_back(attachment.as_mut().unwrap()); }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(kind: PartKind, attachments: Vec<Option<RecursivePartDescription>>) -> RecursivePartDescription {
+        RecursivePartDescription { kind, attachments }
+    }
+    fn hub_with_cargo() -> RecursivePartDescription {
+        part(PartKind::Hub, vec![None, Some(PartKind::Cargo.into()), None, Some(PartKind::Cargo.into())])
+    }
+
+    #[test]
+    fn good_layouts_are_left_alone() {
+        let layout = part(PartKind::Core, vec![Some(hub_with_cargo()), None, Some(part(PartKind::Hub, vec![None, None, Some(PartKind::Thruster.into())]))]);
+        let (validated, problems) = validate_layout(layout.clone());
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(serde_json::to_value(validated).unwrap(), serde_json::to_value(layout).unwrap());
+    }
+
+    #[test]
+    fn roots_that_arent_cores_get_a_plain_core() {
+        let (validated, problems) = validate_layout(hub_with_cargo());
+        assert_eq!(problems, vec![LayoutProblem::RootNotCore(PartKind::Hub)]);
+        assert_eq!(validated.kind, PartKind::Core);
+        assert_eq!(count_parts(&validated), 1);
+    }
+
+    #[test]
+    fn nested_cores_are_cut_off() {
+        let nested = part(PartKind::Core, vec![Some(PartKind::Cargo.into())]);
+        let layout = part(PartKind::Core, vec![Some(part(PartKind::Hub, vec![None, None, Some(nested)])), Some(PartKind::Cargo.into())]);
+        let (validated, problems) = validate_layout(layout);
+        assert_eq!(problems, vec![LayoutProblem::NestedCore(PartPath(vec![0, 2]))]);
+        //Along with what was attached to it, but nothing else
+        assert_eq!(count_parts(&validated), 3);
+        assert!(validated.attachments[0].as_ref().unwrap().attachments[2].is_none());
+    }
+
+    #[test]
+    fn parts_in_slots_that_dont_exist_are_cut_off() {
+        //Hubs have nothing where they attach to their parent, cargo has no slots at all, and no kind has a fifth
+        let layout = part(PartKind::Core, vec![
+            Some(part(PartKind::Hub, vec![Some(PartKind::Cargo.into()), None, Some(part(PartKind::Cargo, vec![Some(PartKind::Thruster.into())]))])),
+            None, None, None, Some(PartKind::Cargo.into()),
+        ]);
+        let (validated, problems) = validate_layout(layout);
+        assert_eq!(problems, vec![
+            LayoutProblem::NoSuchSlot { path: PartPath(vec![4]), kind: PartKind::Cargo },
+            LayoutProblem::NoSuchSlot { path: PartPath(vec![0, 0]), kind: PartKind::Cargo },
+            LayoutProblem::NoSuchSlot { path: PartPath(vec![0, 2, 0]), kind: PartKind::Thruster },
+        ]);
+        assert_eq!(count_parts(&validated), 3);
+        assert_eq!(validated.attachments.len(), 4);
+        assert_eq!(problems[0].to_string(), "a Cargo is attached at root.4, but its parent has no slot 4");
+    }
+
+    #[test]
+    fn the_first_part_to_reach_a_spot_keeps_it() {
+        //Each hub's cargo sticks out diagonally into the corner the next hub round's cargo also reaches
+        let layout = part(PartKind::Core, vec![Some(hub_with_cargo()), Some(hub_with_cargo()), Some(hub_with_cargo()), Some(hub_with_cargo())]);
+        let (validated, problems) = validate_layout(layout);
+        assert_eq!(problems.len(), 4);
+        assert_eq!(problems[0], LayoutProblem::Overlapping { path: PartPath(vec![1, 1]), kind: PartKind::Cargo, occupied_by: PartPath(vec![0, 3]) });
+        assert!(problems.contains(&LayoutProblem::Overlapping { path: PartPath(vec![3, 3]), kind: PartKind::Cargo, occupied_by: PartPath(vec![0, 1]) }));
+        assert_eq!(count_parts(&validated), 9);
+        assert_eq!(validated.attachments[0].as_ref().unwrap().attachments.iter().flatten().count(), 2);
+        assert!(validated.attachments[1].as_ref().unwrap().attachments[1].is_none());
+        assert_eq!(count_parts(&validate_layout(validated).0), 9);
+    }
+
+    #[test]
+    fn big_ships_are_trimmed_from_the_outside_in() {
+        let max = config().max_ship_parts as usize;
+        //A long chain out of each side of the core
+        let chain = |length: usize| (0..length).fold(RecursivePartDescription::from(PartKind::Cargo), |tail, _| part(PartKind::Hub, vec![None, None, Some(tail)]));
+        let layout = part(PartKind::Core, vec![Some(chain(max)), Some(chain(2)), None, None]);
+        let parts = count_parts(&layout);
+        let (validated, problems) = validate_layout(layout);
+        assert_eq!(problems, vec![LayoutProblem::TooManyParts { parts, max }]);
+        assert_eq!(count_parts(&validated), max);
+        //The short arm is nearer the core than the end of the long one, so it's all still there
+        assert_eq!(count_parts(validated.attachments[1].as_ref().unwrap()), 3);
+    }
+
+    #[test]
+    fn trimming_keeps_the_parts_closest_to_the_core() {
+        let mut layout = part(PartKind::Core, vec![Some(hub_with_cargo()), Some(hub_with_cargo()), Some(PartKind::Cargo.into()), None]);
+        trim_to(&mut layout, 4);
+        //Everything one attachment away, but none of the hubs' cargo
+        assert_eq!(count_parts(&layout), 4);
+        assert!(layout.attachments.iter().flatten().all(|attachment| attachment.attachments.iter().all(Option::is_none)));
+
+        let mut layout = part(PartKind::Core, vec![Some(hub_with_cargo()), Some(hub_with_cargo())]);
+        trim_to(&mut layout, 5);
+        assert_eq!(count_parts(&layout), 5);
+        assert_eq!(layout.attachments[0].as_ref().unwrap().attachments.iter().flatten().count(), 2);
+        trim_to(&mut layout, 1);
+        assert_eq!(count_parts(&layout), 1);
+        trim_to(&mut layout, 10);
+        assert_eq!(count_parts(&layout), 1);
+    }
+}
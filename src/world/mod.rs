@@ -2,7 +2,6 @@ use nalgebra::Vector2;
 use nphysics2d::object::{RigidBody, Body, BodyPartHandle, DefaultColliderHandle};
 use std::collections::{BTreeMap, BTreeSet};
 use nphysics2d::force_generator::DefaultForceGeneratorSet;
-use num_traits::Pow;
 use nphysics2d::algebra::{Force2, ForceType, Inertia2};
 use nphysics2d::joint::{DefaultJointConstraintHandle, MouseConstraint, JointConstraint};
 use nphysics2d::math::Point;
@@ -11,9 +10,19 @@ use crate::PartOfPlayer;
 use generational_arena::{Arena, Index};
 use crate::codec::ToClientMsg;
 use std::ops::{Deref, DerefMut};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 
 pub mod planets;
 pub mod parts;
+pub mod layout;
+pub mod gravity;
+pub mod atmosphere;
+pub mod orbit;
+pub mod generator;
+pub mod snapshot;
+pub mod projectiles;
+pub mod impacts;
 use parts::{Part, AttachedPartFacing, RecursivePartDescription};
 use planets::AmPlanet;
 
@@ -41,52 +50,90 @@ pub struct Simulation {
     pub joints: MyJointSet,
     persistant_forces: MyForceSet,
     pub planets: planets::Planets,
+    /// Everything random about the world comes from here, so the same seed makes the same world
+    pub rng: StdRng,
+    next_projectile_id: u16,
+    /// Filled in by the contact solver during each step
+    impacts: impacts::Impacts,
 }
+/// 64 bit FNV-1a, which hashes the same bytes the same way on every build and platform
+pub struct Fnv1a(u64);
+impl Default for Fnv1a {
+    fn default() -> Fnv1a { Fnv1a(0xcbf29ce484222325) }
+}
+impl std::hash::Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes { self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3); }
+    }
+    //The defaults go by native endianness
+    fn write_u16(&mut self, value: u16) { self.write(&value.to_le_bytes()) }
+    fn write_u32(&mut self, value: u32) { self.write(&value.to_le_bytes()) }
+    fn write_u64(&mut self, value: u64) { self.write(&value.to_le_bytes()) }
+    fn finish(&self) -> u64 { self.0 }
+}
+
 pub enum SimulationEvent {
     PlayerTouchPlanet { player: u16, part: MyHandle, planet: u16, },
     PlayerUntouchPlanet { player: u16, part: MyHandle, planet: u16 },
+    /// Its health has already gone down, and it's up to whoever owns it to get rid of it once that reaches 0
+    PartDamaged { part: MyHandle },
+    /// A shot touched something and is gone. `part` is what it hit, if that was a part
+    ProjectileHit { projectile: u16, shooter: u16, damage: u16, part: Option<MyHandle> },
+    /// Went its whole range without touching anything
+    ProjectileExpired { projectile: u16 },
 }
 
 
 impl Simulation {
-    pub fn new(step_time: f32) -> Simulation {
+    pub fn new(step_time: f32, planets: &[planets::CelestialObjectDef], seed: u64) -> Simulation {
         let mut mechanics = MyMechanicalWorld::new(Vector2::new(0.0, 0.0));
         mechanics.set_timestep(step_time);
         mechanics.integration_parameters.max_ccd_substeps = 5;
+        let impacts = impacts::Impacts::default();
+        mechanics.solver.set_contact_model(Box::new(impacts::ImpactRecorder::new(impacts.clone())));
         let geometry: MyGeometricalWorld = MyGeometricalWorld::new();
         let mut colliders: MyColliderSet = MyColliderSet::new();
         let mut bodies = World::default();
-        let planets = planets::Planets::new(&mut colliders, &mut bodies);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let planets = planets::Planets::new(planets, &mut colliders, &mut bodies, &mut rng);
         let simulation = Simulation {
-            mechanics, geometry, colliders, world: bodies,
+            mechanics, geometry, colliders, world: bodies, rng,
             joints: MyJointSet::new(),
             persistant_forces: MyForceSet::new(),
             planets,
+            next_projectile_id: 0,
+            impacts,
         };
         simulation
     }
 
     fn celestial_gravity(&mut self) {
+        let settings = gravity::GravitySettings::from_config();
+        let attractors = self.planets.attractors();
         for (_part_handle, part) in self.world.iter_parts_mut() {
             let part = part.body_mut();
-            const GRAVITATION_CONSTANT: f32 = 1.0; //Lolrandom
-            for body in &self.planets.celestial_objects() {
-                let distance: (f32, f32) = ((body.position.0 - part.position().translation.x),
-                                            (body.position.1 - part.position().translation.y));
-                let magnitude: f32 = part.augmented_mass().linear * body.mass
-                                     / (distance.0.pow(2f32) + distance.1.pow(2f32));
-                                     //* GRAVITATION_CONSTANT;
-                if distance.0.abs() > distance.1.abs() {
-                    part.apply_force(0, &Force2::linear(Vector2::new(if distance.0 >= 0.0 { magnitude } else { -magnitude }, distance.1 / distance.0.abs() * magnitude)), ForceType::Force, false);
-                } else {
-                    part.apply_force(0, &Force2::linear(Vector2::new(distance.0 / distance.1.abs() * magnitude, if distance.1 >= 0.0 { magnitude } else { -magnitude })), ForceType::Force, false);
-                }
-            }
+            let pull = gravity::acceleration(part.position().translation.vector, &attractors, &settings) * part.augmented_mass().linear;
+            part.apply_force(0, &Force2::linear(pull), ForceType::Force, false);
+        }
+    }
+
+    fn atmospheric_drag(&mut self) {
+        let airs = self.planets.airs();
+        if airs.is_empty() { return };
+        let timestep = self.mechanics.timestep();
+        for (_part_handle, part) in self.world.iter_parts_mut() {
+            let part = part.body_mut();
+            let drag = atmosphere::drag(part.position().translation.vector, part.velocity().linear, part.augmented_mass().linear, timestep, &airs);
+            if drag != Vector2::zeros() { part.apply_force(0, &Force2::linear(drag), ForceType::Force, false); }
         }
     }
 
     pub fn simulate(&mut self, events: &mut Vec<SimulationEvent>) {
+        self.expire_projectiles(events);
+        self.planets.advance(self.mechanics.timestep(), &mut self.world);
         self.celestial_gravity();
+        self.atmospheric_drag();
+        self.impacts.lock().unwrap().clear();
         self.mechanics.step(&mut self.geometry, &mut self.world, &mut self.colliders, &mut self.joints, &mut self.persistant_forces);
         for contact_event in self.geometry.contact_events() {
             match contact_event {
@@ -122,6 +169,83 @@ impl Simulation {
                 }
             }
         }
+        self.impact_damage(events);
+        self.projectile_hits(events);
+    }
+
+    /// Fires `weapon_part` if it's a weapon, returning the shot's message for clients. Harmless shots still fly and get used up
+    /// on whatever they touch, they just don't do any damage.
+    pub fn fire(&mut self, weapon_part: MyHandle, shooter: u16, harmless: bool) -> Option<ToClientMsg> {
+        let part = self.world.get_part(weapon_part)?;
+        let weapon = part.kind().weapon()?;
+        let position = part.body().position();
+        let muzzle = position.transform_point(&Point::new(0.0, 1.3)).coords;
+        let velocity = part.body().velocity().linear + position.rotation * Vector2::new(0.0, weapon.speed);
+        let id = self.next_projectile_id;
+        self.next_projectile_id = self.next_projectile_id.wrapping_add(1);
+        let timestep = self.mechanics.timestep();
+        let handle = projectiles::Projectile::launch(&mut (&mut self.world).into(), &mut self.colliders, id, shooter, &weapon, harmless, muzzle, velocity, timestep);
+        self.world.get_projectile(handle).map(|projectile| projectile.add_msg())
+    }
+
+    fn expire_projectiles(&mut self, events: &mut Vec<SimulationEvent>) {
+        let mut expired = Vec::new();
+        for (handle, projectile) in self.world.iter_projectiles_mut() {
+            projectile.ticks_left -= 1;
+            if projectile.ticks_left < 1 { expired.push(handle); }
+        }
+        for handle in expired {
+            let projectile = self.remove_projectile(handle);
+            events.push(SimulationEvent::ProjectileExpired { projectile: projectile.id() });
+        }
+    }
+
+    /// Shots are used up on the first thing they touch, whatever it is
+    fn projectile_hits(&mut self, events: &mut Vec<SimulationEvent>) {
+        let mut hits: BTreeMap<MyHandle, Option<MyHandle>> = BTreeMap::new();
+        for contact_event in self.geometry.contact_events() {
+            if let ContactEvent::Started(collider1, collider2) = contact_event {
+                let bodies = match (self.colliders.get(*collider1), self.colliders.get(*collider2)) { (Some(collider1), Some(collider2)) => (collider1.body(), collider2.body()), _ => continue };
+                for (shot, other) in &[bodies, (bodies.1, bodies.0)] {
+                    if self.world.get_projectile(*shot).is_some() && !hits.contains_key(shot) {
+                        hits.insert(*shot, if self.world.get_part(*other).is_some() { Some(*other) } else { None });
+                    }
+                }
+            }
+        }
+        for (handle, part) in hits {
+            let projectile = self.remove_projectile(handle);
+            events.push(SimulationEvent::ProjectileHit { projectile: projectile.id(), shooter: projectile.shooter, damage: projectile.damage, part });
+        }
+    }
+
+    fn remove_projectile(&mut self, handle: MyHandle) -> projectiles::Projectile {
+        let projectile = self.world.remove_projectile_unprotected(handle);
+        self.colliders.remove(projectile.collider());
+        projectile
+    }
+
+    /// Hurts parts by how hard they hit whatever they're touching: the impulse the contact solver needed to keep them apart this step.
+    /// Joints and thrusters don't push through contacts, so being yanked around by them doesn't hurt, and neither do
+    /// two parts of the same ship pressing on each other.
+    fn impact_damage(&mut self, events: &mut Vec<SimulationEvent>) {
+        let config = crate::config::config();
+        let impacts = std::mem::take(&mut *self.impacts.lock().unwrap());
+        if config.impact_damage <= 0.0 { return };
+        let mut impulses: BTreeMap<MyHandle, f32> = BTreeMap::new();
+        for ((body1, body2), impulse) in impacts {
+            let owner = |body| self.world.get_part(body).and_then(|part| part.part_of_player());
+            if let (Some(owner1), Some(owner2)) = (owner(body1), owner(body2)) { if owner1 == owner2 { continue } }
+            *impulses.entry(body1).or_default() += impulse;
+            *impulses.entry(body2).or_default() += impulse;
+        }
+        for (handle, impulse) in impulses {
+            let part = match self.world.get_part_mut(handle) { Some(part) => part, None => continue };
+            let hurt = (impulse - config.impact_damage_threshold) * config.impact_damage;
+            if hurt < 1.0 { continue };
+            part.set_health(part.health().saturating_sub(hurt.min(u16::MAX as f32) as u16));
+            events.push(SimulationEvent::PartDamaged { part: handle });
+        }
     }
 
     pub fn equip_mouse_dragging(&mut self, part: MyHandle) -> DefaultJointConstraintHandle {
@@ -152,6 +276,25 @@ impl Simulation {
 
     pub fn geometrical_world(&self) -> &MyGeometricalWorld { &self.geometry }
 
+    /// Hash of where every body is and how it's moving, for telling when a replay stops matching the original run.
+    /// Recordings outlive the build that made them, so this sticks to a hash that's spelled out here rather than whatever std uses.
+    pub fn checksum(&self) -> u64 {
+        use std::hash::Hasher;
+        let mut hasher = Fnv1a::default();
+        hasher.write_u64(self.planets.time.to_bits());
+        for (_handle, object) in &self.world.storage {
+            if let WorldlyObject::Part(part) = object { hasher.write_u16(part.id()); }
+            if let Some(body) = object.rigid() {
+                let position = body.position();
+                let velocity = body.velocity();
+                for value in &[position.translation.x, position.translation.y, position.rotation.re, position.rotation.im, velocity.linear.x, velocity.linear.y, velocity.angular] {
+                    hasher.write_u32(value.to_bits());
+                }
+            }
+        }
+        hasher.finish()
+    }
+
     pub fn inflate(&mut self, parts: &RecursivePartDescription, initial_location: MyIsometry) -> MyHandle {
         parts.inflate(&mut (&mut self.world).into(), &mut self.colliders, &mut self.joints, initial_location)
     }
@@ -167,11 +310,14 @@ pub struct World {
     storage: MyStorage,
     removal_events: std::collections::VecDeque<MyHandle>,
     reference_point_body: Index,
+    /// Kept per world rather than per process, so a replay numbers its parts the same as the run it came from
+    next_part_id: u16,
 }
 
 pub enum WorldlyObject {
     CelestialObject(MyRigidBody),
     Part(Part),
+    Projectile(projectiles::Projectile),
     Uninitialized,
 }
 impl WorldlyObject {
@@ -179,6 +325,7 @@ impl WorldlyObject {
          match self {
             WorldlyObject::Part(part) => Some(part.body()),
             WorldlyObject::CelestialObject(body) => Some(body),
+            WorldlyObject::Projectile(projectile) => Some(projectile.body()),
             WorldlyObject::Uninitialized => None
         }
     }
@@ -186,6 +333,7 @@ impl WorldlyObject {
         match self {
             WorldlyObject::Part(part) => Some(part.body_mut()),
             WorldlyObject::CelestialObject(body) => Some(body),
+            WorldlyObject::Projectile(projectile) => Some(projectile.body_mut()),
             WorldlyObject::Uninitialized => None
         }
     }
@@ -203,6 +351,11 @@ impl<'a> WorldAddHandle<'a> {
         }
     }
     pub fn deconstruct(self) -> &'a mut World { self.0 }
+    pub fn next_part_id(&mut self) -> u16 {
+        let id = self.0.next_part_id;
+        self.0.next_part_id = id.wrapping_add(1);
+        id
+    }
 }
 impl<'a> From<&'a mut World> for WorldAddHandle<'a> {
     fn from(world: &'a mut World) -> WorldAddHandle<'a> { WorldAddHandle(world) }
@@ -231,6 +384,9 @@ impl World {
             _ => panic!("Delete part called on non-part")
         }
     }
+    pub fn get_projectile(&self, index: MyHandle) -> Option<&projectiles::Projectile> {
+        self.storage.get(index).map(|obj| match obj { WorldlyObject::Projectile(projectile) => Some(projectile), _ => None }).flatten()
+    }
     pub fn add_celestial_object(&mut self, body: MyRigidBody) -> MyHandle { self.storage.insert(WorldlyObject::CelestialObject(body)) }
 
     pub fn recurse_part<'a, F>(&'a self, part_handle: MyHandle, details: PartVisitDetails, func: &mut F)
@@ -349,6 +505,20 @@ impl World {
         } else { panic!("remove_part_unprotected") }
     }
 
+    pub fn remove_projectile_unprotected(&mut self, handle: MyHandle) -> projectiles::Projectile {
+        if let Some(WorldlyObject::Projectile(projectile)) = self.storage.remove(handle) {
+            self.removal_events.push_back(handle);
+            projectile
+        } else { panic!("remove_projectile_unprotected") }
+    }
+
+    pub fn iter_projectiles<'a>(&'a self) -> Box<dyn Iterator<Item=(MyHandle, &'a projectiles::Projectile)> + 'a> {
+        Box::new(self.storage.iter().filter_map(|(handle, obj)| if let WorldlyObject::Projectile(projectile) = obj { Some((handle, projectile)) } else { None }))
+    }
+    pub fn iter_projectiles_mut<'a>(&'a mut self) -> Box<dyn Iterator<Item=(MyHandle, &'a mut projectiles::Projectile)> + 'a> {
+        Box::new(self.storage.iter_mut().filter_map(|(handle, obj)| if let WorldlyObject::Projectile(projectile) = obj { Some((handle, projectile)) } else { None }))
+    }
+
     pub fn iter_parts_mut<'a>(&'a mut self) -> Box<dyn Iterator<Item=(MyHandle, &'a mut Part)> + 'a> {
         Box::new(self.storage.iter_mut().filter_map(|(handle, obj)| if let WorldlyObject::Part(part) = obj { Some((handle, part)) } else { None }))
     }
@@ -362,6 +532,7 @@ impl Default for World {
             storage,
             reference_point_body,
             removal_events: std::collections::VecDeque::<MyHandle>::new(),
+            next_part_id: 0,
         }
     }
 }
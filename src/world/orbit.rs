@@ -0,0 +1,149 @@
+use nalgebra::Vector2;
+
+/// A Keplerian orbit around another celestial object. Bodies on one are moved along it kinematically each tick
+/// rather than being pulled around by gravity, so orbits never decay and clients can extrapolate them exactly.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Orbit {
+    /// Id of the celestial object at the focus
+    pub parent: u16,
+    pub semi_major_axis: f32,
+    pub eccentricity: f32,
+    /// Angle of the closest approach from the x axis, counter clockwise
+    pub argument_of_periapsis: f32,
+    /// Where along the orbit the body was at time 0
+    pub mean_anomaly_at_epoch: f32,
+    /// Radians per second. Counter clockwise orbits only.
+    pub mean_motion: f32,
+}
+
+impl Orbit {
+    /// Mean motion for an orbit of this size by Kepler's third law, `sqrt(G * (M + m) / a³)`
+    pub fn kepler_mean_motion(semi_major_axis: f32, parent_mass: f32, mass: f32, gravitational_constant: f32) -> f32 {
+        (gravitational_constant * (parent_mass + mass) / semi_major_axis.powi(3)).max(0.0).sqrt()
+    }
+
+    /// A circular orbit through `relative_position` (from the parent)
+    pub fn circular(parent: u16, relative_position: Vector2<f32>, mean_motion: f32) -> Orbit {
+        Orbit {
+            parent,
+            semi_major_axis: relative_position.norm(),
+            eccentricity: 0.0,
+            argument_of_periapsis: 0.0,
+            mean_anomaly_at_epoch: relative_position.y.atan2(relative_position.x),
+            mean_motion,
+        }
+    }
+
+    /// Always between 0 and 2π, which is where `eccentric_anomaly`'s first guess for eccentric orbits is good for
+    pub fn mean_anomaly(&self, time: f64) -> f32 {
+        (self.mean_anomaly_at_epoch as f64 + self.mean_motion as f64 * time).rem_euclid(std::f64::consts::TAU) as f32
+    }
+
+    /// Solves Kepler's equation `M = E - e sin E` by Newton's method
+    fn eccentric_anomaly(&self, mean_anomaly: f32) -> f32 {
+        let e = self.eccentricity;
+        let mut eccentric_anomaly = if e > 0.8 { std::f32::consts::PI } else { mean_anomaly };
+        for _ in 0..16 {
+            let delta = (eccentric_anomaly - e * eccentric_anomaly.sin() - mean_anomaly) / (1.0 - e * eccentric_anomaly.cos());
+            eccentric_anomaly -= delta;
+            if delta.abs() < 1e-6 { break };
+        }
+        eccentric_anomaly
+    }
+
+    /// Position and velocity relative to the parent
+    pub fn state_at(&self, time: f64) -> (Vector2<f32>, Vector2<f32>) {
+        let a = self.semi_major_axis;
+        let e = self.eccentricity;
+        let eccentric_anomaly = self.eccentric_anomaly(self.mean_anomaly(time));
+        let (sin_e, cos_e) = eccentric_anomaly.sin_cos();
+        let semi_minor_axis = a * (1.0 - e * e).sqrt();
+        let position = Vector2::new(a * (cos_e - e), semi_minor_axis * sin_e);
+        let eccentric_anomaly_rate = self.mean_motion / (1.0 - e * cos_e);
+        let velocity = Vector2::new(-a * sin_e, semi_minor_axis * cos_e) * eccentric_anomaly_rate;
+        let rotation = nalgebra::Rotation2::new(self.argument_of_periapsis);
+        (rotation * position, rotation * velocity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::{PI, TAU};
+
+    fn orbit(eccentricity: f32) -> Orbit {
+        Orbit { parent: 0, semi_major_axis: 1000.0, eccentricity, argument_of_periapsis: 0.7, mean_anomaly_at_epoch: 0.3, mean_motion: 0.05 }
+    }
+
+    fn assert_close(a: Vector2<f32>, b: Vector2<f32>, tolerance: f32) {
+        assert!((a - b).norm() <= tolerance, "{:?} isn't within {} of {:?}", a, tolerance, b);
+    }
+
+    #[test]
+    fn eccentric_anomaly_solves_keplers_equation() {
+        for &e in &[0.0, 0.3, 0.85, 0.99] {
+            let orbit = orbit(e);
+            for step in 0..64 {
+                let mean_anomaly = TAU * step as f32 / 64.0;
+                let eccentric_anomaly = orbit.eccentric_anomaly(mean_anomaly);
+                let error = eccentric_anomaly - e * eccentric_anomaly.sin() - mean_anomaly;
+                assert!(error.abs() < 1e-4, "e = {}, M = {}: off by {}", e, mean_anomaly, error);
+            }
+        }
+        //Circular orbits have nothing to solve
+        assert_eq!(orbit(0.0).eccentric_anomaly(1.25), 1.25);
+    }
+
+    #[test]
+    fn velocity_matches_how_the_position_moves_over_a_tick() {
+        let tick = 1.0 / 20.0;
+        for &e in &[0.0, 0.9] {
+            let orbit = orbit(e);
+            //Periapsis is where an eccentric orbit moves fastest and turns hardest
+            let periapsis = ((TAU - orbit.mean_anomaly_at_epoch) / orbit.mean_motion) as f64;
+            for &time in &[0.0, 10.0, 50.0, periapsis] {
+                let (before, _) = orbit.state_at(time - tick / 2.0);
+                let (after, _) = orbit.state_at(time + tick / 2.0);
+                let (_, velocity) = orbit.state_at(time);
+                assert_close((after - before) / tick as f32, velocity, velocity.norm() * 0.01);
+            }
+        }
+    }
+
+    #[test]
+    fn orbits_come_back_round_after_a_period() {
+        for &e in &[0.0, 0.5, 0.9] {
+            let orbit = orbit(e);
+            let period = (TAU / orbit.mean_motion) as f64;
+            let (start, start_velocity) = orbit.state_at(3.0);
+            let (end, end_velocity) = orbit.state_at(3.0 + period);
+            assert_close(end, start, 0.5);
+            assert_close(end_velocity, start_velocity, 0.05);
+            //Half way round is the other side of the focus
+            let (half, _) = orbit.state_at(3.0 + period / 2.0);
+            assert!((half - start).norm() > orbit.semi_major_axis * (1.0 - e));
+        }
+    }
+
+    #[test]
+    fn mean_anomaly_wraps_round_instead_of_growing() {
+        let orbit = orbit(0.5);
+        let period = (TAU / orbit.mean_motion) as f64;
+        assert!((orbit.mean_anomaly(0.0) - 0.3).abs() < 1e-6);
+        //Long after the epoch it's still small enough for f32 to place the body precisely
+        let much_later = 1000.0 * period + 3.0;
+        assert!((0.0..TAU).contains(&orbit.mean_anomaly(much_later)));
+        assert!((orbit.mean_anomaly(much_later) - orbit.mean_anomaly(3.0)).abs() < 1e-3);
+        assert_close(orbit.state_at(much_later).0, orbit.state_at(3.0).0, 1.0);
+        //A body set off below the x axis starts at a negative angle, but is still put in range
+        let circular = Orbit::circular(0, Vector2::new(0.0, -10.0), 1.0);
+        assert!((circular.mean_anomaly(0.0) - 1.5 * PI).abs() < 1e-4);
+        assert_close(circular.state_at(0.0).0, Vector2::new(0.0, -10.0), 1e-3);
+        let mut eccentric = orbit;
+        eccentric.eccentricity = 0.9;
+        eccentric.mean_anomaly_at_epoch = -3.0;
+        let (position, _) = eccentric.state_at(0.0);
+        assert!(position.norm() <= eccentric.semi_major_axis * 1.9 + 1.0);
+        assert_close(position, eccentric.state_at((TAU / eccentric.mean_motion) as f64).0, 0.5);
+    }
+}
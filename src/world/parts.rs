@@ -11,7 +11,6 @@ use crate::PlayerMeta;
 use crate::codec::ToClientMsg;
 use super::{WorldAddHandle, World, WorldlyObject};
 use crate::session::WorldUpdatePartMove;
-use std::sync::atomic::{AtomicU16, Ordering as AtomicOrdering};
 
 
 lazy_static! {
@@ -21,11 +20,10 @@ lazy_static! {
     static ref ATTACHMENT_COLLIDER_CUBOID: ShapeHandle<MyUnits> = ShapeHandle::new(Cuboid::new(Vector2::new(1.0, 1.0)));
     static ref SUPER_THRUSTER_CUBOID: ShapeHandle<MyUnits> = ShapeHandle::new(Cuboid::new(Vector2::new(0.38, 0.44)));
 }
-static mut NEXT_PART_ID: AtomicU16 = AtomicU16::new(0);
 
 pub const ATTACHMENT_COLLIDER_COLLISION_GROUP: [usize; 1] = [5];
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct RecursivePartDescription {
     pub kind: PartKind,
     pub attachments: Vec<Option<RecursivePartDescription>>,
@@ -38,6 +36,7 @@ pub struct Part {
     attachments: [Option<PartAttachment>; 4],
     pub thrust_mode: CompactThrustMode,
     part_of_player: Option<u16>,
+    health: u16,
 }
 pub struct PartAttachment {
     part: MyHandle,
@@ -69,7 +68,7 @@ impl RecursivePartDescription {
                 } else { None }
             }).flatten();
         };
-        let my_part_id = if let Some(id) = id { id } else { unsafe { NEXT_PART_ID.fetch_add(1, AtomicOrdering::AcqRel) } };
+        let my_part_id = id.unwrap_or_else(|| bodies.next_part_id());
         let part = Part {
             id: my_part_id,
             body,
@@ -78,6 +77,7 @@ impl RecursivePartDescription {
             attachments,
             thrust_mode: CompactThrustMode::calculate(true_facing, rel_part_x, rel_part_y),
             part_of_player: None,
+            health: self.kind.max_health(),
         };
         bodies.add_its_later(body_handle, WorldlyObject::Part(part));
         body_handle
@@ -209,6 +209,10 @@ impl Part {
     pub fn kind(&self) -> PartKind { self.kind }
     pub fn body(&self) -> &MyRigidBody { &self.body }
     pub fn body_mut(&mut self) -> &mut MyRigidBody { &mut self.body }
+    pub fn health(&self) -> u16 { self.health }
+    /// Clamped to what the kind can take
+    pub fn set_health(&mut self, health: u16) { self.health = health.min(self.kind.max_health()); }
+    pub fn is_damaged(&self) -> bool { self.health < self.kind.max_health() }
 
     pub fn inflation_msgs(&self) -> [ToClientMsg; 3] {
         [ self.add_msg(), self.move_msg(), self.update_meta_msg() ]
@@ -220,6 +224,7 @@ impl Part {
     } }
     pub fn update_meta_msg(&self) -> ToClientMsg { ToClientMsg::UpdatePartMeta { id: self.id, owning_player: self.part_of_player, thrust_mode: self.thrust_mode.into() } }
     pub fn remove_msg(&self) -> ToClientMsg { ToClientMsg::RemovePart { id: self.id } }
+    pub fn health_msg(&self) -> ToClientMsg { ToClientMsg::UpdatePartHealth { id: self.id, health: self.health, max_health: self.kind.max_health() } }
 
     pub fn physics_update_msg(&self, bodies: &MyBodySet, out: &mut Vec<WorldUpdatePartMove>) {
         let position = self.body.position();
@@ -243,7 +248,7 @@ impl PartAttachment {
         if let Some(attachment) = parent.attachment_locations()[attachment_slot] {
             Some(MyIsometry::new(parent_location.transform_point(&Point2::new(attachment.x, attachment.y)).coords, attachment.facing.part_rotation() + parent_location.rotation.angle()))
         } else {
-            eprintln!("calculate_attachment_position: PartKind {:?} doesn't have attachment slot {}", parent, attachment_slot);
+            warn!("world"; "calculate_attachment_position: PartKind {:?} doesn't have attachment slot {}", parent, attachment_slot);
             None
         }
     }
@@ -269,12 +274,11 @@ impl PartAttachment {
             Point::new(-HALF_CONNECTION_WIDTH, 0f32),
             nalgebra::UnitComplex::new(-attachment.facing.part_rotation()),
         );
-        const MAX_TORQUE: f32 = 200.0;
-        const MAX_FORCE: f32 = MAX_TORQUE * 3.5;
-        constraint1.set_break_torque(MAX_TORQUE);
-        constraint1.set_break_force(MAX_FORCE);
-        constraint2.set_break_torque(MAX_TORQUE);
-        constraint2.set_break_force(MAX_FORCE);
+        let config = crate::config::config();
+        constraint1.set_break_torque(config.joint_break_torque);
+        constraint1.set_break_force(config.joint_break_force);
+        constraint2.set_break_torque(config.joint_break_torque);
+        constraint2.set_break_force(config.joint_break_force);
         PartAttachment {
             part,
             connections: (joints.insert(constraint1), joints.insert(constraint2))
@@ -301,6 +305,14 @@ impl std::ops::Deref for PartAttachment {
 
 pub use crate::codec::PartKind;
 impl PartKind {
+    pub const ALL: [PartKind; 12] = [
+        PartKind::Core, PartKind::Cargo, PartKind::LandingThruster, PartKind::Hub, PartKind::SolarPanel, PartKind::EcoThruster,
+        PartKind::Thruster, PartKind::SuperThruster, PartKind::PowerHub, PartKind::HubThruster, PartKind::LandingWheel, PartKind::Cannon,
+    ];
+    /// By the variant name, as data files spell it
+    pub fn from_name(name: &str) -> Option<PartKind> {
+        PartKind::ALL.iter().copied().find(|kind| format!("{:?}", kind) == name)
+    }
     pub fn physics_components(&self) -> (RigidBodyDesc<MyUnits>, ColliderDesc<MyUnits>) {
         match self {
             _ => {
@@ -308,7 +320,7 @@ impl PartKind {
                     RigidBodyDesc::new().status(BodyStatus::Dynamic).local_inertia(self.inertia()),
                     ColliderDesc::new( match self {
                         PartKind::Core | PartKind::Hub | PartKind::PowerHub | PartKind::HubThruster => UNIT_CUBOID.clone(),
-                        PartKind::Cargo | PartKind::LandingThruster | PartKind::Thruster => CARGO_CUBOID.clone(),
+                        PartKind::Cargo | PartKind::LandingThruster | PartKind::Thruster | PartKind::Cannon => CARGO_CUBOID.clone(),
                         PartKind::SolarPanel | PartKind::EcoThruster | PartKind::LandingWheel => SOLAR_PANEL_CUBOID.clone(), 
                         PartKind::SuperThruster => SUPER_THRUSTER_CUBOID.clone(),
                     } )
@@ -331,7 +343,7 @@ impl PartKind {
             PartKind::SuperThruster => Some(ThrustDetails { fuel_cost: 7, force: Force2::linear_at_point(Vector2::new(0.0, -13.5), &Point2::new(0.0, 1.0)) }),
             PartKind::HubThruster => Some(ThrustDetails { fuel_cost: 4, force: Force2::linear_at_point(Vector2::new(0.0, -6.0), &Point2::new(0.0, 1.0)) }),
             PartKind::EcoThruster => Some(ThrustDetails { fuel_cost: 1, force: Force2::linear_at_point(Vector2::new(0.0, -5.5), &Point2::new(0.0, 1.0)) }),
-            PartKind::PowerHub | PartKind::LandingWheel => None,
+            PartKind::PowerHub | PartKind::LandingWheel | PartKind::Cannon => None,
         }
     }
     pub fn inertia(&self) -> Inertia2<MyUnits> {
@@ -347,6 +359,7 @@ impl PartKind {
             PartKind::EcoThruster => Inertia2::new(1.35, 1.35),
             PartKind::PowerHub => Inertia2::new(1.1, 1.1),
             PartKind::LandingWheel => Inertia2::new(0.75, 0.75),
+            PartKind::Cannon => Inertia2::new(1.2, 1.2),
         }
     }
     pub fn attachment_locations(&self) -> [Option<AttachmentPointDetails>; 4] {
@@ -363,7 +376,7 @@ impl PartKind {
                 Some(AttachmentPointDetails{ x: 0.0, y: 1.1, facing: AttachedPartFacing::Up, perpendicular: (1.0, 0.0) }),
                 Some(AttachmentPointDetails{ x: -0.6, y: 0.5, facing: AttachedPartFacing::Right, perpendicular: (0.0, 1.0) }),
             ],
-            PartKind::Cargo | PartKind::LandingThruster | PartKind::SolarPanel | PartKind::Thruster | PartKind::SuperThruster | PartKind::EcoThruster | PartKind::LandingWheel => [ None, None, None, None ],
+            PartKind::Cargo | PartKind::LandingThruster | PartKind::SolarPanel | PartKind::Thruster | PartKind::SuperThruster | PartKind::EcoThruster | PartKind::LandingWheel | PartKind::Cannon => [ None, None, None, None ],
             PartKind::HubThruster => [
                 None,
                 Some(AttachmentPointDetails{ x: 0.6, y: 0.5, facing: AttachedPartFacing::Left, perpendicular: (0.0, -1.0) }),
@@ -373,17 +386,40 @@ impl PartKind {
         }
     }
     pub fn power_storage(&self) -> u32 {
-        const CORE_MAX_POWER: u32 = 100 * crate::TICKS_PER_SECOND as u32;
+        let core_max_power = crate::config::config().core_max_power;
         match self {
-            PartKind::Core => CORE_MAX_POWER,
-            PartKind::Cargo => 0, //CORE_MAX_POWER / 10,
-            PartKind::LandingThruster | PartKind::HubThruster => CORE_MAX_POWER / 5,
-            PartKind::Hub => CORE_MAX_POWER / 3,
+            PartKind::Core => core_max_power,
+            PartKind::Cargo => 0, //core_max_power / 10,
+            PartKind::LandingThruster | PartKind::HubThruster => core_max_power / 5,
+            PartKind::Hub => core_max_power / 3,
             PartKind::SolarPanel => 0,
-            PartKind::Thruster | PartKind::SuperThruster => CORE_MAX_POWER / 4,
-            PartKind::EcoThruster => CORE_MAX_POWER / 6,
-            PartKind::PowerHub => CORE_MAX_POWER / 3 * 2,
-            PartKind::LandingWheel => 0,
+            PartKind::Thruster | PartKind::SuperThruster => core_max_power / 4,
+            PartKind::EcoThruster => core_max_power / 6,
+            PartKind::PowerHub => core_max_power / 3 * 2,
+            PartKind::LandingWheel | PartKind::Cannon => 0,
+        }
+    }
+    /// Hit points of a fresh part. Landing gear is built to take a beating, solar panels aren't
+    pub fn max_health(&self) -> u16 {
+        match self {
+            PartKind::Core => 200,
+            PartKind::Hub => 100,
+            PartKind::Cargo => 60,
+            PartKind::LandingThruster => 160,
+            PartKind::Thruster | PartKind::HubThruster => 100,
+            PartKind::SuperThruster => 120,
+            PartKind::EcoThruster => 80,
+            PartKind::SolarPanel => 40,
+            PartKind::PowerHub => 120,
+            PartKind::LandingWheel => 250,
+            PartKind::Cannon => 90,
+        }
+    }
+    /// What it shoots, for parts that are weapons. Shots go out the far end of the part, away from whatever it's attached to
+    pub fn weapon(&self) -> Option<WeaponDetails> {
+        match self {
+            PartKind::Cannon => Some(WeaponDetails { power_cost: 40, reload_seconds: 0.5, speed: 40.0, range: 60.0, damage: 25 }),
+            _ => None,
         }
     }
     pub fn power_regen_per_5_ticks(&self) -> u32 {
@@ -531,3 +567,13 @@ impl Default for CompactThrustMode {
 // }
 
 struct ThrustDetails { fuel_cost: u32, force: Force2<MyUnits> }
+#[derive(Copy, Clone, Debug)]
+pub struct WeaponDetails {
+    pub power_cost: u32,
+    pub reload_seconds: f32,
+    /// Relative to the part firing it
+    pub speed: f32,
+    /// How far a shot goes before it fizzles out
+    pub range: f32,
+    pub damage: u16,
+}
@@ -1,469 +1,304 @@
 use super::{MyUnits, MyHandle};
-use nphysics2d::object::{RigidBody, RigidBodyDesc, BodyStatus, BodyPartHandle};
+use nphysics2d::object::{RigidBodyDesc, BodyStatus, BodyPartHandle};
 use nphysics2d::object::ColliderDesc;
 use ncollide2d::shape::{Ball, ShapeHandle};
 use nalgebra::Vector2;
 use nphysics2d::material::{BasicMaterial, MaterialHandle};
 use rand::Rng;
+use serde::de::{Deserialize, Deserializer, Error};
+use serde::ser::{Serialize, Serializer};
+use std::collections::BTreeMap;
 use super::parts::PartKind;
+use super::orbit::Orbit;
+use super::atmosphere::{Atmosphere, Air};
 
-pub struct Planets {
-    pub earth: CelestialObject,
-    pub moon: CelestialObject,
-    pub planet_material: MaterialHandle<MyUnits>,
-    pub mars: CelestialObject,
-    pub mercury: CelestialObject,
-    pub jupiter: CelestialObject,
-    //pub pluto: CelestialObject,
-    pub saturn: CelestialObject,
-    pub neptune: CelestialObject,
-    pub venus: CelestialObject,
-    pub uranus: CelestialObject,
-    pub sun: CelestialObject,
-    //pub trade: CelestialObject,
-}
-impl Planets {
-    pub fn new(colliders: &mut super::MyColliderSet, bodies: &mut super::World) -> Planets {
-        const EARTH_MASS: f32 = 600.0;
-        const EARTH_SIZE: f32 = 25.0;
-        let earth_pos = planet_location(1500.0);
-        let planet_material = MaterialHandle::new(BasicMaterial::new(0.0, 1.0));
-        let earth = {
-            let body = RigidBodyDesc::new()
-            .translation(earth_pos.clone())
-                .gravity_enabled(false)
-                .status(BodyStatus::Static)
-                .mass(EARTH_MASS)
-                .build();
-            let position = (body.position().translation.x, body.position().translation.y);
-            let mass = body.augmented_mass().linear;
-            let body_handle = bodies.add_celestial_object(body);
-            const RADIUS: f32 = EARTH_SIZE;
-            let shape = ShapeHandle::new(Ball::new(RADIUS));
-			let id = make_planet_id();
-            let collider = ColliderDesc::new(shape)
-                .material(planet_material.clone())
-				.user_data(AmPlanet {id})
-                .build(BodyPartHandle(body_handle, 0));
-            colliders.insert(collider);
-
-            
-            
-            CelestialObject {
-                name: String::from("earth"),
-                display_name: String::from("Earth"),
-                radius: RADIUS,
-                body: body_handle,
-                id,
-                cargo_upgrade: None,
-                can_beamout: true,
-                position: (earth_pos.x, earth_pos.y),
-                mass,
-            }
-        };
-
-        let moon = {
-            let body = RigidBodyDesc::new()
-            .translation(planet_location(100.0) + earth_pos.clone())
-                .gravity_enabled(false)
-                .status(BodyStatus::Static)
-                .mass(EARTH_MASS / 35.0)
-                .build();
-            let position = (body.position().translation.x, body.position().translation.y);
-            let mass = body.augmented_mass().linear;
-            let body_handle = bodies.add_celestial_object(body);
-            const RADIUS: f32 = EARTH_SIZE / 4.0;
-            let shape = ShapeHandle::new(Ball::new(RADIUS));
-			let id = make_planet_id();
-            let collider = ColliderDesc::new(shape)
-                .material(planet_material.clone())
-				.user_data(AmPlanet {id})
-                .build(BodyPartHandle(body_handle, 0));
-            colliders.insert(collider);
-
-            
-            
-            CelestialObject {
-                name: String::from("moon"),
-                display_name: String::from("Moon"),
-                radius: RADIUS,
-                body: body_handle,
-                id,
-                cargo_upgrade: Some(super::parts::PartKind::LandingThruster),
-                can_beamout: true,
-                position,
-                mass,
-            }
-        };
-
-        let mars = {
-            let body = RigidBodyDesc::new()
-                .translation(planet_location(2000.0))
-                .gravity_enabled(false)
-                .status(BodyStatus::Static)
-                .mass(EARTH_MASS / 4.0)
-                .build();
-            let position = (body.position().translation.x, body.position().translation.y);
-            let mass = body.augmented_mass().linear;
-            let body_handle = bodies.add_celestial_object(body);
-            const RADIUS: f32 = EARTH_SIZE / 2.0;
-            let shape = ShapeHandle::new(Ball::new(RADIUS));
-			let id = make_planet_id();
-            let collider = ColliderDesc::new(shape)
-                .material(planet_material.clone())
-				.user_data(AmPlanet {id})
-                .build(BodyPartHandle(body_handle, 0));
-            colliders.insert(collider);
-
-            
-            
-            CelestialObject {
-                name: String::from("mars"),
-                display_name: String::from("Mars"),
-                radius: RADIUS,
-                body: body_handle,
-                id,
-                cargo_upgrade: Some(super::parts::PartKind::Hub),
-                can_beamout: false,
-                position,
-                mass,
-            }
-        };
-
-        let mercury = {
-            let body = RigidBodyDesc::new()
-                .translation(planet_location(500.0))
-                .gravity_enabled(false)
-                .status(BodyStatus::Static)
-                .mass(EARTH_MASS / 15.0)
-                .build();
-            let position = (body.position().translation.x, body.position().translation.y);
-            let mass = body.augmented_mass().linear;
-            let body_handle = bodies.add_celestial_object(body);
-            const RADIUS: f32 = EARTH_SIZE * 0.38;
-            let shape = ShapeHandle::new(Ball::new(RADIUS));
-			let id = make_planet_id();
-            let collider = ColliderDesc::new(shape)
-                .material(planet_material.clone())
-				.user_data(AmPlanet {id})
-                .build(BodyPartHandle(body_handle, 0));
-            colliders.insert(collider);
-
-            
-            
-            CelestialObject {
-                name: String::from("mercury"),
-                display_name: String::from("Mercury"),
-                radius: RADIUS,
-                body: body_handle,
-                id,
-                cargo_upgrade: Some(super::parts::PartKind::SolarPanel),
-                can_beamout: false,
-                position,
-                mass,
-            }
-        };
-
-        let jupiter = {
-            let body = RigidBodyDesc::new()
-                .translation(planet_location(3500.0))
-                .gravity_enabled(false)
-                .status(BodyStatus::Static)
-                .mass(EARTH_MASS * 10.0)
-                .build();
-            let position = (body.position().translation.x, body.position().translation.y);
-            let mass = body.augmented_mass().linear;
-            let body_handle = bodies.add_celestial_object(body);
-            const RADIUS: f32 = EARTH_SIZE * 2.0;
-            let shape = ShapeHandle::new(Ball::new(RADIUS));
-			let id = make_planet_id();
-            let collider = ColliderDesc::new(shape)
-                .material(planet_material.clone())
-				.user_data(AmPlanet {id})
-                .build(BodyPartHandle(body_handle, 0));
-            colliders.insert(collider);
-
-            
-
-            CelestialObject {
-                name: String::from("jupiter"),
-                display_name: String::from("jupiter"),
-                radius: RADIUS,
-                body: body_handle,
-                id,
-                cargo_upgrade: Some(super::parts::PartKind::Thruster),
-                can_beamout: false,
-                position,
-                mass,
-            }
-        };
-
-        /*let pluto = {
-            let body = RigidBodyDesc::new()
-                .translation(planet_location(6000.0))
-                .gravity_enabled(false)
-                .status(BodyStatus::Static)
-                .mass(EARTH_MASS / 10.0)
-                .build();
-            let position = (body.position().translation.x, body.position().translation.y);
-            let mass = body.augmented_mass().linear;
-            let body_handle = bodies.add_celestial_object(body);
-            const RADIUS: f32 = EARTH_SIZE / 4.0;
-            let shape = ShapeHandle::new(Ball::new(RADIUS));
-			let id = make_planet_id();
-            let collider = ColliderDesc::new(shape)
-                .material(planet_material.clone())
-				.user_data(AmPlanet {id})
-                .build(BodyPartHandle(body_handle, 0));
-            colliders.insert(collider);
+/// The solar system the server ships with, used when `planets` isn't set in the config
+pub const DEFAULT_PLANETS: &str = include_str!("../../planets.json");
 
-            
-
-            CelestialObject {
-                name: String::from("pluto"),
-                display_name: String::from("pluto"),
-                radius: RADIUS,
-                body: body_handle,
-                id,
-                cargo_upgrade: Some(PartKind::LandingWheel),
-                can_beamout: false,
-                position,
-                mass,
-            }
-        };*/
-
-        let saturn = {
-            let body = RigidBodyDesc::new()
-                .translation(planet_location(4000.0))
-                .gravity_enabled(false)
-                .status(BodyStatus::Static)
-                .mass(EARTH_MASS * 10.0)
-                .build();
-            let position = (body.position().translation.x, body.position().translation.y);
-            let mass = body.augmented_mass().linear;
-            let body_handle = bodies.add_celestial_object(body);
-            const RADIUS: f32 = EARTH_SIZE * 2.0;
-            let shape = ShapeHandle::new(Ball::new(RADIUS));
-			let id = make_planet_id();
-            let collider = ColliderDesc::new(shape)
-                .material(planet_material.clone())
-				.user_data(AmPlanet {id})
-                .build(BodyPartHandle(body_handle, 0));
-            colliders.insert(collider);
-
-            
-
-            CelestialObject {
-                name: String::from("saturn"),
-                display_name: String::from("saturn"),
-                radius: RADIUS,
-                body: body_handle,
-                id,
-                cargo_upgrade: Some(PartKind::SuperThruster),
-                can_beamout: false,
-                position,
-                mass,
-            }
-        };
-
-        let neptune = {
-            let body = RigidBodyDesc::new()
-                .translation(planet_location(5500.0))
-                .gravity_enabled(false)
-                .status(BodyStatus::Static)
-                .mass(EARTH_MASS * 4.0)
-                .build();
-            let position = (body.position().translation.x, body.position().translation.y);
-            let mass = body.augmented_mass().linear;
-            let body_handle = bodies.add_celestial_object(body);
-            const RADIUS: f32 = EARTH_SIZE * 1.5;
-            let shape = ShapeHandle::new(Ball::new(RADIUS));
-			let id = make_planet_id();
-            let collider = ColliderDesc::new(shape)
-                .material(planet_material.clone())
-				.user_data(AmPlanet {id})
-                .build(BodyPartHandle(body_handle, 0));
-            colliders.insert(collider);
-
-            
-
-            CelestialObject {
-                name: String::from("neptune"),
-                display_name: String::from("neptune"),
-                radius: RADIUS,
-                body: body_handle,
-                id,
-                cargo_upgrade: Some(PartKind::HubThruster),
-                can_beamout: false,
-                position,
-                mass,
-            }
-        };
-
-        let venus = {
-            let body = RigidBodyDesc::new()
-                .translation(planet_location(1000.0))
-                .gravity_enabled(false)
-                .status(BodyStatus::Static)
-                .mass(EARTH_MASS * 1.3)
-                .build();
-            let position = (body.position().translation.x, body.position().translation.y);
-            let mass = body.augmented_mass().linear;
-            let body_handle = bodies.add_celestial_object(body);
-            const RADIUS: f32 = EARTH_SIZE;
-            let shape = ShapeHandle::new(Ball::new(RADIUS));
-			let id = make_planet_id();
-            let collider = ColliderDesc::new(shape)
-                .material(planet_material.clone())
-				.user_data(AmPlanet {id})
-                .build(BodyPartHandle(body_handle, 0));
-            colliders.insert(collider);
-
-            
+/// One entry of the planets file, a JSON list of these. Ids are handed out in file order, starting from 1.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CelestialObjectDef {
+    pub name: String,
+    pub display_name: String,
+    /// Name of the object this one orbits. Objects without a parent stay put at `position`
+    #[serde(default)]
+    pub parent: Option<String>,
+    /// Distance from the parent. Where along the orbit it starts is random
+    #[serde(default)]
+    pub orbit_radius: f32,
+    #[serde(default)]
+    pub position: (f32, f32),
+    pub mass: f32,
+    pub radius: f32,
+    /// What cargo turns into after sitting on it for a while, by part kind name
+    #[serde(default, serialize_with = "part_kind_name", deserialize_with = "part_kind_by_name")]
+    pub cargo_upgrade: Option<PartKind>,
+    #[serde(default)]
+    pub can_beamout: bool,
+    /// Ships resting on it get their parts' health back, `repair_per_second` at a time
+    #[serde(default)]
+    pub repairs: bool,
+    /// New players and Earth cargo appear around this one. Exactly one object must have it
+    #[serde(default)]
+    pub spawn: bool,
+    #[serde(default)]
+    pub hazards: Hazards,
+    /// Within this far of the surface, weapons can't hurt anyone
+    #[serde(default)]
+    pub safe_zone: f32,
+    /// Air that slows ships down on their way in. Without one, landing is bouncing off bare rock
+    #[serde(default)]
+    pub atmosphere: Option<Atmosphere>,
+    /// Lets an object be kept in the file without being created
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+fn enabled_by_default() -> bool { true }
 
-            CelestialObject {
-                name: String::from("venus"),
-                display_name: String::from("venus"),
-                radius: RADIUS,
-                body: body_handle,
-                id,
-                cargo_upgrade: Some(PartKind::EcoThruster),
-                can_beamout: false,
-                position,
-                mass,
-            }
-        };
+fn part_kind_name<S: Serializer>(kind: &Option<PartKind>, serializer: S) -> Result<S::Ok, S::Error> {
+    kind.map(|kind| format!("{:?}", kind)).serialize(serializer)
+}
+fn part_kind_by_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PartKind>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(name) => PartKind::from_name(&name).map(Some).ok_or_else(|| D::Error::custom(format!("no part kind is named {}", name))),
+        None => Ok(None),
+    }
+}
 
-        let uranus = {
-            let body = RigidBodyDesc::new()
-                .translation(planet_location(4800.0))
-                .gravity_enabled(false)
-                .status(BodyStatus::Static)
-                .mass(EARTH_MASS * 4.0)
-                .build();
-            let position = (body.position().translation.x, body.position().translation.y);
-            let mass = body.augmented_mass().linear;
-            let body_handle = bodies.add_celestial_object(body);
-            const RADIUS: f32 = EARTH_SIZE * 2.0;
-            let shape = ShapeHandle::new(Ball::new(RADIUS));
-			let id = make_planet_id();
-            let collider = ColliderDesc::new(shape)
-                .material(planet_material.clone())
-				.user_data(AmPlanet {id})
-                .build(BodyPartHandle(body_handle, 0));
-            colliders.insert(collider);
+/// What touching an object does to a ship besides landing it
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hazards {
+    /// Burns up the whole ship
+    pub incinerates: bool,
+}
 
-            
+/// Reads the planets file (or the built-in one) and checks that it describes a usable solar system
+pub fn load_definitions(path: Option<&str>) -> Result<Vec<CelestialObjectDef>, String> {
+    let definitions: Vec<CelestialObjectDef> = match path {
+        Some(path) => {
+            let file = std::fs::read_to_string(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+            serde_json::from_str(&file).map_err(|err| format!("Failed to parse {}: {}", path, err))?
+        },
+        None => serde_json::from_str(DEFAULT_PLANETS).map_err(|err| format!("Failed to parse the built-in planets: {}", err))?,
+    };
+    let definitions: Vec<CelestialObjectDef> = definitions.into_iter().filter(|definition| definition.enabled).collect();
+    validate_definitions(&definitions)?;
+    Ok(definitions)
+}
 
-            CelestialObject {
-                name: String::from("uranus"),
-                display_name: String::from("uranus"),
-                radius: RADIUS,
-                body: body_handle,
-                id,
-                cargo_upgrade: Some(PartKind::PowerHub),
-                can_beamout: false,
-                position,
-                mass,
+pub fn validate_definitions(definitions: &[CelestialObjectDef]) -> Result<(), String> {
+    let mut problems = Vec::new();
+    let by_name: BTreeMap<&str, &CelestialObjectDef> = definitions.iter().map(|definition| (definition.name.as_str(), definition)).collect();
+    if by_name.len() != definitions.len() { problems.push(String::from("Every celestial object needs a different name")); }
+    if definitions.len() > u16::MAX as usize - 1 { problems.push(String::from("Too many celestial objects")); }
+    match definitions.iter().filter(|definition| definition.spawn).count() {
+        1 => (),
+        spawns => problems.push(format!("Exactly one celestial object must be the spawn, not {}", spawns)),
+    }
+    for definition in definitions {
+        if !(definition.radius > 0.0 && definition.radius.is_finite()) { problems.push(format!("{} needs a positive radius", definition.name)); }
+        if !(definition.mass > 0.0 && definition.mass.is_finite()) { problems.push(format!("{} needs a positive mass", definition.name)); }
+        if let Some(parent) = &definition.parent {
+            if !by_name.contains_key(parent.as_str()) { problems.push(format!("{} orbits {}, which doesn't exist", definition.name, parent)); }
+            if !(definition.orbit_radius > 0.0 && definition.orbit_radius.is_finite()) { problems.push(format!("{} needs a positive orbit_radius", definition.name)); }
+        }
+        if !(definition.safe_zone >= 0.0 && definition.safe_zone.is_finite()) { problems.push(format!("{} needs a safe_zone that isn't negative", definition.name)); }
+        if let Some(problem) = definition.atmosphere.as_ref().and_then(Atmosphere::problems) { problems.push(format!("{} needs {}", definition.name, problem)); }
+        //Following parents from here has to reach something that doesn't orbit anything
+        let mut ancestor = definition;
+        for _ in 0..=definitions.len() {
+            match ancestor.parent.as_ref().and_then(|parent| by_name.get(parent.as_str())) {
+                Some(parent) => ancestor = parent,
+                None => break,
             }
-        };
+        }
+        if ancestor.parent.as_ref().map(|parent| by_name.contains_key(parent.as_str())).unwrap_or(false) {
+            problems.push(format!("{} is part of a loop of objects orbiting each other", definition.name));
+        }
+    }
+    if problems.is_empty() { Ok(()) } else { Err(problems.join("\n")) }
+}
 
-        let sun = {
+pub struct Planets {
+    objects: BTreeMap<u16, CelestialObject>,
+    spawn: u16,
+    pub planet_material: MaterialHandle<MyUnits>,
+    /// Seconds of orbital motion so far
+    pub time: f64,
+}
+impl Planets {
+    /// `definitions` should already have passed `validate_definitions`
+    pub fn new(definitions: &[CelestialObjectDef], colliders: &mut super::MyColliderSet, bodies: &mut super::World, rng: &mut impl Rng) -> Planets {
+        let planet_material = MaterialHandle::new(BasicMaterial::new(0.0, 1.0));
+        let ids: BTreeMap<&str, u16> = definitions.iter().enumerate().map(|(index, definition)| (definition.name.as_str(), index as u16 + 1)).collect();
+        let mut positions: BTreeMap<u16, Vector2<f32>> = BTreeMap::new();
+        let mut objects = BTreeMap::new();
+        for definition in definitions {
+            let id = ids[definition.name.as_str()];
+            let position = place(definition, definitions, &ids, &mut positions, rng);
             let body = RigidBodyDesc::new()
-                .translation(Vector2::new(0.0,0.0))
+                .translation(position)
                 .gravity_enabled(false)
-                .status(BodyStatus::Static)
-                .mass(EARTH_MASS * 50.0)
+                .status(if definition.parent.is_some() { BodyStatus::Kinematic } else { BodyStatus::Static })
+                .mass(definition.mass)
                 .build();
-            let position = (body.position().translation.x, body.position().translation.y);
             let mass = body.augmented_mass().linear;
             let body_handle = bodies.add_celestial_object(body);
-            const RADIUS: f32 = EARTH_SIZE * 4.7;
-            let shape = ShapeHandle::new(Ball::new(RADIUS));
-			let id = make_planet_id();
+            let shape = ShapeHandle::new(Ball::new(definition.radius));
             let collider = ColliderDesc::new(shape)
                 .material(planet_material.clone())
-				.user_data(AmPlanet {id})
+                .user_data(AmPlanet {id})
                 .build(BodyPartHandle(body_handle, 0));
             colliders.insert(collider);
-
-            
-            
-            CelestialObject {
-                name: String::from("sun"),
-                display_name: String::from("sun"),
-                radius: RADIUS,
+            objects.insert(id, CelestialObject {
+                name: definition.name.clone(),
+                display_name: definition.display_name.clone(),
+                radius: definition.radius,
                 body: body_handle,
                 id,
-                cargo_upgrade: None,
-                can_beamout: false,
-                position,
+                cargo_upgrade: definition.cargo_upgrade,
+                can_beamout: definition.can_beamout,
+                repairs: definition.repairs,
+                hazards: definition.hazards,
+                safe_zone: definition.safe_zone,
+                atmosphere: definition.atmosphere,
+                position: (position.x, position.y),
                 mass,
-            }
-        };
-
-        /*let trade = {
-            let body = RigidBodyDesc::new()
-                .translation(Vector2::new(earth_pos.x / earth_pos.magnitude() * -2500.0, earth_pos.y / earth_pos.magnitude() * -2500.0))
-                .gravity_enabled(false)
-                .status(BodyStatus::Static)
-                .mass(EARTH_MASS)
-                .build();
-            let position = (body.position().translation.x, body.position().translation.y);
-            let mass = body.augmented_mass().linear;
-            let body_handle = bodies.add_celestial_object(body);
-            const RADIUS: f32 = EARTH_SIZE * 0.75;
-            let shape = ShapeHandle::new(Ball::new(RADIUS));
-			let id = make_planet_id();
-            let collider = ColliderDesc::new(shape)
-                .material(planet_material.clone())
-				.user_data(AmPlanet {id})
-                .build(BodyPartHandle(body_handle, 0));
-            colliders.insert(collider);
-
-            
+                parent: definition.parent.as_ref().map(|parent| ids[parent.as_str()]),
+                orbit: None,
+            });
+        }
+        let spawn = definitions.iter().find(|definition| definition.spawn).map(|definition| ids[definition.name.as_str()]).expect("No spawn planet");
+        let mut planets = Planets { objects, spawn, planet_material, time: 0.0 };
+
+        //Everything starts out circling its parent from wherever it was placed
+        let config = crate::config::config();
+        let orbits: Vec<(u16, Orbit)> = planets.celestial_objects().filter_map(|object| {
+            let parent = planets.parent_of(object)?;
+            let relative_position = Vector2::new(object.position.0 - parent.position.0, object.position.1 - parent.position.1);
+            let mean_motion = Orbit::kepler_mean_motion(relative_position.norm(), parent.mass, object.mass, config.gravitational_constant) * config.orbit_speed;
+            Some((object.id, Orbit::circular(parent.id, relative_position, mean_motion)))
+        }).collect();
+        for (id, orbit) in orbits { planets.objects.get_mut(&id).unwrap().orbit = Some(orbit); }
+        planets
+    }
 
-            CelestialObject {
-                name: String::from("trade"),
-                display_name: String::from("Trade Planet"),
-                radius: RADIUS,
-                body: body_handle,
-                id,
-                cargo_upgrade: None,
-                can_beamout: true,
-                position,
-                mass,
+    /// Moves everything along its orbit by `dt` seconds, keeping the bodies' velocities in step so contacts carry landed ships along
+    pub fn advance(&mut self, dt: f32, bodies: &mut super::World) {
+        self.time += dt as f64;
+        let states: Vec<(u16, Vector2<f32>, Vector2<f32>)> = self.celestial_objects()
+            .filter(|object| object.orbit.is_some())
+            .map(|object| { let (position, velocity) = self.state_of(object); (object.id, position, velocity) })
+            .collect();
+        for (id, position, velocity) in states {
+            let object = self.objects.get_mut(&id).unwrap();
+            object.position = (position.x, position.y);
+            if let Some(body) = bodies.get_rigid_mut(object.body) {
+                body.set_position(nalgebra::Isometry2::new(position, 0.0));
+                body.set_linear_velocity(velocity);
             }
-        };*/
+        }
+    }
 
-        Planets {
-            earth, moon, planet_material, mars, mercury, jupiter, /* pluto, */ saturn, neptune, venus, uranus, sun, /* trade, */
+    /// Absolute position and velocity right now
+    pub fn state_of(&self, object: &CelestialObject) -> (Vector2<f32>, Vector2<f32>) {
+        match (&object.orbit, object.orbit.and_then(|orbit| self.get_celestial_object(orbit.parent).ok())) {
+            (Some(orbit), Some(parent)) => {
+                let (parent_position, parent_velocity) = self.state_of(parent);
+                let (position, velocity) = orbit.state_at(self.time);
+                (parent_position + position, parent_velocity + velocity)
+            },
+            _ => (Vector2::new(object.position.0, object.position.1), Vector2::zeros()),
         }
     }
+    pub fn velocity_of(&self, object: &CelestialObject) -> Vector2<f32> { self.state_of(object).1 }
+
+    /// In id order
+    pub fn celestial_objects(&self) -> impl Iterator<Item = &CelestialObject> { self.objects.values() }
+    /// Where new players and Earth cargo appear
+    pub fn spawn_planet(&self) -> &CelestialObject { &self.objects[&self.spawn] }
+    pub fn parent_of(&self, object: &CelestialObject) -> Option<&CelestialObject> {
+        object.parent.and_then(|parent| self.objects.get(&parent))
+    }
+    pub fn attractors(&self) -> Vec<super::gravity::Attractor> {
+        self.celestial_objects().map(|object| {
+            let position = Vector2::new(object.position.0, object.position.1);
+            let sphere_of_influence = self.parent_of(object).map(|parent| {
+                super::gravity::sphere_of_influence((position - Vector2::new(parent.position.0, parent.position.1)).norm(), object.mass, parent.mass)
+            });
+            super::gravity::Attractor { position, mass: object.mass, radius: object.radius, sphere_of_influence }
+        }).collect()
+    }
+    pub fn airs(&self) -> Vec<Air> {
+        self.celestial_objects().filter_map(|object| {
+            let atmosphere = object.atmosphere?;
+            let (center, velocity) = self.state_of(object);
+            Some(Air { center, radius: object.radius, velocity, atmosphere })
+        }).collect()
+    }
+    /// Whether `at` is inside any object's safe zone
+    pub fn in_safe_zone(&self, at: Vector2<f32>) -> bool {
+        self.celestial_objects().any(|object| object.safe_zone > 0.0 && (at - Vector2::new(object.position.0, object.position.1)).norm() - object.radius < object.safe_zone)
+    }
+    pub fn get_celestial_object(&self, id: u16) -> Result<&CelestialObject, ()> {
+        self.objects.get(&id).ok_or(())
+    }
+    pub fn get_by_name(&self, name: &str) -> Option<&CelestialObject> {
+        self.celestial_objects().find(|object| object.name == name)
+    }
 
-    pub fn celestial_objects<'a>(&'a self) -> [&'a CelestialObject; 10] {
-        [&self.earth, &self.moon, &self.mars, &self.mercury, &self.jupiter, /* &self.pluto, */ &self.saturn, &self.neptune, &self.venus, &self.uranus, &self.sun, /* &self.trade */]
+    pub fn snapshot(&self) -> PlanetsSnapshot {
+        PlanetsSnapshot {
+            time: self.time,
+            orbits: self.celestial_objects().filter_map(|object| {
+                let orbit = object.orbit?;
+                Some(OrbitSnapshot { name: object.name.clone(), parent: self.parent_of(object)?.name.clone(), orbit })
+            }).collect(),
+        }
     }
-    pub fn get_celestial_object<'a>(&'a self, id: u16) -> Result<&'a CelestialObject, ()> {
-        if id == self.earth.id { Ok(&self.earth) }
-        else if id == self.moon.id { Ok(&self.moon) }
-        else if id == self.mars.id { Ok(&self.mars) }
-        else if id == self.mercury.id { Ok(&self.mercury) }
-        else if id == self.jupiter.id { Ok(&self.jupiter) }
-        //else if id == self.pluto.id { Ok(&self.pluto) }
-        else if id == self.saturn.id { Ok(&self.saturn) }
-        else if id == self.neptune.id { Ok(&self.neptune) }
-        else if id == self.venus.id { Ok(&self.venus) }
-        else if id == self.uranus.id { Ok(&self.uranus) }
-        else if id == self.sun.id { Ok(&self.sun) }
-        //else if id == self.trade.id { Ok(&self.trade) }
-        else { Err(()) }
+
+    /// Puts everything back where the snapshot had it. Objects are matched up by name,
+    /// anything that's new or now orbits something else keeps its fresh orbit.
+    pub fn restore(&mut self, snapshot: &PlanetsSnapshot, bodies: &mut super::World) {
+        for saved in &snapshot.orbits {
+            let parent = if let Some(parent) = self.get_by_name(&saved.parent) { parent.id } else { continue };
+            let id = if let Some(object) = self.get_by_name(&saved.name) { object.id } else { continue };
+            let object = self.objects.get_mut(&id).unwrap();
+            if object.parent == Some(parent) { object.orbit = Some(Orbit { parent, ..saved.orbit }); }
+        }
+        self.time = snapshot.time;
+        self.advance(0.0, bodies);
     }
 }
 
-static mut NEXT_PLANET_ID: u16 = 1;
-fn make_planet_id() -> u16 {
-    unsafe { let id = NEXT_PLANET_ID; NEXT_PLANET_ID += 1; id }
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlanetsSnapshot {
+    pub time: f64,
+    pub orbits: Vec<OrbitSnapshot>,
+}
+/// Ids depend on the planets file, so objects are saved by name
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrbitSnapshot {
+    pub name: String,
+    pub parent: String,
+    pub orbit: Orbit,
+}
+
+/// Where an object starts: a random point on its orbit around wherever its parent starts
+fn place(definition: &CelestialObjectDef, definitions: &[CelestialObjectDef], ids: &BTreeMap<&str, u16>, positions: &mut BTreeMap<u16, Vector2<f32>>, rng: &mut impl Rng) -> Vector2<f32> {
+    let id = ids[definition.name.as_str()];
+    if let Some(position) = positions.get(&id) { return *position };
+    let position = match &definition.parent {
+        Some(parent) => {
+            let parent = definitions.iter().find(|other| &other.name == parent).unwrap();
+            place(parent, definitions, ids, positions, rng) + planet_location(definition.orbit_radius, rng)
+        },
+        None => Vector2::new(definition.position.0, definition.position.1),
+    };
+    positions.insert(id, position);
+    position
 }
 
 pub struct CelestialObject {
@@ -474,12 +309,18 @@ pub struct CelestialObject {
     pub id: u16,
     pub cargo_upgrade: Option<super::parts::PartKind>,
     pub can_beamout: bool,
+    pub repairs: bool,
+    pub hazards: Hazards,
+    pub safe_zone: f32,
+    pub atmosphere: Option<Atmosphere>,
     pub position: (f32, f32),
     pub mass: f32,
+    /// Id of what it orbits
+    pub parent: Option<u16>,
+    pub orbit: Option<super::orbit::Orbit>,
 }
 
-pub fn planet_location(radius: f32) -> nalgebra::Matrix<f32, nalgebra::U2, nalgebra::U1, nalgebra::ArrayStorage<f32, nalgebra::U2, nalgebra::U1>> {
-    let mut rng = rand::thread_rng();
+pub fn planet_location(radius: f32, rng: &mut impl Rng) -> nalgebra::Matrix<f32, nalgebra::U2, nalgebra::U1, nalgebra::ArrayStorage<f32, nalgebra::U2, nalgebra::U1>> {
     let angle: f32 = rng.gen::<f32>() * std::f32::consts::PI * 2.0;
     let pos = Vector2::new(f32::cos(angle) * radius, f32::sin(angle) * radius);
     pos
@@ -493,5 +334,76 @@ pub struct AmPlanet {
 /*impl UserData for AmPlanet {
     fn clone_boxed(&self) -> Box<dyn UserData> { Box::new(*self) }
     fn to_any(&self) -> Box<dyn std::any::Any + Send + Sync> { Box::new(*self) }
-    fn as_any(&self) -> &dyn std::any::Any { self }    
+    fn as_any(&self) -> &dyn std::any::Any { self }
 }*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Vec<CelestialObjectDef> { serde_json::from_str(json).unwrap() }
+
+    #[test]
+    fn built_in_planets_are_valid() {
+        let definitions = load_definitions(None).unwrap();
+        assert_eq!(definitions.len(), 11);
+        assert_eq!(definitions[0].name, "earth");
+        assert!(definitions[0].spawn);
+        assert_eq!(definitions[1].cargo_upgrade, Some(PartKind::LandingThruster));
+        assert!(definitions.iter().find(|definition| definition.name == "sun").unwrap().hazards.incinerates);
+        assert_eq!(definitions[0].atmosphere.unwrap().falloff, 2.0);
+        assert!(definitions[0].repairs);
+        assert!(definitions[0].safe_zone > 0.0);
+        //Added after the rest, so every older planet keeps its id
+        assert_eq!(definitions.iter().map(|definition| definition.name.as_str()).collect::<Vec<_>>(),
+            vec!["earth", "moon", "mars", "mercury", "jupiter", "saturn", "neptune", "venus", "uranus", "sun", "phobos"]);
+        assert_eq!(definitions.last().unwrap().cargo_upgrade, Some(PartKind::Cannon));
+        assert_eq!(definitions.iter().find(|definition| definition.name == "mars").unwrap().atmosphere.unwrap().falloff, 1.0);
+    }
+
+    #[test]
+    fn same_seed_same_solar_system() {
+        use rand::SeedableRng;
+        let definitions = load_definitions(None).unwrap();
+        let positions = |seed: u64| {
+            let planets = Planets::new(&definitions, &mut super::super::MyColliderSet::new(), &mut super::super::World::default(), &mut rand::rngs::StdRng::seed_from_u64(seed));
+            planets.celestial_objects().map(|object| object.position).collect::<Vec<_>>()
+        };
+        assert_eq!(positions(7), positions(7));
+        assert_ne!(positions(7), positions(8));
+    }
+
+    #[test]
+    fn restored_planets_pick_up_where_they_left_off() {
+        use rand::SeedableRng;
+        let definitions = load_definitions(None).unwrap();
+        let mut world = super::super::World::default();
+        let mut planets = Planets::new(&definitions, &mut super::super::MyColliderSet::new(), &mut world, &mut rand::rngs::StdRng::seed_from_u64(1));
+        planets.advance(120.0, &mut world);
+        let snapshot: PlanetsSnapshot = serde_json::from_str(&serde_json::to_string(&planets.snapshot()).unwrap()).unwrap();
+
+        let mut other_world = super::super::World::default();
+        let mut restored = Planets::new(&definitions, &mut super::super::MyColliderSet::new(), &mut other_world, &mut rand::rngs::StdRng::seed_from_u64(2));
+        restored.restore(&snapshot, &mut other_world);
+        assert_eq!(restored.time, planets.time);
+        for (object, restored_object) in planets.celestial_objects().zip(restored.celestial_objects()) {
+            assert!((object.position.0 - restored_object.position.0).abs() < 0.01 && (object.position.1 - restored_object.position.1).abs() < 0.01, "{} moved", object.name);
+        }
+    }
+
+    #[test]
+    fn broken_solar_systems_are_refused() {
+        let no_spawn = parse(r#"[{ "name": "sun", "display_name": "Sun", "mass": 1, "radius": 1 }]"#);
+        assert!(validate_definitions(&no_spawn).is_err());
+        let missing_parent = parse(r#"[{ "name": "earth", "display_name": "Earth", "parent": "sun", "orbit_radius": 10, "mass": 1, "radius": 1, "spawn": true }]"#);
+        assert!(validate_definitions(&missing_parent).unwrap_err().contains("doesn't exist"));
+        let loop_ = parse(r#"[
+            { "name": "a", "display_name": "A", "parent": "b", "orbit_radius": 10, "mass": 1, "radius": 1, "spawn": true },
+            { "name": "b", "display_name": "B", "parent": "a", "orbit_radius": 10, "mass": 1, "radius": 1 }
+        ]"#);
+        assert!(validate_definitions(&loop_).unwrap_err().contains("loop"));
+        assert!(serde_json::from_str::<Vec<CelestialObjectDef>>(r#"[{ "name": "a", "display_name": "A", "mass": 1, "radius": 1, "cargo_upgrade": "Warp" }]"#).is_err());
+        let airless_atmosphere = parse(r#"[{ "name": "a", "display_name": "A", "mass": 1, "radius": 1, "spawn": true, "atmosphere": { "thickness": 0, "density": 1 } }]"#);
+        assert!(validate_definitions(&airless_atmosphere).unwrap_err().contains("thickness"));
+    }
+}
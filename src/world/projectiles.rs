@@ -0,0 +1,59 @@
+//! Shots from weapon parts. They're small, quick bodies that fly in a straight line, since nothing pulls on them,
+//! until they touch something or run out of range. Clients only hear where one starts off and when it's gone.
+
+use nalgebra::Vector2;
+use nphysics2d::object::{RigidBodyDesc, ColliderDesc, BodyPartHandle, BodyStatus, DefaultColliderHandle};
+use nphysics2d::algebra::Velocity2;
+use ncollide2d::shape::{Ball, ShapeHandle};
+use super::nphysics_types::*;
+use super::{WorldAddHandle, WorldlyObject};
+use super::parts::WeaponDetails;
+use crate::codec::ToClientMsg;
+
+lazy_static! {
+    static ref PROJECTILE_BALL: ShapeHandle<MyUnits> = ShapeHandle::new(Ball::new(0.15));
+}
+/// Light enough that a hit barely nudges what it hits; the damage is what hurts
+const PROJECTILE_MASS: f32 = 0.05;
+
+pub struct Projectile {
+    id: u16,
+    body: MyRigidBody,
+    collider: DefaultColliderHandle,
+    /// Player who fired it
+    pub shooter: u16,
+    /// Hit points it takes off whatever it hits. Shots that can't hurt anyone, like ones fired from a safe zone, have 0
+    pub damage: u16,
+    pub ticks_left: u16,
+}
+
+impl Projectile {
+    #[allow(clippy::too_many_arguments)]
+    pub fn launch(bodies: &mut WorldAddHandle, colliders: &mut MyColliderSet, id: u16, shooter: u16, weapon: &WeaponDetails, harmless: bool, position: Vector2<f32>, velocity: Vector2<f32>, timestep: f32) -> MyHandle {
+        let body = RigidBodyDesc::new()
+            .status(BodyStatus::Dynamic)
+            .mass(PROJECTILE_MASS)
+            .translation(position)
+            .velocity(Velocity2::linear(velocity.x, velocity.y))
+            .build();
+        let body_handle = bodies.add_later();
+        //Fast enough to pass clean through a part between two steps otherwise
+        let collider = colliders.insert(ColliderDesc::new(PROJECTILE_BALL.clone()).ccd_enabled(true).build(BodyPartHandle(body_handle, 0)));
+        let ticks_left = ((weapon.range / weapon.speed / timestep).ceil() as u16).max(1);
+        let damage = if harmless { 0 } else { weapon.damage };
+        bodies.add_its_later(body_handle, WorldlyObject::Projectile(Projectile { id, body, collider, shooter, damage, ticks_left }));
+        body_handle
+    }
+
+    pub fn id(&self) -> u16 { self.id }
+    pub fn body(&self) -> &MyRigidBody { &self.body }
+    pub fn body_mut(&mut self) -> &mut MyRigidBody { &mut self.body }
+    pub fn collider(&self) -> DefaultColliderHandle { self.collider }
+
+    pub fn add_msg(&self) -> ToClientMsg {
+        let position = self.body.position().translation;
+        let velocity = self.body.velocity().linear;
+        ToClientMsg::AddProjectile { id: self.id, x: position.x, y: position.y, vel_x: velocity.x, vel_y: velocity.y }
+    }
+    pub fn remove_msg(&self) -> ToClientMsg { ToClientMsg::RemoveProjectile { id: self.id } }
+}
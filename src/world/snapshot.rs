@@ -0,0 +1,92 @@
+use nalgebra::{Vector2, Isometry2};
+use nphysics2d::algebra::Velocity2;
+use super::parts::{PartKind, RecursivePartDescription, CompactThrustMode};
+use super::{Simulation, World, MyHandle};
+
+/// A part and everything attached to it, exactly where and how fast it was going.
+/// Part ids aren't kept, restored parts get fresh ones.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PartSnapshot {
+    pub kind: PartKind,
+    pub position: (f32, f32),
+    pub rotation: f32,
+    pub velocity: (f32, f32),
+    pub angular_velocity: f32,
+    pub thrust_mode: u8,
+    /// Snapshots from before parts had health come back in one piece
+    #[serde(default)]
+    pub health: Option<u16>,
+    pub attachments: Vec<Option<PartSnapshot>>,
+}
+
+impl PartSnapshot {
+    fn layout(&self) -> RecursivePartDescription {
+        RecursivePartDescription {
+            kind: self.kind,
+            attachments: self.attachments.iter().map(|attachment| attachment.as_ref().map(PartSnapshot::layout)).collect(),
+        }
+    }
+}
+
+impl Simulation {
+    pub fn snapshot_part(&self, part: MyHandle) -> PartSnapshot {
+        snapshot_part(&self.world, part)
+    }
+
+    /// Builds the parts back up and puts each one exactly where it was, joints and all
+    pub fn restore_part(&mut self, snapshot: &PartSnapshot) -> MyHandle {
+        let handle = self.inflate(&snapshot.layout(), Isometry2::new(Vector2::new(snapshot.position.0, snapshot.position.1), snapshot.rotation));
+        restore_part(&mut self.world, handle, snapshot);
+        handle
+    }
+}
+
+fn snapshot_part(world: &World, handle: MyHandle) -> PartSnapshot {
+    let part = world.get_part(handle).unwrap();
+    let position = part.body().position();
+    let velocity = part.body().velocity();
+    PartSnapshot {
+        kind: part.kind(),
+        position: (position.translation.x, position.translation.y),
+        rotation: position.rotation.angle(),
+        velocity: (velocity.linear.x, velocity.linear.y),
+        angular_velocity: velocity.angular,
+        thrust_mode: part.thrust_mode.into(),
+        health: Some(part.health()),
+        attachments: part.attachments().iter().map(|attachment| attachment.as_ref().map(|attachment| snapshot_part(world, **attachment))).collect(),
+    }
+}
+
+fn restore_part(world: &mut World, handle: MyHandle, snapshot: &PartSnapshot) {
+    let part = world.get_part_mut(handle).unwrap();
+    part.body_mut().set_position(Isometry2::new(Vector2::new(snapshot.position.0, snapshot.position.1), snapshot.rotation));
+    part.body_mut().set_velocity(Velocity2::new(Vector2::new(snapshot.velocity.0, snapshot.velocity.1), snapshot.angular_velocity));
+    part.thrust_mode = CompactThrustMode::from(snapshot.thrust_mode);
+    if let Some(health) = snapshot.health { part.set_health(health); }
+    let children: Vec<(MyHandle, &PartSnapshot)> = part.attachments().iter().zip(snapshot.attachments.iter())
+        .filter_map(|(attachment, snapshot)| Some((**attachment.as_ref()?, snapshot.as_ref()?)))
+        .collect();
+    for (child, snapshot) in children { restore_part(world, child, snapshot); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::planets::load_definitions;
+
+    #[test]
+    fn parts_come_back_where_they_were() {
+        let mut simulation = Simulation::new(0.05, &load_definitions(None).unwrap(), 7);
+        let layout = RecursivePartDescription { kind: PartKind::Core, attachments: vec![Some(PartKind::Hub.into()), None, Some(PartKind::Thruster.into())] };
+        let original = simulation.inflate(&layout, Isometry2::new(Vector2::new(3000.0, -40.0), 0.5));
+        simulation.world.get_rigid_mut(original).unwrap().set_velocity(Velocity2::new(Vector2::new(1.5, -2.0), 0.25));
+        simulation.world.get_part_mut(original).unwrap().set_health(37);
+        let snapshot = simulation.snapshot_part(original);
+
+        let restored = simulation.restore_part(&serde_json::from_str(&serde_json::to_string(&snapshot).unwrap()).unwrap());
+        let again = simulation.snapshot_part(restored);
+        assert_eq!(serde_json::to_value(&again).unwrap(), serde_json::to_value(&snapshot).unwrap());
+        assert_eq!(again.attachments.iter().filter(|attachment| attachment.is_some()).count(), 2);
+        assert_eq!(simulation.world.get_part(restored).unwrap().health(), 37);
+    }
+}